@@ -1,3 +1,10 @@
+// kept as originally written
+#![allow(
+    deprecated,
+    clippy::needless_borrows_for_generic_args,
+    clippy::print_with_newline
+)]
+
 extern crate molysite;
 
 use std::env;
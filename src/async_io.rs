@@ -0,0 +1,202 @@
+//! Parsing from tokio readers, enabled by the `async` feature.
+//!
+//! Input is read asynchronously and then parsed in one go, as the parsers
+//! need the whole of it. Parsing is CPU-bound, so it runs on tokio's
+//! blocking threads rather than holding up the async workers.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::task::{self, JoinSet};
+
+use crate::common::check_text;
+use crate::json::parse_json_bytes;
+use crate::parser::Parser;
+use crate::types::{JsonValue, ParseError, ReadError};
+
+/// Reads `reader` to the end and parses it as HCL.
+pub async fn parse_hcl_async<R: AsyncRead + Unpin>(reader: R) -> Result<JsonValue, ReadError> {
+    Parser::new().parse_async(reader).await
+}
+
+/// Reads `reader` to the end and parses it as JSON.
+pub async fn parse_json_async<R: AsyncRead + Unpin>(reader: R) -> Result<JsonValue, ReadError> {
+    let config = read_limited(reader, None).await?;
+    parse_blocking(move || parse_json_bytes(&config)).await
+}
+
+impl Parser {
+    /// Like `parse_reader`, for tokio readers.
+    pub async fn parse_async<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+    ) -> Result<JsonValue, ReadError> {
+        let config = read_limited(reader, self.options.max_input_size).await?;
+        let parser = self.clone();
+        parse_blocking(move || parser.parse_bytes(&config)).await
+    }
+
+    // like `parse_async`, for JSON
+    async fn parse_json_async<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+    ) -> Result<JsonValue, ReadError> {
+        let config = read_limited(reader, self.options.max_input_size).await?;
+        let parser = self.clone();
+        parse_blocking(move || parser.parse_json(check_text(&config)?)).await
+    }
+
+    // like `parse_async`, for HCL2
+    async fn parse_hcl2_async<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+    ) -> Result<JsonValue, ReadError> {
+        let config = read_limited(reader, self.options.max_input_size).await?;
+        let parser = self.clone();
+        parse_blocking(move || parser.parse_hcl2(check_text(&config)?)).await
+    }
+}
+
+async fn parse_blocking<F>(parse: F) -> Result<JsonValue, ReadError>
+where
+    F: FnOnce() -> Result<JsonValue, ParseError> + Send + 'static,
+{
+    match task::spawn_blocking(parse).await {
+        Ok(result) => Ok(result?),
+        Err(e) => Err(ReadError::Io(io::Error::other(e))),
+    }
+}
+
+async fn read_limited<R: AsyncRead + Unpin>(reader: R, max: Option<usize>) -> io::Result<Vec<u8>> {
+    let mut config = Vec::new();
+    match max {
+        Some(max) => reader.take(max as u64 + 1).read_to_end(&mut config).await?,
+        None => {
+            let mut reader = reader;
+            reader.read_to_end(&mut config).await?
+        }
+    };
+    Ok(config)
+}
+
+/// Loads every `.hcl`, `.tf` and `.json` file directly inside `dir`, or
+/// linked to from it, concurrently. `.hcl` files are parsed with `parser`,
+/// `.tf` files with `parser.parse_hcl2` and `.json` files with
+/// `parser.parse_json`.
+///
+/// Results are per file and sorted by path, so one bad file does not hide
+/// the others; only failing to list `dir` fails the whole load. Must be
+/// called from within a tokio runtime.
+pub async fn parse_dir_async<P: AsRef<Path>>(
+    dir: P,
+    parser: &Parser,
+) -> io::Result<Vec<(PathBuf, Result<JsonValue, ReadError>)>> {
+    let mut entries = fs::read_dir(dir).await?;
+    let mut tasks = JoinSet::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let extension = match path.extension().and_then(|e| e.to_str()) {
+            Some(e @ "hcl") | Some(e @ "tf") | Some(e @ "json") => e.to_string(),
+            _ => continue,
+        };
+        // follows symlinks; a broken one is reported when it fails to open
+        if let Ok(metadata) = fs::metadata(&path).await {
+            if !metadata.is_file() {
+                continue;
+            }
+        }
+        let parser = parser.clone();
+        tasks.spawn(async move {
+            let result = match fs::File::open(&path).await {
+                Ok(file) if extension == "json" => parser.parse_json_async(file).await,
+                Ok(file) if extension == "tf" => parser.parse_hcl2_async(file).await,
+                Ok(file) => parser.parse_async(file).await,
+                Err(e) => Err(ReadError::Io(e)),
+            };
+            (path, result)
+        });
+    }
+    let mut results = Vec::new();
+    while let Some(done) = tasks.join_next().await {
+        results.push(done.map_err(io::Error::other)?);
+    }
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::TempDir;
+    use crate::types::{Limit, ParseErrorKind};
+
+    #[tokio::test]
+    async fn async_parse_file() {
+        let dir = TempDir::new("async-file");
+        let path = dir.join("main.hcl");
+        std::fs::write(&path, "foo = \"bar\"\n").unwrap();
+        let parsed = parse_hcl_async(fs::File::open(&path).await.unwrap()).await;
+        assert_eq!(crate::hcl::parse_hcl("foo = \"bar\"").ok(), parsed.ok());
+        match parse_json_async(&b"{\"a\": }"[..]).await {
+            Err(ReadError::Parse(_)) => (),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn async_parse_dir() {
+        let dir = TempDir::new("async-dir");
+        std::fs::write(dir.join("a.hcl"), "a = 1\n").unwrap();
+        std::fs::write(dir.join("b.tf"), "b = [\n").unwrap();
+        std::fs::write(dir.join("c.json"), "{\"c\": true}").unwrap();
+        std::fs::write(dir.join("d.tf"), "d = var.x\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not config").unwrap();
+        std::fs::create_dir(dir.join("nested.hcl")).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("a.hcl"), dir.join("e.hcl")).unwrap();
+
+        let results = parse_dir_async(dir.path(), &Parser::new()).await.unwrap();
+        let names: Vec<_> = results
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        let mut expected = vec!["a.hcl", "b.tf", "c.json", "d.tf"];
+        if cfg!(unix) {
+            expected.push("e.hcl");
+        }
+        assert_eq!(expected, names);
+        assert_eq!(
+            crate::hcl::parse_hcl("a = 1").ok(),
+            results[0].1.as_ref().ok().cloned()
+        );
+        assert!(matches!(results[1].1, Err(ReadError::Parse(_))));
+        assert_eq!(
+            crate::json::parse_json("{\"c\": true}").ok(),
+            results[2].1.as_ref().ok().cloned()
+        );
+        assert_eq!(
+            crate::hcl2::parse_hcl2("d = var.x").ok(),
+            results[3].1.as_ref().ok().cloned()
+        );
+        if cfg!(unix) {
+            assert_eq!(results[0].1.as_ref().ok(), results[4].1.as_ref().ok());
+        }
+
+        let limited = parse_dir_async(dir.path(), &Parser::new().max_input_size(8))
+            .await
+            .unwrap();
+        assert!(matches!(
+            limited[2].1,
+            Err(ReadError::Parse(ParseError {
+                kind: ParseErrorKind::LimitExceeded(Limit::InputSize),
+                ..
+            }))
+        ));
+
+        assert!(parse_dir_async(dir.join("missing"), &Parser::new())
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! Comments attached to the attributes and blocks they document.
+//!
+//! A comment group directly above an item, with no blank line in between,
+//! leads it; a comment after an item on its last line trails it. Comments
+//! are found with the lexer, so they can be looked up by the path the item
+//! has in the parsed tree, without the lists HCL1 wraps blocks in.
+
+use std::collections::HashMap;
+
+use nom::IResult::Done;
+
+use crate::hcl::quoted_key;
+use crate::lexer::{tokenize, Token, TokenKind};
+#[cfg(test)]
+use crate::types::JsonValue;
+use crate::types::ParseError;
+
+/// The comments documenting one attribute or block, without their `#`,
+/// `//` or `/* */` markers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Attached {
+    pub leading: Vec<String>,
+    pub trailing: Option<String>,
+}
+
+/// Attached comments by item path, such as `["variable", "amis", "default"]`
+/// for the `default` attribute of `variable "amis" {}`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Comments {
+    items: HashMap<Vec<String>, Attached>,
+}
+
+impl Comments {
+    pub fn get(&self, path: &[&str]) -> Option<&Attached> {
+        let path: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+        self.items.get(&path)
+    }
+
+    /// Every documented item, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[String], &Attached)> {
+        self.items.iter().map(|(path, c)| (&path[..], c))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+/// Finds the comments of the attributes and blocks in `source`.
+pub fn extract_comments(source: &str) -> Result<Comments, ParseError> {
+    let tokens = tokenize(source)?;
+    let mut walker = Walker {
+        tokens: &tokens,
+        pos: 0,
+        comments: Comments::default(),
+    };
+    walker.body(&[], false);
+    Ok(walker.comments)
+}
+
+/// A comment's text without its markers.
+pub fn comment_text(comment: &str) -> String {
+    let text = if let Some(inner) = comment.strip_prefix("/*") {
+        inner.strip_suffix("*/").unwrap_or(inner)
+    } else if let Some(line) = comment.strip_prefix("//") {
+        line
+    } else {
+        comment.strip_prefix('#').unwrap_or(comment)
+    };
+    text.trim().to_string()
+}
+
+struct Walker<'t, 'a> {
+    tokens: &'t [Token<'a>],
+    pos: usize,
+    comments: Comments,
+}
+
+impl<'t, 'a> Walker<'t, 'a> {
+    fn peek(&self) -> Option<&'t Token<'a>> {
+        self.tokens.get(self.pos)
+    }
+
+    fn is(&self, kind: TokenKind, text: &str) -> bool {
+        self.peek()
+            .is_some_and(|t| t.kind == kind && t.text == text)
+    }
+
+    // walks the items of a body, up to its closing brace when `braced`
+    fn body(&mut self, path: &[String], braced: bool) {
+        let mut pending: Vec<String> = Vec::new();
+        let mut newlines = 0;
+        while let Some(token) = self.peek() {
+            match token.kind {
+                TokenKind::Newline => {
+                    newlines += 1;
+                    if newlines > 1 {
+                        pending.clear();
+                    }
+                    self.pos += 1;
+                }
+                TokenKind::Comment => {
+                    pending.push(comment_text(token.text));
+                    newlines = 0;
+                    self.pos += 1;
+                }
+                TokenKind::Punctuation if token.text == "}" => {
+                    self.pos += 1;
+                    if braced {
+                        return;
+                    }
+                }
+                TokenKind::Identifier | TokenKind::StringStart => {
+                    let leading = std::mem::take(&mut pending);
+                    self.item(path, leading);
+                    newlines = 0;
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    fn item(&mut self, path: &[String], leading: Vec<String>) {
+        let mut item_path = path.to_vec();
+        let key = match self.key() {
+            Some(key) => key,
+            None => return,
+        };
+        item_path.push(key);
+        if self.is(TokenKind::Punctuation, "=") {
+            self.pos += 1;
+            if self.is(TokenKind::Punctuation, "{") {
+                // hcl1 treats an assigned object like a block
+                self.pos += 1;
+                self.body(&item_path, true);
+            } else {
+                self.value();
+            }
+        } else {
+            while !self.is(TokenKind::Punctuation, "{") {
+                match self.key() {
+                    Some(label) => item_path.push(label),
+                    None => return,
+                }
+            }
+            self.pos += 1;
+            self.body(&item_path, true);
+        }
+        let trailing = match self.peek() {
+            Some(t) if t.kind == TokenKind::Comment => {
+                self.pos += 1;
+                Some(comment_text(t.text))
+            }
+            _ => None,
+        };
+        if leading.is_empty() && trailing.is_none() {
+            return;
+        }
+        let attached = self.comments.items.entry(item_path).or_default();
+        attached.leading.extend(leading);
+        if trailing.is_some() {
+            attached.trailing = trailing;
+        }
+    }
+
+    // an identifier, possibly dotted, or a quoted string
+    fn key(&mut self) -> Option<String> {
+        let first = self.peek()?;
+        match first.kind {
+            TokenKind::Identifier => {
+                let mut key = first.text.to_string();
+                let mut end = first.span.end;
+                self.pos += 1;
+                while let Some(t) = self.peek() {
+                    let joins = t.kind == TokenKind::Identifier
+                        || t.kind == TokenKind::Number
+                        || (t.kind == TokenKind::Punctuation && t.text == ".");
+                    if t.span.start != end || !joins {
+                        break;
+                    }
+                    key.push_str(t.text);
+                    end = t.span.end;
+                    self.pos += 1;
+                }
+                Some(key)
+            }
+            TokenKind::StringStart => {
+                self.pos += 1;
+                let mut key = String::new();
+                while let Some(t) = self.peek() {
+                    self.pos += 1;
+                    if t.kind == TokenKind::StringEnd {
+                        // unescaped as the parser does, to match its paths
+                        let quoted = format!("\"{}\"", key);
+                        return Some(match quoted_key(quoted.as_bytes()) {
+                            Done(&[], unescaped) => unescaped,
+                            _ => key,
+                        });
+                    }
+                    key.push_str(t.text);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // skips a value, to the end of its last line
+    fn value(&mut self) {
+        let mut depth = 0usize;
+        while let Some(t) = self.peek() {
+            let opens = match t.kind {
+                TokenKind::StringStart
+                | TokenKind::InterpolationStart
+                | TokenKind::HeredocStart => true,
+                TokenKind::Punctuation => matches!(t.text, "{" | "[" | "("),
+                _ => false,
+            };
+            let closes = match t.kind {
+                TokenKind::StringEnd | TokenKind::InterpolationEnd | TokenKind::HeredocEnd => true,
+                TokenKind::Punctuation => matches!(t.text, "}" | "]" | ")"),
+                _ => false,
+            };
+            if depth == 0 {
+                let ends = t.kind == TokenKind::Newline
+                    || t.kind == TokenKind::Comment
+                    || (t.kind == TokenKind::Punctuation && t.text == ",")
+                    || closes;
+                if ends {
+                    return;
+                }
+            }
+            if opens {
+                depth += 1;
+            } else if closes {
+                depth -= 1;
+            }
+            self.pos += 1;
+        }
+    }
+}
+
+#[test]
+fn comments_leading_and_trailing() {
+    let test = "# The AMI to use.
+// Per region.
+variable \"amis\" {
+  /* the default */
+  default = {
+    # east
+    us-east-1 = \"ami-1\" # trailing
+  }
+} // end
+
+# detached
+
+count = 2 # how many
+list = [
+  1,
+  2,
+] # items
+";
+    let comments = extract_comments(test).unwrap();
+    let get = |path: &[&str]| comments.get(path).cloned().unwrap_or_default();
+    assert_eq!(
+        vec!["The AMI to use.", "Per region."],
+        get(&["variable", "amis"]).leading
+    );
+    assert_eq!(Some("end".to_string()), get(&["variable", "amis"]).trailing);
+    assert_eq!(
+        vec!["the default"],
+        get(&["variable", "amis", "default"]).leading
+    );
+    assert_eq!(
+        Attached {
+            leading: vec!["east".to_string()],
+            trailing: Some("trailing".to_string()),
+        },
+        get(&["variable", "amis", "default", "us-east-1"])
+    );
+    assert_eq!(
+        Attached {
+            leading: vec![],
+            trailing: Some("how many".to_string()),
+        },
+        get(&["count"])
+    );
+    assert_eq!(Some("items".to_string()), get(&["list"]).trailing);
+    assert_eq!(5, comments.iter().count());
+}
+
+#[test]
+fn comments_heredocs_and_strings() {
+    let test = "# script
+run = <<EOT
+# not a comment
+EOT
+# quoted key
+\"a b\" = \"# not a comment\" /* c */
+# escaped
+\"a\\\"b\" = 1
+";
+    let comments = extract_comments(test).unwrap();
+    assert_eq!(
+        Some(&Attached {
+            leading: vec!["script".to_string()],
+            trailing: None,
+        }),
+        comments.get(&["run"])
+    );
+    assert_eq!(
+        Some(&Attached {
+            leading: vec!["quoted key".to_string()],
+            trailing: Some("c".to_string()),
+        }),
+        comments.get(&["a b"])
+    );
+    let (value, comments) = crate::parser::Parser::new()
+        .parse_with_comments(test)
+        .unwrap();
+    assert!(matches!(value, JsonValue::Object(ref obj) if obj.contains_key("a\"b")));
+    assert_eq!(
+        vec!["escaped".to_string()],
+        comments.get(&["a\"b"]).unwrap().leading
+    );
+    assert_eq!(3, comments.iter().count());
+}
@@ -1,8 +1,188 @@
-use std::num::ParseIntError;
+use std::cell::RefCell;
 use std::str::{self, FromStr};
-use std::u32;
 
-use nom::{digit, hex_digit};
+use nom::IResult::{Done, Error};
+use nom::{digit, eol, ErrorKind, IResult};
+
+use crate::parser::ParserOptions;
+use crate::types::{Limit, ParseError, ParseErrorKind};
+
+struct State {
+    base: usize,
+    options: ParserOptions,
+    failure: Option<(usize, ParseErrorKind)>,
+    warnings: Vec<(usize, ParseErrorKind)>,
+    depth: usize,
+}
+
+thread_local! {
+    // the parse in progress; positions are kept as addresses until it ends
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> Option<R> {
+    STATE.with(|s| s.borrow_mut().as_mut().map(f))
+}
+
+/// The address of the current input, resolved to an offset once the parse ends.
+pub fn here(input: &[u8]) -> IResult<&[u8], usize> {
+    Done(input, input.as_ptr() as usize)
+}
+
+/// The options of the parse in progress.
+pub fn options<R>(f: impl FnOnce(&ParserOptions) -> R) -> R {
+    STATE.with(|s| match *s.borrow() {
+        Some(ref state) => f(&state.options),
+        None => f(&ParserOptions::default()),
+    })
+}
+
+/// Turns an address taken during the parse in progress into a byte offset.
+pub fn offset(addr: usize) -> usize {
+    with_state(|s| addr - s.base).unwrap_or(0)
+}
+
+/// Records a failure that backtracking cannot recover from. Alternatives will
+/// still be tried, but the parse as a whole reports the first such failure
+/// rather than wherever the top level parser gave up.
+pub fn fail_at(addr: usize, kind: ParseErrorKind) {
+    with_state(|s| {
+        if s.failure.is_none() {
+            s.failure = Some((addr, kind));
+        }
+    });
+}
+
+/// Fails the parse at `input`, see `fail_at`.
+pub fn fail<T>(input: &[u8], kind: ParseErrorKind) -> IResult<&[u8], T> {
+    fail_at(input.as_ptr() as usize, kind);
+    Error(error_position!(ErrorKind::Custom(1), input))
+}
+
+/// Runs `parser` one level of nesting deeper, failing past the depth limit.
+/// Once a limit has been exceeded nothing nested is parsed again, so
+/// alternatives that backtrack over hostile input cannot multiply the work.
+pub fn nested<'a, T>(
+    input: &'a [u8],
+    parser: fn(&'a [u8]) -> IResult<&'a [u8], T>,
+) -> IResult<&'a [u8], T> {
+    let (depth, exceeded) = with_state(|s| {
+        s.depth += 1;
+        let exceeded = matches!(s.failure, Some((_, ParseErrorKind::LimitExceeded(_))));
+        (s.depth, exceeded)
+    })
+    .unwrap_or((0, false));
+    let result = if exceeded {
+        Error(error_position!(ErrorKind::Custom(1), input))
+    } else if options(|o| o.max_depth.is_some_and(|max| depth > max)) {
+        fail(input, ParseErrorKind::LimitExceeded(Limit::Depth))
+    } else {
+        parser(input)
+    };
+    with_state(|s| s.depth -= 1);
+    result
+}
+
+/// Whether `len` is past the configured limit on string or array lengths.
+pub fn exceeds(limit: Limit, len: usize) -> bool {
+    let max = options(|o| match limit {
+        Limit::StringLength => o.max_string_length,
+        Limit::ArrayLength => o.max_array_length,
+        _ => None,
+    });
+    max.is_some_and(|max| len > max)
+}
+
+/// Records a problem that does not stop the parse.
+pub fn warn_at(addr: usize, kind: ParseErrorKind) {
+    with_state(|s| {
+        // bodies parsed again after backtracking report the same problems
+        if !s.warnings.iter().any(|w| w.0 == addr && w.1 == kind) {
+            s.warnings.push((addr, kind));
+        }
+    });
+}
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+const GIT_CRYPT: &[u8] = b"\0GITCRYPT";
+
+/// Checks raw input against the configured size limit.
+pub fn check_size(input: &[u8], options: &ParserOptions) -> Result<(), ParseError> {
+    match options.max_input_size {
+        Some(max) if input.len() > max => Err(ParseError::new(
+            ParseErrorKind::LimitExceeded(Limit::InputSize),
+            max,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that raw input is text, not an encrypted or binary file.
+pub fn check_text(input: &[u8]) -> Result<&str, ParseError> {
+    if input.starts_with(GIT_CRYPT) {
+        return Err(ParseError::new(ParseErrorKind::Encrypted, 0));
+    }
+    if let Some(n) = input.iter().position(|&c| c == 0) {
+        return Err(ParseError::new(ParseErrorKind::Binary, n));
+    }
+    str::from_utf8(input).map_err(|e| ParseError::new(ParseErrorKind::InvalidUtf8, e.valid_up_to()))
+}
+
+/// Runs a top level parser with default options, see `run_with`.
+pub fn run<'a, T, F>(source: &'a [u8], parser: F) -> Result<T, ParseError>
+where
+    F: Fn(&'a [u8]) -> IResult<&'a [u8], T>,
+{
+    run_with(source, &ParserOptions::default(), parser).map(|(value, _)| value)
+}
+
+/// Runs a top level parser, which must consume all of `source`, returning
+/// its value along with any warnings.
+pub fn run_with<'a, T, F>(
+    source: &'a [u8],
+    options: &ParserOptions,
+    parser: F,
+) -> Result<(T, Vec<ParseError>), ParseError>
+where
+    F: Fn(&'a [u8]) -> IResult<&'a [u8], T>,
+{
+    let base = source.as_ptr() as usize;
+    let state = State {
+        base,
+        options: options.clone(),
+        failure: None,
+        warnings: Vec::new(),
+        depth: 0,
+    };
+    // parses may nest, e.g. a type constraint read while decoding
+    let outer = STATE.with(|s| s.borrow_mut().replace(state));
+    // a byte order mark is skipped, but offsets still count it
+    let result = parser(source.strip_prefix(BOM).unwrap_or(source));
+    let state = match STATE.with(|s| std::mem::replace(&mut *s.borrow_mut(), outer)) {
+        Some(state) => state,
+        None => unreachable!(),
+    };
+
+    if let Some((addr, kind)) = state.failure {
+        return Err(ParseError::new(kind, addr - base));
+    }
+    let warnings = state
+        .warnings
+        .into_iter()
+        .map(|(addr, kind)| ParseError::new(kind, addr - base))
+        .collect();
+    match result {
+        Done(&[], value) => Ok((value, warnings)),
+        Done(rest, _) => Err(ParseError::new(
+            ParseErrorKind::Syntax,
+            source.len() - rest.len(),
+        )),
+        _ => Err(ParseError::new(ParseErrorKind::Syntax, 0)),
+    }
+}
+
+// CRLF, LF, or a lone CR as old Mac editors write
+named!(pub line_break, alt_complete!(eol | tag!("\r")));
 
 named!(pub boolean<bool>,
     map!(
@@ -20,35 +200,143 @@ named!(
     ))
 );
 
+named!(sign, alt!(tag!("+") | tag!("-")));
+
 named!(pub float<f32>, map_res!(
     map_res!(
-        recognize!(alt_complete!(
-            delimited!(
-                pair!(opt!(alt!(tag!("+") | tag!("-"))), unsigned_float),
-                tag!("e"),
-                pair!(opt!(alt!(tag!("+") | tag!("-"))), unsigned_float)
-            ) |
-            unsigned_float
+        recognize!(tuple!(
+            opt!(sign),
+            unsigned_float,
+            opt!(complete!(tuple!(alt!(tag!("e") | tag!("E")), opt!(sign), digit)))
         )),
         str::from_utf8
     ),
     FromStr::from_str
 ));
 
-fn to_i(i: &str) -> Result<u32, ParseIntError> {
-    u32::from_str_radix(i, 16)
+/// Integer literals as hashicorp/hcl reads them: an optional sign followed by
+/// decimal, `0x` hex, `0o` or leading-zero octal, or `0b` binary digits.
+/// Literals that do not fit in an `i64` are an error.
+pub fn int(input: &[u8]) -> IResult<&[u8], i64> {
+    let (negative, unsigned) = match input.first() {
+        Some(&b'-') => (true, &input[1..]),
+        Some(&b'+') => (false, &input[1..]),
+        _ => (false, input),
+    };
+    let (radix, digits) = match unsigned {
+        [b'0', b'x', ..] | [b'0', b'X', ..] => (16, &unsigned[2..]),
+        [b'0', b'o', ..] | [b'0', b'O', ..] => (8, &unsigned[2..]),
+        [b'0', b'b', ..] | [b'0', b'B', ..] => (2, &unsigned[2..]),
+        [b'0', c, ..] if c.is_ascii_digit() => (8, &unsigned[1..]),
+        _ => (10, unsigned),
+    };
+    let len = digits
+        .iter()
+        .position(|c| !(radix == 16 && c.is_ascii_hexdigit() || c.is_ascii_digit()))
+        .unwrap_or(digits.len());
+    if len == 0 {
+        return Error(error_position!(ErrorKind::Digit, input));
+    }
+    // digits outside the radix and overflow both fail here
+    let text = format!(
+        "{}{}",
+        if negative { "-" } else { "" },
+        String::from_utf8_lossy(&digits[..len])
+    );
+    match i64::from_str_radix(&text, radix) {
+        Ok(i) => Done(&digits[len..], i),
+        Err(_) => Error(error_position!(ErrorKind::Custom(0), input)),
+    }
 }
 
-named!(pub int<u32>, map_res!(
-    map_res!(
-        preceded!(tag!("0x"), hex_digit),
-        str::from_utf8
-    ),
-    to_i
-));
+/// A float if the literal has a fraction or exponent, an integer otherwise.
+pub fn number(input: &[u8]) -> IResult<&[u8], f32> {
+    if let Done(rest, f) = float(input) {
+        let text = &input[..input.len() - rest.len()];
+        if text.iter().any(|&c| c == b'.' || c == b'e' || c == b'E') {
+            return Done(rest, f);
+        }
+    }
+    map!(input, int, |i| i as f32)
+}
 
-// TODO: add support for octal
-named!(pub number<f32>, alt_complete!(
-    map!(int, |i| { i as f32 }) |
-    float
-));
+/// A fresh directory for a test's files, removed when dropped, so also when
+/// the test fails.
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "molysite-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// Writes each file, creating the directories it is in.
+    pub fn write(&self, files: &[(&str, &str)]) {
+        for &(name, source) in files {
+            let path = self.0.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn int_test() {
+    assert_eq!(Done(&b""[..], 42), int(b"42"));
+    assert_eq!(Done(&b""[..], -5), int(b"-5"));
+    assert_eq!(Done(&b""[..], 66), int(b"0x42"));
+    assert_eq!(Done(&b""[..], -66), int(b"-0X42"));
+    assert_eq!(Done(&b""[..], 0o644), int(b"0644"));
+    assert_eq!(Done(&b""[..], 0o644), int(b"0o644"));
+    assert_eq!(Done(&b""[..], 5), int(b"0b101"));
+    assert_eq!(Done(&b""[..], 0), int(b"0"));
+    assert_eq!(Done(&b" "[..], 7), int(b"+7 "));
+    assert_eq!(Done(&b""[..], i64::MIN), int(b"-9223372036854775808"));
+    assert!(int(b"9223372036854775808").is_err());
+    assert!(int(b"0x10000000000000000").is_err());
+    assert!(int(b"089").is_err());
+    assert!(int(b"0b2").is_err());
+    assert!(int(b"-").is_err());
+}
+
+#[test]
+fn number_test() {
+    assert_eq!(Done(&b""[..], -5.), number(b"-5"));
+    assert_eq!(Done(&b""[..], 420.), number(b"0644"));
+    assert_eq!(Done(&b""[..], -1.5), number(b"-1.5"));
+    assert_eq!(Done(&b""[..], 0.5), number(b"0.5"));
+    assert_eq!(Done(&b""[..], 1.2e-10), number(b"1.2e-10"));
+    assert_eq!(Done(&b""[..], -1e10), number(b"-1E10"));
+    assert!(number(b"99999999999999999999").is_err());
+}
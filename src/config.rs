@@ -0,0 +1,402 @@
+//! Loading an application's configuration from layered sources.
+//!
+//! From lowest to highest precedence: programmatic defaults, files found on
+//! the search path (later directories overriding earlier ones), then
+//! environment variables such as `MYAPP_DB__HOST` for `db.host`. Layers are
+//! merged with `MergeStrategy::Blocks`, or by the labels a schema declares,
+//! and the source of every effective value is kept.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+use crate::common::TempDir;
+use crate::hcl::parse_hcl;
+use crate::json::{parse_json, parse_value};
+use crate::schema::Schema;
+use crate::types::{JsonValue, MergeStrategy, ReadError, Type, TypeError};
+
+/// Where an effective value came from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Source::Default => write!(f, "default"),
+            Source::File(ref path) => write!(f, "{}", path.display()),
+            Source::Env(ref var) => write!(f, "environment variable {}", var),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A file on the search path could not be read or parsed.
+    File(PathBuf, ReadError),
+    /// An environment variable could not be converted to the type of the
+    /// value it overrides.
+    Env(String, TypeError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::File(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Env(ref var, ref e) => write!(f, "{}: {}", var, e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            ConfigError::File(_, ref e) => Some(e),
+            ConfigError::Env(_, ref e) => Some(e),
+        }
+    }
+}
+
+/// Finds and layers the configuration of one application.
+#[derive(Clone, Debug)]
+pub struct Loader {
+    name: String,
+    search_path: Vec<PathBuf>,
+    prefix: Option<String>,
+    defaults: Vec<(String, JsonValue)>,
+    schema: Option<Schema>,
+}
+
+impl Loader {
+    /// Loads `<name>.hcl` and `<name>.json` from the search path; the
+    /// environment prefix defaults to `name` in upper case.
+    pub fn new(name: &str) -> Loader {
+        Loader {
+            name: name.to_string(),
+            search_path: Vec::new(),
+            prefix: Some(name.to_uppercase()),
+            defaults: Vec::new(),
+            schema: None,
+        }
+    }
+
+    /// Adds a directory to search, with precedence over those added before.
+    pub fn search(mut self, dir: impl Into<PathBuf>) -> Loader {
+        self.search_path.push(dir.into());
+        self
+    }
+
+    /// Sets the prefix of the environment variables to read, or `None` to
+    /// ignore the environment.
+    pub fn env_prefix(mut self, prefix: Option<&str>) -> Loader {
+        self.prefix = prefix.map(|p| p.to_string());
+        self
+    }
+
+    /// Sets a default for the dotted `path`, such as `db.port`.
+    pub fn default(mut self, path: &str, value: JsonValue) -> Loader {
+        self.defaults.push((path.to_string(), value));
+        self
+    }
+
+    /// Merges the blocks `schema` declares by their labels.
+    pub fn schema(mut self, schema: Schema) -> Loader {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Loads the configuration, overlaying the process environment.
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        self.load_with_env(std::env::vars())
+    }
+
+    /// Loads the configuration, overlaying the given environment variables.
+    pub fn load_with_env<I>(&self, vars: I) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut config = Config {
+            value: JsonValue::Object(Default::default()),
+            origins: BTreeMap::new(),
+        };
+        for (path, value) in &self.defaults {
+            let mut layer = JsonValue::Object(Default::default());
+            set(&mut layer, &split(path), value.clone());
+            config.overlay(layer, &Source::Default, self.schema.as_ref());
+        }
+        for dir in &self.search_path {
+            for ext in &["hcl", "json"] {
+                let path = dir.join(format!("{}.{}", self.name, ext));
+                if let Some(layer) = read(&path)? {
+                    config.overlay(layer, &Source::File(path), self.schema.as_ref());
+                }
+            }
+        }
+        if let Some(ref prefix) = self.prefix {
+            let prefix = format!("{}_", prefix);
+            let mut vars: Vec<(String, String)> = vars
+                .into_iter()
+                .filter(|(var, _)| var.starts_with(&prefix) && var.len() > prefix.len())
+                .collect();
+            vars.sort();
+            for (var, raw) in vars {
+                let path: Vec<String> = var[prefix.len()..]
+                    .split("__")
+                    .map(|s| s.to_lowercase())
+                    .collect();
+                let value = coerce(get(&config.value, &path), raw)
+                    .map_err(|e| ConfigError::Env(var.clone(), e))?;
+                let mut layer = JsonValue::Object(Default::default());
+                set(&mut layer, &path, value);
+                config.overlay(layer, &Source::Env(var), self.schema.as_ref());
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn read(path: &Path) -> Result<Option<JsonValue>, ConfigError> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(ConfigError::File(path.to_path_buf(), e.into())),
+    };
+    let parsed = if path.extension().is_some_and(|e| e == "json") {
+        parse_json(&source)
+    } else {
+        parse_hcl(&source)
+    };
+    parsed
+        .map(Some)
+        .map_err(|e| ConfigError::File(path.to_path_buf(), e.into()))
+}
+
+// converts an environment variable to the type of the value it overrides,
+// or guesses a type for a new one
+fn coerce(existing: Option<&JsonValue>, raw: String) -> Result<JsonValue, TypeError> {
+    match existing {
+        Some(existing @ &JsonValue::Array(_)) | Some(existing @ &JsonValue::Object(_)) => {
+            let parsed = parse_value(&raw).map_err(|e| TypeError {
+                path: String::new(),
+                message: format!("expected {} as JSON, {}", existing.type_name(), e),
+            })?;
+            if parsed.type_name() != existing.type_name() {
+                return Err(TypeError {
+                    path: String::new(),
+                    message: format!(
+                        "expected {}, found {}",
+                        existing.type_name(),
+                        parsed.type_name()
+                    ),
+                });
+            }
+            Ok(parsed)
+        }
+        Some(&JsonValue::Null) | None => Ok(if raw == "true" || raw == "false" {
+            JsonValue::Boolean(raw == "true")
+        } else if let Ok(n) = raw.parse() {
+            JsonValue::Num(n)
+        } else {
+            JsonValue::Str(raw)
+        }),
+        Some(existing) => Type::of(existing).convert(&JsonValue::Str(raw)),
+    }
+}
+
+fn split(path: &str) -> Vec<String> {
+    path.split('.').map(|s| s.to_string()).collect()
+}
+
+// looks `path` up, looking through the lists HCL1 wraps blocks in
+fn get<'a>(mut value: &'a JsonValue, path: &[String]) -> Option<&'a JsonValue> {
+    for key in path {
+        value = match *value {
+            JsonValue::Object(ref obj) => obj.get(key)?,
+            JsonValue::Array(ref items) => items.iter().rev().find_map(|item| match *item {
+                JsonValue::Object(ref obj) => obj.get(key),
+                _ => None,
+            })?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+fn set(value: &mut JsonValue, path: &[String], new: JsonValue) {
+    match path.split_first() {
+        None => *value = new,
+        Some((key, rest)) => {
+            if let JsonValue::Object(ref mut obj) = *value {
+                let child = obj
+                    .entry(key.clone())
+                    .or_insert_with(|| JsonValue::Object(Default::default()));
+                set(child, rest, new);
+            }
+        }
+    }
+}
+
+// every value that is not an object or a block, by dotted path
+fn leaves(value: &JsonValue, path: &str, out: &mut Vec<String>) {
+    match *value {
+        JsonValue::Object(ref obj) => {
+            for (key, child) in obj {
+                let child_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                leaves(child, &child_path, out);
+            }
+        }
+        JsonValue::Array(ref items)
+            if !items.is_empty() && items.iter().all(|i| matches!(*i, JsonValue::Object(_))) =>
+        {
+            for item in items {
+                leaves(item, path, out);
+            }
+        }
+        _ => out.push(path.to_string()),
+    }
+}
+
+/// The effective configuration, with the source of each value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    value: JsonValue,
+    origins: BTreeMap<String, Source>,
+}
+
+impl Config {
+    fn overlay(&mut self, layer: JsonValue, source: &Source, schema: Option<&Schema>) {
+        let mut paths = Vec::new();
+        leaves(&layer, "", &mut paths);
+        for path in paths {
+            self.origins.insert(path, source.clone());
+        }
+        match schema {
+            Some(schema) => self.value.merge_with_schema(layer, schema),
+            None => self.value.merge(layer, MergeStrategy::Blocks),
+        }
+    }
+
+    pub fn value(&self) -> &JsonValue {
+        &self.value
+    }
+
+    pub fn into_value(self) -> JsonValue {
+        self.value
+    }
+
+    /// The value at the dotted `path`, such as `db.host`.
+    pub fn get(&self, path: &str) -> Option<&JsonValue> {
+        get(&self.value, &split(path))
+    }
+
+    /// Where the value at the dotted `path` came from.
+    pub fn origin(&self, path: &str) -> Option<&Source> {
+        self.origins.get(path)
+    }
+
+    /// Every value's source, by dotted path.
+    pub fn origins(&self) -> impl Iterator<Item = (&str, &Source)> {
+        self.origins
+            .iter()
+            .map(|(path, source)| (path.as_str(), source))
+    }
+}
+
+#[test]
+fn config_layers() {
+    let dir = TempDir::new("config");
+    dir.write(&[
+        (
+            "base/myapp.hcl",
+            "name = \"app\"\ndb {\n  host = \"localhost\"\n  port = 5432\n}\n\
+             queue \"jobs\" {\n  size = 10\n}\n",
+        ),
+        (
+            "host/myapp.json",
+            "{\"db\": {\"host\": \"db1\"}, \"queue\": {\"jobs\": {\"workers\": 2}}}",
+        ),
+    ]);
+    let (base, host) = (dir.join("base"), dir.join("host"));
+
+    let vars = vec![
+        ("MYAPP_DB__PORT".to_string(), "6432".to_string()),
+        ("MYAPP_DEBUG".to_string(), "true".to_string()),
+        ("OTHER_DB__PORT".to_string(), "1".to_string()),
+    ];
+    let config = Loader::new("myapp")
+        .search(&base)
+        .search(&host)
+        .default("db.pool", JsonValue::Num(5.0))
+        .default("name", JsonValue::Str("unnamed".to_string()))
+        .schema(Schema::new().block("queue", 1, Schema::new()))
+        .load_with_env(vars)
+        .unwrap();
+
+    let get = |path| config.get(path).cloned();
+    assert_eq!(Some(JsonValue::Str("app".to_string())), get("name"));
+    assert_eq!(Some(JsonValue::Str("db1".to_string())), get("db.host"));
+    assert_eq!(Some(JsonValue::Num(6432.0)), get("db.port"));
+    assert_eq!(Some(JsonValue::Num(5.0)), get("db.pool"));
+    assert_eq!(Some(JsonValue::Boolean(true)), get("debug"));
+    assert_eq!(Some(JsonValue::Num(10.0)), get("queue.jobs.size"));
+    assert_eq!(Some(JsonValue::Num(2.0)), get("queue.jobs.workers"));
+
+    let origins: Vec<String> = config
+        .origins()
+        .map(|(path, source)| format!("{} {}", path, source))
+        .collect();
+    assert_eq!(
+        vec![
+            format!("db.host {}", host.join("myapp.json").display()),
+            "db.pool default".to_string(),
+            "db.port environment variable MYAPP_DB__PORT".to_string(),
+            "debug environment variable MYAPP_DEBUG".to_string(),
+            format!("name {}", base.join("myapp.hcl").display()),
+            format!("queue.jobs.size {}", base.join("myapp.hcl").display()),
+            format!("queue.jobs.workers {}", host.join("myapp.json").display()),
+        ],
+        origins
+    );
+}
+
+#[test]
+fn config_env_errors() {
+    let vars = vec![("APP_PORT".to_string(), "eighty".to_string())];
+    let err = Loader::new("app")
+        .default("port", JsonValue::Num(80.0))
+        .load_with_env(vars)
+        .unwrap_err();
+    assert_eq!(
+        "APP_PORT: a number is required, found \"eighty\"",
+        err.to_string()
+    );
+
+    let tags = |raw: &str| {
+        Loader::new("app")
+            .default("tags", JsonValue::Array(Vec::new()))
+            .load_with_env(vec![("APP_TAGS".to_string(), raw.to_string())])
+            .map(|config| config.get("tags").cloned())
+            .map_err(|e| e.to_string())
+    };
+    assert_eq!(
+        Ok(Some(JsonValue::Array(vec![
+            JsonValue::Str("a".to_string()),
+            JsonValue::Str("b".to_string()),
+        ]))),
+        tags(" [\"a\", \"b\"] ")
+    );
+    assert_eq!(
+        Err("APP_TAGS: expected tuple, found object".to_string()),
+        tags("{\"a\": 1}")
+    );
+}
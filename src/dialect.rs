@@ -0,0 +1,172 @@
+//! Detecting which syntax a configuration is written in.
+
+use std::fmt;
+use std::path::Path;
+
+use crate::hcl::parse_hcl;
+use crate::hcl2::parse_hcl2;
+use crate::json::parse_json;
+use crate::lexer::{tokenize, TokenKind};
+use crate::types::{JsonValue, ParseError};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dialect {
+    Hcl1,
+    Hcl2,
+    Json,
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Dialect::Hcl1 => write!(f, "HCL1"),
+            Dialect::Hcl2 => write!(f, "HCL2"),
+            Dialect::Json => write!(f, "HCL JSON"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DetectError {
+    /// The input is valid HCL1 and HCL2, but means something different in each.
+    Ambiguous,
+    /// The input is not valid in any of the dialects it could be written in.
+    Invalid(Vec<(Dialect, ParseError)>),
+}
+
+impl fmt::Display for DetectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DetectError::Ambiguous => write!(
+                f,
+                "input is valid HCL1 and HCL2 with different meanings, parse it explicitly"
+            ),
+            DetectError::Invalid(ref errors) => {
+                write!(f, "input is not valid")?;
+                for (n, &(dialect, _)) in errors.iter().enumerate() {
+                    write!(f, "{}{}", if n == 0 { " " } else { " or " }, dialect)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn first_significant_byte(source: &str) -> Option<u8> {
+    let source = source.strip_prefix('\u{FEFF}').unwrap_or(source);
+    source.bytes().find(|c| !c.is_ascii_whitespace())
+}
+
+/// Parses a configuration in whichever dialect it is written in.
+///
+/// `.json` files and documents starting with `{` are HCL JSON. Anything else
+/// is tried as both HCL1 and HCL2, and unquoted expressions only parse as
+/// HCL2. Input that is valid in both dialects is read as HCL2 from a `.tf`
+/// file and as HCL1 from a `.hcl` file. Without either extension, a string
+/// holding a template directive (`%{`), which only exists in HCL2, makes it
+/// HCL2; otherwise it is reported as HCL1, the dialect `parse_hcl`
+/// implements, if both give the same meaning, and as ambiguous if not.
+pub fn parse_any(source: &str, filename: &str) -> Result<(Dialect, JsonValue), DetectError> {
+    let extension = Path::new(filename)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    if extension.as_deref() == Some("json") || first_significant_byte(source) == Some(b'{') {
+        return parse_json(source)
+            .map(|v| (Dialect::Json, v))
+            .map_err(|e| DetectError::Invalid(vec![(Dialect::Json, e)]));
+    }
+
+    let known = matches!(extension.as_deref(), Some("tf") | Some("hcl"));
+    let hcl2 = parse_hcl2(source);
+    if !known && has_directive(source) {
+        return hcl2
+            .map(|v| (Dialect::Hcl2, v))
+            .map_err(|e| DetectError::Invalid(vec![(Dialect::Hcl2, e)]));
+    }
+    match (parse_hcl(source), hcl2) {
+        (Ok(v1), Ok(v2)) => match extension.as_deref() {
+            Some("tf") => Ok((Dialect::Hcl2, v2)),
+            Some("hcl") => Ok((Dialect::Hcl1, v1)),
+            _ if v1 == v2 => Ok((Dialect::Hcl1, v1)),
+            _ => Err(DetectError::Ambiguous),
+        },
+        (Ok(v), Err(_)) => Ok((Dialect::Hcl1, v)),
+        (Err(_), Ok(v)) => Ok((Dialect::Hcl2, v)),
+        (Err(e1), Err(e2)) => Err(DetectError::Invalid(vec![
+            (Dialect::Hcl1, e1),
+            (Dialect::Hcl2, e2),
+        ])),
+    }
+}
+
+// whether a string or heredoc holds a template directive, rather than a
+// comment or the like merely containing `%{`
+fn has_directive(source: &str) -> bool {
+    tokenize(source).is_ok_and(|tokens| {
+        tokens
+            .iter()
+            .any(|t| t.kind == TokenKind::InterpolationStart && t.text.starts_with("%{"))
+    })
+}
+
+#[test]
+fn dialect_json() {
+    let (dialect, _) = parse_any("{\"a\": 1}", "main.tf.json").unwrap();
+    assert_eq!(Dialect::Json, dialect);
+    let (dialect, _) = parse_any("\n  {\"a\": 1}", "config").unwrap();
+    assert_eq!(Dialect::Json, dialect);
+    let (dialect, _) = parse_any("\u{FEFF}{\"a\": 1}", "config").unwrap();
+    assert_eq!(Dialect::Json, dialect);
+    assert_eq!(
+        Err(DetectError::Invalid(vec![(
+            Dialect::Json,
+            ParseError::new(crate::types::ParseErrorKind::Syntax, 0)
+        )])),
+        parse_any("a = 1", "config.json")
+    );
+}
+
+#[test]
+fn dialect_hcl1() {
+    let (dialect, _) = parse_any("\"quoted\" = 1\nfoo.bar = true", "a.hcl").unwrap();
+    assert_eq!(Dialect::Hcl1, dialect);
+    let (dialect, _) = parse_any("a = 1\nb { c = \"d\" }", "a.hcl").unwrap();
+    assert_eq!(Dialect::Hcl1, dialect);
+    let (dialect, _) = parse_any("tags = { env = \"prod\" }", "a.hcl").unwrap();
+    assert_eq!(Dialect::Hcl1, dialect);
+    // the extension decides, whatever looks like a directive
+    let (dialect, _) = parse_any("# uses %{x}\nb { c = \"d\" }", "a.hcl").unwrap();
+    assert_eq!(Dialect::Hcl1, dialect);
+    let (dialect, _) = parse_any("a = \"%{ if true }x%{ endif }\"", "a.hcl").unwrap();
+    assert_eq!(Dialect::Hcl1, dialect);
+    let (dialect, _) = parse_any("# uses %{x}\na = 1", "config").unwrap();
+    assert_eq!(Dialect::Hcl1, dialect);
+}
+
+#[test]
+fn dialect_hcl2() {
+    let (dialect, _) = parse_any("a = var.x", "main.tf").unwrap();
+    assert_eq!(Dialect::Hcl2, dialect);
+    let (dialect, _) = parse_any("a = \"%{ if true }x%{ endif }\"", "main.tf").unwrap();
+    assert_eq!(Dialect::Hcl2, dialect);
+    let (dialect, _) = parse_any("a = \"%{ if true }x%{ endif }\"", "config").unwrap();
+    assert_eq!(Dialect::Hcl2, dialect);
+    let (dialect, value) = parse_any("tags = { env = \"prod\" }", "main.tf").unwrap();
+    assert_eq!(Dialect::Hcl2, dialect);
+    assert_eq!(parse_hcl2("tags = { env = \"prod\" }"), Ok(value));
+    let (dialect, _) = parse_any("a = 1", "main.tf").unwrap();
+    assert_eq!(Dialect::Hcl2, dialect);
+}
+
+#[test]
+fn dialect_ambiguous() {
+    // hcl1 wraps the object in a list, hcl2 does not
+    assert_eq!(
+        Err(DetectError::Ambiguous),
+        parse_any("tags = { env = \"prod\" }", "config")
+    );
+    assert!(match parse_any("a = ", "main.tf") {
+        Err(DetectError::Invalid(ref errors)) => errors.len() == 2,
+        _ => false,
+    });
+}
@@ -0,0 +1,1330 @@
+//! HCL2 expression syntax: parsing and evaluation over `JsonValue` data.
+//!
+//! This covers the expression language described in the HCL native syntax
+//! specification, including `for` expressions and both splat operators.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::{self, FromStr};
+
+use nom::IResult::{Done, Error};
+use nom::{digit, multispace, ErrorKind, IResult};
+
+use crate::common::{nested, run};
+use crate::hcl::comment;
+use crate::template::{self, TemplatePart};
+use crate::types::{JsonValue, ParseError};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Literal(JsonValue),
+    Template(Vec<TemplatePart>),
+    Variable(String),
+    Tuple(Vec<Expr>),
+    Object(Vec<(Expr, Expr)>),
+    FunctionCall(String, Vec<Expr>, bool),
+    GetAttr(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Splat(Box<Expr>, Vec<Traversal>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    For(Box<ForExpr>),
+    Parens(Box<Expr>),
+}
+
+/// A single attribute or index step applied to each element by a splat.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Traversal {
+    Attr(String),
+    Index(Expr),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Multiply,
+    Divide,
+    Modulo,
+    Add,
+    Subtract,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+/// `[for k, v in coll : value if cond]`, or the object form when `key` is set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForExpr {
+    pub key_var: Option<String>,
+    pub value_var: String,
+    pub collection: Expr,
+    pub key: Option<Expr>,
+    pub value: Expr,
+    pub condition: Option<Expr>,
+    pub grouped: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalError {
+    UnknownVariable(String),
+    UnknownFunction(String),
+    UnsupportedAttribute(String),
+    InvalidIndex(String),
+    TypeMismatch(&'static str, &'static str),
+    DuplicateKey(String),
+    InvalidArguments(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvalError::UnknownVariable(ref name) => write!(f, "unknown variable \"{}\"", name),
+            EvalError::UnknownFunction(ref name) => write!(f, "unknown function \"{}\"", name),
+            EvalError::UnsupportedAttribute(ref name) => {
+                write!(f, "unsupported attribute \"{}\"", name)
+            }
+            EvalError::InvalidIndex(ref index) => write!(f, "invalid index {}", index),
+            EvalError::TypeMismatch(expected, found) => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            EvalError::DuplicateKey(ref key) => write!(f, "duplicate object key \"{}\"", key),
+            EvalError::InvalidArguments(ref msg) => {
+                write!(f, "invalid function arguments: {}", msg)
+            }
+        }
+    }
+}
+
+impl BinaryOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo => 6,
+            BinaryOp::Add | BinaryOp::Subtract => 5,
+            BinaryOp::Greater
+            | BinaryOp::GreaterOrEqual
+            | BinaryOp::Less
+            | BinaryOp::LessOrEqual => 4,
+            BinaryOp::Equal | BinaryOp::NotEqual => 3,
+            BinaryOp::And => 2,
+            BinaryOp::Or => 1,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Modulo => "%",
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterOrEqual => ">=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessOrEqual => "<=",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+}
+
+// writes `e`, parenthesized when it binds more loosely than `precedence`
+fn write_operand(f: &mut fmt::Formatter, e: &Expr, precedence: u8) -> fmt::Result {
+    let inner = match *e {
+        Expr::Binary(op, _, _) => op.precedence(),
+        Expr::Conditional(_, _, _) => 0,
+        _ => u8::MAX,
+    };
+    if inner < precedence {
+        write!(f, "({})", e)
+    } else {
+        write!(f, "{}", e)
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter, items: &[Expr]) -> fmt::Result {
+    for (n, item) in items.iter().enumerate() {
+        if n != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+/// Prints the expression back as HCL2 source.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Literal(JsonValue::Str(ref s)) => {
+                template::write_template(f, &[TemplatePart::Literal(s.clone())], true)
+            }
+            Expr::Literal(ref v) => write!(f, "{}", v),
+            Expr::Template(ref parts) => template::write_template(f, parts, true),
+            Expr::Variable(ref name) => write!(f, "{}", name),
+            Expr::Tuple(ref items) => {
+                write!(f, "[")?;
+                write_list(f, items)?;
+                write!(f, "]")
+            }
+            Expr::Object(ref items) => {
+                write!(f, "{{")?;
+                for (n, (k, v)) in items.iter().enumerate() {
+                    let sep = if n == 0 { " " } else { ", " };
+                    write!(f, "{}{} = {}", sep, k, v)?;
+                }
+                if items.is_empty() {
+                    write!(f, "}}")
+                } else {
+                    write!(f, " }}")
+                }
+            }
+            Expr::FunctionCall(ref name, ref args, expand) => {
+                write!(f, "{}(", name)?;
+                write_list(f, args)?;
+                write!(f, "{})", if expand { "..." } else { "" })
+            }
+            Expr::GetAttr(ref e, ref name) => match **e {
+                Expr::Splat(_, _) => write!(f, "({}).{}", e, name),
+                _ => write!(f, "{}.{}", e, name),
+            },
+            Expr::Index(ref e, ref key) => match **e {
+                Expr::Splat(_, _) => write!(f, "({})[{}]", e, key),
+                _ => write!(f, "{}[{}]", e, key),
+            },
+            Expr::Splat(ref e, ref each) => {
+                let attrs_only = each.iter().all(|t| matches!(*t, Traversal::Attr(_)));
+                write!(f, "{}{}", e, if attrs_only { ".*" } else { "[*]" })?;
+                for step in each {
+                    match *step {
+                        Traversal::Attr(ref name) => write!(f, ".{}", name)?,
+                        Traversal::Index(ref key) => write!(f, "[{}]", key)?,
+                    }
+                }
+                Ok(())
+            }
+            Expr::Unary(op, ref e) => {
+                write!(f, "{}", if op == UnaryOp::Negate { "-" } else { "!" })?;
+                write_operand(f, e, u8::MAX)
+            }
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                write_operand(f, lhs, op.precedence())?;
+                write!(f, " {} ", op.symbol())?;
+                // operators are left associative
+                write_operand(f, rhs, op.precedence() + 1)
+            }
+            Expr::Conditional(ref cond, ref t, ref e) => {
+                write_operand(f, cond, 1)?;
+                write!(f, " ? {} : {}", t, e)
+            }
+            Expr::For(ref fe) => {
+                let (open, close) = if fe.key.is_some() {
+                    ("{", "}")
+                } else {
+                    ("[", "]")
+                };
+                write!(f, "{}for ", open)?;
+                if let Some(ref k) = fe.key_var {
+                    write!(f, "{}, ", k)?;
+                }
+                write!(f, "{} in {} : ", fe.value_var, fe.collection)?;
+                if let Some(ref k) = fe.key {
+                    write!(f, "{} => ", k)?;
+                }
+                write!(f, "{}", fe.value)?;
+                if fe.grouped {
+                    write!(f, "...")?;
+                }
+                if let Some(ref cond) = fe.condition {
+                    write!(f, " if {}", cond)?;
+                }
+                write!(f, "{}", close)
+            }
+            Expr::Parens(ref e) => write!(f, "({})", e),
+        }
+    }
+}
+
+pub type Function = fn(&[JsonValue]) -> Result<JsonValue, EvalError>;
+
+/// Variables and functions available while evaluating an expression.
+pub struct Context {
+    variables: HashMap<String, JsonValue>,
+    functions: HashMap<String, Function>,
+}
+
+impl Default for Context {
+    fn default() -> Context {
+        Context::new()
+    }
+}
+
+impl Context {
+    /// Creates a context with no variables and the built-in functions.
+    pub fn new() -> Context {
+        let mut functions: HashMap<String, Function> = HashMap::new();
+        functions.insert("upper".to_string(), fn_upper);
+        functions.insert("lower".to_string(), fn_lower);
+        functions.insert("length".to_string(), fn_length);
+        functions.insert("keys".to_string(), fn_keys);
+        functions.insert("values".to_string(), fn_values);
+        functions.insert("concat".to_string(), fn_concat);
+        functions.insert("join".to_string(), fn_join);
+        functions.insert("tostring".to_string(), fn_tostring);
+        functions.insert("tonumber".to_string(), fn_tonumber);
+        Context {
+            variables: HashMap::new(),
+            functions,
+        }
+    }
+
+    pub fn set_variable(&mut self, name: &str, value: JsonValue) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    pub fn set_function(&mut self, name: &str, function: Function) {
+        self.functions.insert(name.to_string(), function);
+    }
+}
+
+pub fn parse_expr(source: &str) -> Result<Expr, ParseError> {
+    run(source.as_bytes(), full_expression)
+}
+
+named!(full_expression<Expr>, terminated!(expression, blank));
+
+// whitespace, including newlines, and comments are insignificant inside expressions
+named!(
+    pub blank,
+    recognize!(many0!(alt_complete!(
+        multispace | comment
+    )))
+);
+
+macro_rules! tok (
+    ($i:expr, $t:expr) => (
+        preceded!($i, blank, complete!(tag!($t)))
+    )
+);
+
+pub(crate) fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+fn is_ident_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'-'
+}
+
+pub(crate) fn identifier(input: &[u8]) -> IResult<&[u8], String> {
+    let i = match blank(input) {
+        Done(i, _) => i,
+        _ => input,
+    };
+    match i.first() {
+        Some(&c) if is_ident_start(c) => {
+            let end = i.iter().position(|&c| !is_ident_char(c)).unwrap_or(i.len());
+            Done(&i[end..], String::from_utf8_lossy(&i[..end]).into_owned())
+        }
+        _ => Error(error_position!(ErrorKind::Custom(0), input)),
+    }
+}
+
+pub(crate) fn keyword<'a>(input: &'a [u8], word: &str) -> IResult<&'a [u8], ()> {
+    match identifier(input) {
+        Done(i, ref id) if id == word => Done(i, ()),
+        _ => Error(error_position!(ErrorKind::Custom(0), input)),
+    }
+}
+
+// `=` as used in object constructors, which must not swallow `==` or `=>`
+fn assign(input: &[u8]) -> IResult<&[u8], ()> {
+    let i = match blank(input) {
+        Done(i, _) => i,
+        _ => input,
+    };
+    match i.first() {
+        Some(&b':') => Done(&i[1..], ()),
+        Some(&b'=') if i.get(1) != Some(&b'=') && i.get(1) != Some(&b'>') => Done(&i[1..], ()),
+        _ => Error(error_position!(ErrorKind::Custom(0), input)),
+    }
+}
+
+named!(
+    number_lit<f32>,
+    map_res!(
+        map_res!(
+            recognize!(tuple!(
+                digit,
+                opt!(complete!(pair!(tag!("."), digit))),
+                opt!(complete!(tuple!(
+                    alt!(tag!("e") | tag!("E")),
+                    opt!(alt!(tag!("+") | tag!("-"))),
+                    digit
+                )))
+            )),
+            str::from_utf8
+        ),
+        FromStr::from_str
+    )
+);
+
+named!(
+    quoted_template<Expr>,
+    do_parse!(
+        tok!("\"")
+            >> parts: call!(template::quoted_template_parts)
+            >> complete!(tag!("\""))
+            >> (Expr::Template(parts))
+    )
+);
+
+named!(
+    tuple_cons<Expr>,
+    do_parse!(
+        tok!("[")
+            >> items: opt!(complete!(do_parse!(
+                first: expression
+                    >> rest: many0!(complete!(preceded!(tok!(","), expression)))
+                    >> opt!(tok!(","))
+                    >> ({
+                        let mut items = vec![first];
+                        items.extend(rest);
+                        items
+                    })
+            )))
+            >> tok!("]")
+            >> (Expr::Tuple(items.unwrap_or_default()))
+    )
+);
+
+named!(
+    object_item<(Expr, Expr)>,
+    do_parse!(key: expression >> assign >> value: expression >> opt!(tok!(",")) >> ((key, value)))
+);
+
+named!(
+    object_cons<Expr>,
+    do_parse!(
+        tok!("{") >> items: many0!(complete!(object_item)) >> tok!("}") >> (Expr::Object(items))
+    )
+);
+
+named!(
+    for_intro<(Option<String>, String, Expr)>,
+    do_parse!(
+        call!(keyword, "for")
+            >> first: identifier
+            >> second: opt!(complete!(preceded!(tok!(","), identifier)))
+            >> call!(keyword, "in")
+            >> collection: expression
+            >> tok!(":")
+            >> (match second {
+                Some(value) => (Some(first), value, collection),
+                None => (None, first, collection),
+            })
+    )
+);
+
+named!(
+    for_tuple<Expr>,
+    do_parse!(
+        tok!("[")
+            >> intro: for_intro
+            >> value: expression
+            >> condition: opt!(complete!(preceded!(call!(keyword, "if"), expression)))
+            >> tok!("]")
+            >> (Expr::For(Box::new(ForExpr {
+                key_var: intro.0,
+                value_var: intro.1,
+                collection: intro.2,
+                key: None,
+                value,
+                condition,
+                grouped: false,
+            })))
+    )
+);
+
+named!(
+    for_object<Expr>,
+    do_parse!(
+        tok!("{")
+            >> intro: for_intro
+            >> key: expression
+            >> tok!("=>")
+            >> value: expression
+            >> grouped: opt!(tok!("..."))
+            >> condition: opt!(complete!(preceded!(call!(keyword, "if"), expression)))
+            >> tok!("}")
+            >> (Expr::For(Box::new(ForExpr {
+                key_var: intro.0,
+                value_var: intro.1,
+                collection: intro.2,
+                key: Some(key),
+                value,
+                condition,
+                grouped: grouped.is_some(),
+            })))
+    )
+);
+
+named!(
+    function_call<Expr>,
+    do_parse!(
+        name: identifier
+            >> tok!("(")
+            >> args: opt!(complete!(do_parse!(
+                first: expression
+                    >> rest: many0!(complete!(preceded!(tok!(","), expression)))
+                    >> expand: opt!(tok!("..."))
+                    >> opt!(tok!(","))
+                    >> ({
+                        let mut args = vec![first];
+                        args.extend(rest);
+                        (args, expand.is_some())
+                    })
+            )))
+            >> tok!(")")
+            >> ({
+                let (args, expand) = args.unwrap_or_default();
+                Expr::FunctionCall(name, args, expand)
+            })
+    )
+);
+
+named!(
+    parens<Expr>,
+    do_parse!(tok!("(") >> e: expression >> tok!(")") >> (Expr::Parens(Box::new(e))))
+);
+
+named!(
+    variable<Expr>,
+    map!(identifier, |name| match name.as_str() {
+        "true" => Expr::Literal(JsonValue::Boolean(true)),
+        "false" => Expr::Literal(JsonValue::Boolean(false)),
+        "null" => Expr::Literal(JsonValue::Null),
+        _ => Expr::Variable(name),
+    })
+);
+
+named!(
+    expr_primary<Expr>,
+    alt_complete!(
+        preceded!(blank, number_lit) => { |n| Expr::Literal(JsonValue::Num(n)) } |
+        for_tuple |
+        tuple_cons |
+        for_object |
+        object_cons |
+        quoted_template |
+        parens |
+        function_call |
+        variable
+    )
+);
+
+enum Postfix {
+    Step(Traversal),
+    Splat(Vec<Traversal>),
+}
+
+named!(
+    attr_step<Traversal>,
+    preceded!(
+        tok!("."),
+        alt_complete!(
+            identifier => { Traversal::Attr } |
+            digit      => { |d: &[u8]| Traversal::Index(Expr::Literal(JsonValue::Num(
+                String::from_utf8_lossy(d).parse().unwrap_or(0.)
+            ))) }
+        )
+    )
+);
+
+named!(
+    index_step<Traversal>,
+    do_parse!(tok!("[") >> e: expression >> tok!("]") >> (Traversal::Index(e)))
+);
+
+named!(
+    postfix<Postfix>,
+    alt_complete!(
+        // attribute-only splat: only the following attribute accesses apply per element
+        do_parse!(tok!(".") >> complete!(tag!("*")) >> each: many0!(complete!(attr_step)) >> (Postfix::Splat(each))) |
+        // full splat: every following traversal applies per element
+        do_parse!(
+            tok!("[") >> tok!("*") >> tok!("]")
+                >> each: many0!(complete!(alt_complete!(attr_step | index_step)))
+                >> (Postfix::Splat(each))
+        ) |
+        attr_step  => { Postfix::Step } |
+        index_step => { Postfix::Step }
+    )
+);
+
+named!(
+    expr_term<Expr>,
+    do_parse!(
+        init: expr_primary
+            >> term: fold_many0!(complete!(postfix), init, |acc, op| match op {
+                Postfix::Step(Traversal::Attr(name)) => Expr::GetAttr(Box::new(acc), name),
+                Postfix::Step(Traversal::Index(key)) => Expr::Index(Box::new(acc), Box::new(key)),
+                Postfix::Splat(each) => Expr::Splat(Box::new(acc), each),
+            })
+            >> (term)
+    )
+);
+
+named!(
+    expr_unary<Expr>,
+    alt_complete!(
+        preceded!(tok!("-"), call!(nested, expr_unary)) => {
+            |e| Expr::Unary(UnaryOp::Negate, Box::new(e))
+        } |
+        preceded!(tok!("!"), call!(nested, expr_unary)) => {
+            |e| Expr::Unary(UnaryOp::Not, Box::new(e))
+        } |
+        expr_term
+    )
+);
+
+fn binary(acc: Expr, (op, rhs): (BinaryOp, Expr)) -> Expr {
+    Expr::Binary(op, Box::new(acc), Box::new(rhs))
+}
+
+macro_rules! binary_level (
+    ($name:ident, $operand:ident, $($t:expr => $op:expr),*) => (
+        named!(
+            $name<Expr>,
+            do_parse!(
+                init: $operand
+                    >> res: fold_many0!(
+                        complete!(pair!(alt_complete!($(tok!($t) => { |_| $op } )|*), $operand)),
+                        init,
+                        binary
+                    )
+                    >> (res)
+            )
+        );
+    )
+);
+
+binary_level!(expr_multiplicative, expr_unary,
+    "*" => BinaryOp::Multiply, "/" => BinaryOp::Divide, "%" => BinaryOp::Modulo);
+binary_level!(expr_additive, expr_multiplicative,
+    "+" => BinaryOp::Add, "-" => BinaryOp::Subtract);
+binary_level!(expr_comparison, expr_additive,
+    ">=" => BinaryOp::GreaterOrEqual, ">" => BinaryOp::Greater,
+    "<=" => BinaryOp::LessOrEqual, "<" => BinaryOp::Less);
+binary_level!(expr_equality, expr_comparison,
+    "==" => BinaryOp::Equal, "!=" => BinaryOp::NotEqual);
+binary_level!(expr_and, expr_equality, "&&" => BinaryOp::And);
+binary_level!(expr_or, expr_and, "||" => BinaryOp::Or);
+
+/// Parses an expression, as a nested one counting towards the depth limit.
+pub fn expression(input: &[u8]) -> IResult<&[u8], Expr> {
+    nested(input, expr_conditional)
+}
+
+named!(
+    expr_conditional<Expr>,
+    do_parse!(
+        cond: expr_or
+            >> branches: opt!(complete!(do_parse!(
+                tok!("?") >> t: expression >> tok!(":") >> f: expression >> ((t, f))
+            )))
+            >> (match branches {
+                Some((t, f)) => Expr::Conditional(Box::new(cond), Box::new(t), Box::new(f)),
+                None => cond,
+            })
+    )
+);
+
+pub(crate) type Scope = Vec<(String, JsonValue)>;
+
+impl Expr {
+    pub fn evaluate(&self, ctx: &Context) -> Result<JsonValue, EvalError> {
+        self.eval(ctx, &mut Vec::new())
+    }
+
+    pub(crate) fn eval(&self, ctx: &Context, scope: &mut Scope) -> Result<JsonValue, EvalError> {
+        match *self {
+            Expr::Literal(ref v) => Ok(v.clone()),
+            Expr::Template(ref parts) => template::evaluate(parts, ctx, scope),
+            Expr::Variable(ref name) => scope
+                .iter()
+                .rev()
+                .find(|&(n, _)| n == name)
+                .map(|(_, v)| v)
+                .or_else(|| ctx.variables.get(name))
+                .cloned()
+                .ok_or_else(|| EvalError::UnknownVariable(name.clone())),
+            Expr::Tuple(ref items) => items
+                .iter()
+                .map(|e| e.eval(ctx, scope))
+                .collect::<Result<Vec<_>, _>>()
+                .map(JsonValue::Array),
+            Expr::Object(ref items) => {
+                let mut h = HashMap::new();
+                for (k, v) in items {
+                    let key = match *k {
+                        Expr::Variable(ref name) => name.clone(),
+                        _ => stringify(&k.eval(ctx, scope)?)?,
+                    };
+                    h.insert(key, v.eval(ctx, scope)?);
+                }
+                Ok(JsonValue::Object(h))
+            }
+            Expr::FunctionCall(ref name, ref args, expand) => {
+                let f = ctx
+                    .functions
+                    .get(name)
+                    .ok_or_else(|| EvalError::UnknownFunction(name.clone()))?;
+                let mut values = args
+                    .iter()
+                    .map(|e| e.eval(ctx, scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if expand {
+                    match values.pop() {
+                        Some(JsonValue::Array(rest)) => values.extend(rest),
+                        Some(other) => {
+                            return Err(EvalError::TypeMismatch("tuple", other.type_name()))
+                        }
+                        None => {}
+                    }
+                }
+                f(&values)
+            }
+            Expr::GetAttr(ref e, ref name) => get_attr(&e.eval(ctx, scope)?, name),
+            Expr::Index(ref e, ref key) => index(&e.eval(ctx, scope)?, &key.eval(ctx, scope)?),
+            Expr::Splat(ref e, ref each) => {
+                let items = match e.eval(ctx, scope)? {
+                    JsonValue::Null => Vec::new(),
+                    JsonValue::Array(items) => items,
+                    other => vec![other],
+                };
+                let mut out = Vec::new();
+                for item in items {
+                    let mut cur = item;
+                    for step in each {
+                        cur = match *step {
+                            Traversal::Attr(ref name) => get_attr(&cur, name)?,
+                            Traversal::Index(ref key) => index(&cur, &key.eval(ctx, scope)?)?,
+                        };
+                    }
+                    out.push(cur);
+                }
+                Ok(JsonValue::Array(out))
+            }
+            Expr::Unary(op, ref e) => {
+                let v = e.eval(ctx, scope)?;
+                match op {
+                    UnaryOp::Negate => Ok(JsonValue::Num(-to_number(&v)?)),
+                    UnaryOp::Not => Ok(JsonValue::Boolean(!to_bool(&v)?)),
+                }
+            }
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                binary_op(op, &lhs.eval(ctx, scope)?, &rhs.eval(ctx, scope)?)
+            }
+            Expr::Conditional(ref cond, ref t, ref f) => {
+                if to_bool(&cond.eval(ctx, scope)?)? {
+                    t.eval(ctx, scope)
+                } else {
+                    f.eval(ctx, scope)
+                }
+            }
+            Expr::For(ref f) => f.eval(ctx, scope),
+            Expr::Parens(ref e) => e.eval(ctx, scope),
+        }
+    }
+}
+
+/// A reference: a variable with the attributes accessed on it, such as
+/// `["aws_instance", "web", "id"]`.
+pub type Reference = Vec<String>;
+
+impl Expr {
+    /// The references in the expression, sorted, leaving out the variables
+    /// that `for` expressions and template directives bind.
+    pub fn references(&self) -> Vec<Reference> {
+        let mut out = Vec::new();
+        self.collect_references(&mut Vec::new(), &mut out);
+        out.sort();
+        out.dedup();
+        out
+    }
+
+    pub(crate) fn collect_references(&self, bound: &mut Vec<String>, out: &mut Vec<Reference>) {
+        match *self {
+            Expr::Variable(_) | Expr::GetAttr(..) | Expr::Index(..) => {
+                match self.traversal(bound, out) {
+                    Some(path) => {
+                        if !bound.contains(&path[0]) {
+                            out.push(path);
+                        }
+                    }
+                    None => match *self {
+                        Expr::GetAttr(ref e, _) => e.collect_references(bound, out),
+                        Expr::Index(ref e, ref key) => {
+                            e.collect_references(bound, out);
+                            key.collect_references(bound, out);
+                        }
+                        _ => {}
+                    },
+                }
+            }
+            Expr::Literal(_) => {}
+            Expr::Template(ref parts) => template::collect_references(parts, bound, out),
+            Expr::Tuple(ref items) | Expr::FunctionCall(_, ref items, _) => {
+                for e in items {
+                    e.collect_references(bound, out);
+                }
+            }
+            Expr::Object(ref items) => {
+                for (k, v) in items {
+                    // a bare key is a name, not a reference
+                    if !matches!(*k, Expr::Variable(_)) {
+                        k.collect_references(bound, out);
+                    }
+                    v.collect_references(bound, out);
+                }
+            }
+            Expr::Splat(ref e, ref each) => {
+                e.collect_references(bound, out);
+                for step in each {
+                    if let Traversal::Index(ref key) = *step {
+                        key.collect_references(bound, out);
+                    }
+                }
+            }
+            Expr::Unary(_, ref e) | Expr::Parens(ref e) => e.collect_references(bound, out),
+            Expr::Binary(_, ref lhs, ref rhs) => {
+                lhs.collect_references(bound, out);
+                rhs.collect_references(bound, out);
+            }
+            Expr::Conditional(ref cond, ref t, ref f) => {
+                cond.collect_references(bound, out);
+                t.collect_references(bound, out);
+                f.collect_references(bound, out);
+            }
+            Expr::For(ref f) => {
+                f.collection.collect_references(bound, out);
+                let depth = bound.len();
+                bound.extend(f.key_var.clone());
+                bound.push(f.value_var.clone());
+                for e in f.key.iter().chain(Some(&f.value)).chain(f.condition.iter()) {
+                    e.collect_references(bound, out);
+                }
+                bound.truncate(depth);
+            }
+        }
+    }
+
+    // the variable and attributes a traversal starts with, collecting the
+    // references in its index keys along the way
+    fn traversal(&self, bound: &mut Vec<String>, out: &mut Vec<Reference>) -> Option<Reference> {
+        match *self {
+            Expr::Variable(ref name) => Some(vec![name.clone()]),
+            Expr::GetAttr(ref e, ref name) => {
+                let mut path = e.traversal(bound, out)?;
+                path.push(name.clone());
+                Some(path)
+            }
+            Expr::Index(ref e, ref key) => {
+                let path = e.traversal(bound, out)?;
+                key.collect_references(bound, out);
+                Some(path)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ForExpr {
+    fn eval(&self, ctx: &Context, scope: &mut Scope) -> Result<JsonValue, EvalError> {
+        let pairs = iterate(self.collection.eval(ctx, scope)?)?;
+        let mut tuple = Vec::new();
+        let mut object: HashMap<String, JsonValue> = HashMap::new();
+        for (k, v) in pairs {
+            let depth = scope.len();
+            if let Some(ref name) = self.key_var {
+                scope.push((name.clone(), k));
+            }
+            scope.push((self.value_var.clone(), v));
+            let result = self.eval_item(ctx, scope);
+            scope.truncate(depth);
+            let (key, value) = match result? {
+                Some(item) => item,
+                None => continue,
+            };
+            match key {
+                None => tuple.push(value),
+                Some(key) => {
+                    if self.grouped {
+                        match object
+                            .entry(key)
+                            .or_insert_with(|| JsonValue::Array(Vec::new()))
+                        {
+                            JsonValue::Array(ref mut group) => group.push(value),
+                            _ => unreachable!(),
+                        }
+                    } else if object.insert(key.clone(), value).is_some() {
+                        return Err(EvalError::DuplicateKey(key));
+                    }
+                }
+            }
+        }
+        if self.key.is_some() {
+            Ok(JsonValue::Object(object))
+        } else {
+            Ok(JsonValue::Array(tuple))
+        }
+    }
+
+    fn eval_item(
+        &self,
+        ctx: &Context,
+        scope: &mut Scope,
+    ) -> Result<Option<(Option<String>, JsonValue)>, EvalError> {
+        if let Some(ref cond) = self.condition {
+            if !to_bool(&cond.eval(ctx, scope)?)? {
+                return Ok(None);
+            }
+        }
+        let key = match self.key {
+            Some(ref k) => Some(stringify(&k.eval(ctx, scope)?)?),
+            None => None,
+        };
+        Ok(Some((key, self.value.eval(ctx, scope)?)))
+    }
+}
+
+/// Returns the key/value pairs a `for` visits: indexes for tuples and
+/// lexically ordered keys for objects.
+pub(crate) fn iterate(collection: JsonValue) -> Result<Vec<(JsonValue, JsonValue)>, EvalError> {
+    Ok(match collection {
+        JsonValue::Array(items) => items
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (JsonValue::Num(i as f32), v))
+            .collect(),
+        JsonValue::Object(h) => {
+            let mut entries: Vec<(String, JsonValue)> = h.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            entries
+                .into_iter()
+                .map(|(k, v)| (JsonValue::Str(k), v))
+                .collect()
+        }
+        other => return Err(EvalError::TypeMismatch("collection", other.type_name())),
+    })
+}
+
+fn get_attr(v: &JsonValue, name: &str) -> Result<JsonValue, EvalError> {
+    match *v {
+        JsonValue::Object(ref h) => h
+            .get(name)
+            .cloned()
+            .ok_or_else(|| EvalError::UnsupportedAttribute(name.to_string())),
+        _ => Err(EvalError::UnsupportedAttribute(name.to_string())),
+    }
+}
+
+fn index(v: &JsonValue, key: &JsonValue) -> Result<JsonValue, EvalError> {
+    match *v {
+        JsonValue::Array(ref items) => {
+            let n = to_number(key)?;
+            if n < 0. || n.fract() != 0. {
+                return Err(EvalError::InvalidIndex(key.to_string()));
+            }
+            items
+                .get(n as usize)
+                .cloned()
+                .ok_or_else(|| EvalError::InvalidIndex(key.to_string()))
+        }
+        JsonValue::Object(ref h) => h
+            .get(&stringify(key)?)
+            .cloned()
+            .ok_or_else(|| EvalError::InvalidIndex(key.to_string())),
+        _ => Err(EvalError::TypeMismatch("collection", v.type_name())),
+    }
+}
+
+fn binary_op(op: BinaryOp, lhs: &JsonValue, rhs: &JsonValue) -> Result<JsonValue, EvalError> {
+    Ok(match op {
+        BinaryOp::Equal => JsonValue::Boolean(lhs == rhs),
+        BinaryOp::NotEqual => JsonValue::Boolean(lhs != rhs),
+        BinaryOp::And => JsonValue::Boolean(to_bool(lhs)? && to_bool(rhs)?),
+        BinaryOp::Or => JsonValue::Boolean(to_bool(lhs)? || to_bool(rhs)?),
+        _ => {
+            let (a, b) = (to_number(lhs)?, to_number(rhs)?);
+            match op {
+                BinaryOp::Multiply => JsonValue::Num(a * b),
+                BinaryOp::Divide => JsonValue::Num(a / b),
+                BinaryOp::Modulo => JsonValue::Num(a % b),
+                BinaryOp::Add => JsonValue::Num(a + b),
+                BinaryOp::Subtract => JsonValue::Num(a - b),
+                BinaryOp::Greater => JsonValue::Boolean(a > b),
+                BinaryOp::GreaterOrEqual => JsonValue::Boolean(a >= b),
+                BinaryOp::Less => JsonValue::Boolean(a < b),
+                BinaryOp::LessOrEqual => JsonValue::Boolean(a <= b),
+                _ => unreachable!(),
+            }
+        }
+    })
+}
+
+fn to_number(v: &JsonValue) -> Result<f32, EvalError> {
+    match *v {
+        JsonValue::Num(n) => Ok(n),
+        JsonValue::Str(ref s) => s
+            .parse()
+            .map_err(|_| EvalError::TypeMismatch("number", "string")),
+        _ => Err(EvalError::TypeMismatch("number", v.type_name())),
+    }
+}
+
+pub(crate) fn to_bool(v: &JsonValue) -> Result<bool, EvalError> {
+    match *v {
+        JsonValue::Boolean(b) => Ok(b),
+        JsonValue::Str(ref s) if s == "true" => Ok(true),
+        JsonValue::Str(ref s) if s == "false" => Ok(false),
+        _ => Err(EvalError::TypeMismatch("bool", v.type_name())),
+    }
+}
+
+/// Converts a primitive value to the string used when interpolating it.
+pub fn stringify(v: &JsonValue) -> Result<String, EvalError> {
+    match *v {
+        JsonValue::Str(ref s) => Ok(s.clone()),
+        JsonValue::Num(n) => Ok(n.to_string()),
+        JsonValue::Boolean(b) => Ok(b.to_string()),
+        _ => Err(EvalError::TypeMismatch("string", v.type_name())),
+    }
+}
+
+fn arity(args: &[JsonValue], n: usize) -> Result<(), EvalError> {
+    if args.len() != n {
+        return Err(EvalError::InvalidArguments(format!(
+            "expected {} argument(s), found {}",
+            n,
+            args.len()
+        )));
+    }
+    Ok(())
+}
+
+fn fn_upper(args: &[JsonValue]) -> Result<JsonValue, EvalError> {
+    arity(args, 1)?;
+    Ok(JsonValue::Str(stringify(&args[0])?.to_uppercase()))
+}
+
+fn fn_lower(args: &[JsonValue]) -> Result<JsonValue, EvalError> {
+    arity(args, 1)?;
+    Ok(JsonValue::Str(stringify(&args[0])?.to_lowercase()))
+}
+
+fn fn_length(args: &[JsonValue]) -> Result<JsonValue, EvalError> {
+    arity(args, 1)?;
+    let len = match args[0] {
+        JsonValue::Array(ref a) => a.len(),
+        JsonValue::Object(ref h) => h.len(),
+        JsonValue::Str(ref s) => s.chars().count(),
+        ref other => return Err(EvalError::TypeMismatch("collection", other.type_name())),
+    };
+    Ok(JsonValue::Num(len as f32))
+}
+
+fn sorted_entries(v: &JsonValue) -> Result<Vec<(&String, &JsonValue)>, EvalError> {
+    match *v {
+        JsonValue::Object(ref h) => {
+            let mut entries: Vec<_> = h.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            Ok(entries)
+        }
+        _ => Err(EvalError::TypeMismatch("object", v.type_name())),
+    }
+}
+
+fn fn_keys(args: &[JsonValue]) -> Result<JsonValue, EvalError> {
+    arity(args, 1)?;
+    Ok(JsonValue::Array(
+        sorted_entries(&args[0])?
+            .into_iter()
+            .map(|(k, _)| JsonValue::Str(k.clone()))
+            .collect(),
+    ))
+}
+
+fn fn_values(args: &[JsonValue]) -> Result<JsonValue, EvalError> {
+    arity(args, 1)?;
+    Ok(JsonValue::Array(
+        sorted_entries(&args[0])?
+            .into_iter()
+            .map(|(_, v)| v.clone())
+            .collect(),
+    ))
+}
+
+fn fn_concat(args: &[JsonValue]) -> Result<JsonValue, EvalError> {
+    let mut out = Vec::new();
+    for arg in args {
+        match *arg {
+            JsonValue::Array(ref a) => out.extend(a.iter().cloned()),
+            ref other => return Err(EvalError::TypeMismatch("tuple", other.type_name())),
+        }
+    }
+    Ok(JsonValue::Array(out))
+}
+
+fn fn_join(args: &[JsonValue]) -> Result<JsonValue, EvalError> {
+    arity(args, 2)?;
+    let sep = stringify(&args[0])?;
+    match args[1] {
+        JsonValue::Array(ref a) => Ok(JsonValue::Str(
+            a.iter()
+                .map(stringify)
+                .collect::<Result<Vec<_>, _>>()?
+                .join(&sep),
+        )),
+        ref other => Err(EvalError::TypeMismatch("tuple", other.type_name())),
+    }
+}
+
+fn fn_tostring(args: &[JsonValue]) -> Result<JsonValue, EvalError> {
+    arity(args, 1)?;
+    Ok(JsonValue::Str(stringify(&args[0])?))
+}
+
+fn fn_tonumber(args: &[JsonValue]) -> Result<JsonValue, EvalError> {
+    arity(args, 1)?;
+    Ok(JsonValue::Num(to_number(&args[0])?))
+}
+
+#[cfg(test)]
+fn eval_with(source: &str, vars: &[(&str, JsonValue)]) -> Result<JsonValue, EvalError> {
+    let mut ctx = Context::new();
+    for &(name, ref value) in vars {
+        ctx.set_variable(name, value.clone());
+    }
+    parse_expr(source)
+        .expect("expression did not parse")
+        .evaluate(&ctx)
+}
+
+#[cfg(test)]
+fn strs(items: &[&str]) -> JsonValue {
+    JsonValue::Array(
+        items
+            .iter()
+            .map(|s| JsonValue::Str(s.to_string()))
+            .collect(),
+    )
+}
+
+#[test]
+fn expr_arithmetic_precedence() {
+    assert_eq!(Ok(JsonValue::Num(7.)), eval_with("1 + 2 * 3", &[]));
+    assert_eq!(Ok(JsonValue::Num(9.)), eval_with("(1 + 2) * 3", &[]));
+    assert_eq!(Ok(JsonValue::Num(-1.)), eval_with("-3 % 2", &[]));
+    assert_eq!(
+        Ok(JsonValue::Boolean(true)),
+        eval_with("1 < 2 && !false || 1 == 2", &[])
+    );
+    assert_eq!(
+        Ok(JsonValue::Str("b".to_string())),
+        eval_with("1 > 2 ? \"a\" : \"b\"", &[])
+    );
+}
+
+#[test]
+fn expr_template() {
+    let vars = [("name", JsonValue::Str("world".to_string()))];
+    assert_eq!(
+        Ok(JsonValue::Str("hello world!\n".to_string())),
+        eval_with("\"hello ${name}!\\n\"", &vars)
+    );
+    assert_eq!(Ok(JsonValue::Num(3.)), eval_with("\"${1 + 2}\"", &[]));
+    assert_eq!(
+        Ok(JsonValue::Str("${name}".to_string())),
+        eval_with("\"$${name}\"", &vars)
+    );
+}
+
+#[test]
+fn expr_for_tuple() {
+    let list = strs(&["a", "", "b"]);
+    let test = "[for x in var.list : upper(x) if x != \"\"]";
+    let mut var = HashMap::new();
+    var.insert("list".to_string(), list);
+    assert_eq!(
+        Ok(strs(&["A", "B"])),
+        eval_with(test, &[("var", JsonValue::Object(var))])
+    );
+}
+
+#[test]
+fn expr_for_tuple_index() {
+    let test = "[for i, x in list : \"${i}:${x}\"]";
+    assert_eq!(
+        Ok(strs(&["0:a", "1:b"])),
+        eval_with(test, &[("list", strs(&["a", "b"]))])
+    );
+}
+
+#[test]
+fn expr_for_object_grouping() {
+    let mut map = HashMap::new();
+    map.insert("a".to_string(), JsonValue::Str("x".to_string()));
+    map.insert("b".to_string(), JsonValue::Str("y".to_string()));
+    map.insert("c".to_string(), JsonValue::Str("x".to_string()));
+    let vars = [("map", JsonValue::Object(map))];
+
+    let mut expected = HashMap::new();
+    expected.insert("x".to_string(), strs(&["a", "c"]));
+    expected.insert("y".to_string(), strs(&["b"]));
+    assert_eq!(
+        Ok(JsonValue::Object(expected)),
+        eval_with("{for k, v in map : v => k...}", &vars)
+    );
+    assert_eq!(
+        Err(EvalError::DuplicateKey("x".to_string())),
+        eval_with("{for k, v in map : v => k}", &vars)
+    );
+}
+
+#[cfg(test)]
+fn instances() -> JsonValue {
+    let mut items = Vec::new();
+    for (id, ip) in &[("i-1", "10.0.0.1"), ("i-2", "10.0.0.2")] {
+        let mut net = HashMap::new();
+        net.insert("ip".to_string(), JsonValue::Str(ip.to_string()));
+        let mut h = HashMap::new();
+        h.insert("id".to_string(), JsonValue::Str(id.to_string()));
+        h.insert(
+            "network".to_string(),
+            JsonValue::Array(vec![JsonValue::Object(net)]),
+        );
+        items.push(JsonValue::Object(h));
+    }
+    JsonValue::Array(items)
+}
+
+#[test]
+fn expr_attr_splat() {
+    let vars = [("instances", instances())];
+    assert_eq!(
+        Ok(strs(&["i-1", "i-2"])),
+        eval_with("instances.*.id", &vars)
+    );
+    // the index applies to the resulting tuple, not to each element
+    assert_eq!(
+        Ok(JsonValue::Str("i-2".to_string())),
+        eval_with("instances.*.id[1]", &vars)
+    );
+}
+
+#[test]
+fn expr_full_splat() {
+    let vars = [("instances", instances())];
+    assert_eq!(
+        Ok(strs(&["10.0.0.1", "10.0.0.2"])),
+        eval_with("instances[*].network[0].ip", &vars)
+    );
+    assert_eq!(
+        Ok(JsonValue::Array(Vec::new())),
+        eval_with("null[*].id", &[])
+    );
+}
+
+#[test]
+fn expr_splat_single_value() {
+    let mut h = HashMap::new();
+    h.insert("id".to_string(), JsonValue::Str("i-1".to_string()));
+    assert_eq!(
+        Ok(strs(&["i-1"])),
+        eval_with("obj[*].id", &[("obj", JsonValue::Object(h))])
+    );
+}
+
+#[test]
+fn expr_function_expansion() {
+    let vars = [("parts", strs(&["-", "b"]))];
+    assert_eq!(
+        Ok(JsonValue::Str("a-b".to_string())),
+        eval_with("join(\"\", concat([\"a\"], [parts[0]], [parts[1]]))", &vars)
+    );
+    let nested = [(
+        "lists",
+        JsonValue::Array(vec![strs(&["x"]), strs(&["-", "b"])]),
+    )];
+    assert_eq!(
+        Ok(strs(&["x", "-", "b"])),
+        eval_with("concat(lists...)", &nested)
+    );
+}
+
+#[test]
+fn expr_object_cons() {
+    let test = "{
+      name = \"web\" # comment
+      \"port\": 80,
+      (key) = true
+    }";
+    let mut expected = HashMap::new();
+    expected.insert("name".to_string(), JsonValue::Str("web".to_string()));
+    expected.insert("port".to_string(), JsonValue::Num(80.));
+    expected.insert("dyn".to_string(), JsonValue::Boolean(true));
+    assert_eq!(
+        Ok(JsonValue::Object(expected)),
+        eval_with(test, &[("key", JsonValue::Str("dyn".to_string()))])
+    );
+}
+
+#[test]
+fn expr_errors() {
+    assert!(parse_expr("1 +").is_err());
+    assert!(parse_expr("[for x in y]").is_err());
+    assert_eq!(
+        Err(EvalError::UnknownVariable("nope".to_string())),
+        eval_with("nope.attr", &[])
+    );
+    assert_eq!(
+        Err(EvalError::TypeMismatch("number", "bool")),
+        eval_with("1 + true", &[])
+    );
+}
+
+#[test]
+fn expr_references() {
+    let refs = |source: &str| -> Vec<String> {
+        parse_expr(source)
+            .unwrap()
+            .references()
+            .iter()
+            .map(|r| r.join("."))
+            .collect()
+    };
+    assert_eq!(
+        vec!["aws_instance.web.id", "var.i"],
+        refs("aws_instance.web[var.i].id == null ? 0 : 1")
+    );
+    assert_eq!(
+        vec!["module.vpc.subnets", "var.prefix"],
+        refs("{ for s in module.vpc.subnets : s.id => \"${var.prefix}-${s.name}\" if s.public }")
+    );
+    assert_eq!(vec!["local.tags"], refs("merge(local.tags, { Name = 1 })"));
+    assert_eq!(vec!["aws_instance.web"], refs("aws_instance.web[*].id"));
+    assert_eq!(vec!["count.index"], refs("(count.index + 1) * 2"));
+}
@@ -0,0 +1,379 @@
+//! Dependencies between the objects of a Terraform configuration.
+//!
+//! Each variable, local value, resource, data source, module call and output
+//! is a node, with an edge to every node it refers to in its interpolations
+//! or in `depends_on`. References to anything not declared, such as
+//! `count.index` or a resource from another module, are left out.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::expr::{parse_expr, Reference};
+use crate::template::{self, parse_template};
+use crate::terraform::Configuration;
+use crate::types::JsonValue;
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Node {
+    Variable(String),
+    Local(String),
+    /// A resource or data source, by address.
+    Resource(String),
+    Module(String),
+    Output(String),
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Node::Variable(ref name) => write!(f, "var.{}", name),
+            Node::Local(ref name) => write!(f, "local.{}", name),
+            Node::Resource(ref address) => write!(f, "{}", address),
+            Node::Module(ref name) => write!(f, "module.{}", name),
+            Node::Output(ref name) => write!(f, "output.{}", name),
+        }
+    }
+}
+
+/// The dependency graph of a configuration.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Graph {
+    // every node, with the nodes it depends on
+    edges: BTreeMap<Node, BTreeSet<Node>>,
+}
+
+impl Graph {
+    pub fn new(config: &Configuration) -> Graph {
+        let mut graph = Graph::default();
+        let mut values: Vec<(Node, Vec<&JsonValue>, &[String])> = Vec::new();
+        for name in config.variables.keys() {
+            values.push((Node::Variable(name.clone()), Vec::new(), &[]));
+        }
+        for (name, value) in &config.locals {
+            values.push((Node::Local(name.clone()), vec![value], &[]));
+        }
+        for (address, resource) in &config.resources {
+            let mut v: Vec<&JsonValue> = resource.config.values().collect();
+            v.extend(resource.count.iter().chain(resource.for_each.iter()));
+            values.push((Node::Resource(address.clone()), v, &resource.depends_on));
+        }
+        for (name, module) in &config.modules {
+            let mut v: Vec<&JsonValue> = module.inputs.values().collect();
+            v.extend(module.count.iter().chain(module.for_each.iter()));
+            values.push((Node::Module(name.clone()), v, &module.depends_on));
+        }
+        for (name, output) in &config.outputs {
+            let node = Node::Output(name.clone());
+            values.push((node, vec![&output.value], &output.depends_on));
+        }
+        for (node, _, _) in &values {
+            graph.edges.insert(node.clone(), BTreeSet::new());
+        }
+
+        for (node, values, depends_on) in values {
+            let mut references = Vec::new();
+            for value in values {
+                value_references(value, &mut references);
+            }
+            for dependency in depends_on {
+                if let Ok(e) = parse_expr(dependency) {
+                    references.extend(e.references());
+                }
+            }
+            let dependencies: BTreeSet<Node> =
+                references.iter().filter_map(|r| graph.resolve(r)).collect();
+            graph.edges.insert(node, dependencies);
+        }
+        graph
+    }
+
+    // the declared node a reference is to
+    fn resolve(&self, reference: &Reference) -> Option<Node> {
+        let part = |i: usize| reference.get(i).cloned();
+        let node = match reference[0].as_str() {
+            "var" => Node::Variable(part(1)?),
+            "local" => Node::Local(part(1)?),
+            "module" => Node::Module(part(1)?),
+            "data" => Node::Resource(format!("data.{}.{}", part(1)?, part(2)?)),
+            resource_type => Node::Resource(format!("{}.{}", resource_type, part(1)?)),
+        };
+        if self.edges.contains_key(&node) {
+            Some(node)
+        } else {
+            None
+        }
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.edges.keys()
+    }
+
+    /// The nodes `node` refers to directly.
+    pub fn dependencies(&self, node: &Node) -> impl Iterator<Item = &Node> {
+        self.edges.get(node).into_iter().flatten()
+    }
+
+    /// The nodes that refer to `node` directly.
+    pub fn dependents(&self, node: &Node) -> Vec<&Node> {
+        self.edges
+            .iter()
+            .filter(|(_, deps)| deps.contains(node))
+            .map(|(n, _)| n)
+            .collect()
+    }
+
+    /// Every node that depends on `node`, however indirectly, such as all
+    /// that a change to a variable may affect.
+    pub fn transitive_dependents(&self, node: &Node) -> BTreeSet<&Node> {
+        let mut found = BTreeSet::new();
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop() {
+            for dependent in self.dependents(node) {
+                if found.insert(dependent) {
+                    pending.push(dependent);
+                }
+            }
+        }
+        found
+    }
+
+    /// The groups of nodes that depend on each other, each sorted.
+    pub fn cycles(&self) -> Vec<Vec<Node>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            index: BTreeMap::new(),
+            low: BTreeMap::new(),
+            stack: Vec::new(),
+            cycles: Vec::new(),
+        };
+        for node in self.edges.keys() {
+            if !tarjan.index.contains_key(node) {
+                tarjan.visit(node);
+            }
+        }
+        let mut cycles = tarjan.cycles;
+        for cycle in &mut cycles {
+            cycle.sort();
+        }
+        cycles.sort();
+        cycles
+    }
+
+    /// The nodes with each one after everything it depends on, or the
+    /// cycles that make that impossible.
+    pub fn topological_order(&self) -> Result<Vec<&Node>, Vec<Vec<Node>>> {
+        let mut remaining: BTreeMap<&Node, usize> = self
+            .edges
+            .iter()
+            .map(|(node, deps)| (node, deps.len()))
+            .collect();
+        let mut ready: BTreeSet<&Node> = remaining
+            .iter()
+            .filter(|&(_, &n)| n == 0)
+            .map(|(&node, _)| node)
+            .collect();
+        let mut order = Vec::new();
+        while let Some(node) = ready.pop_first() {
+            remaining.remove(node);
+            order.push(node);
+            for dependent in self.dependents(node) {
+                if let Some(n) = remaining.get_mut(dependent) {
+                    *n -= 1;
+                    if *n == 0 {
+                        ready.insert(dependent);
+                    }
+                }
+            }
+        }
+        if remaining.is_empty() {
+            Ok(order)
+        } else {
+            Err(self.cycles())
+        }
+    }
+
+    /// The graph in Graphviz DOT, with edges from each node to those it
+    /// depends on.
+    pub fn to_dot(&self) -> String {
+        let quote = |node: &Node| format!("\"{}\"", node.to_string().replace('"', "\\\""));
+        let mut out = String::from("digraph {\n");
+        for node in self.edges.keys() {
+            out.push_str(&format!("  {};\n", quote(node)));
+        }
+        for (node, deps) in &self.edges {
+            for dep in deps {
+                out.push_str(&format!("  {} -> {};\n", quote(node), quote(dep)));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+// the references in the templates among a value's strings
+fn value_references(value: &JsonValue, out: &mut Vec<Reference>) {
+    match *value {
+        JsonValue::Str(ref s) if s.contains("${") || s.contains("%{") => {
+            if let Ok(parts) = parse_template(s) {
+                template::collect_references(&parts, &mut Vec::new(), out);
+            }
+        }
+        JsonValue::Array(ref items) => {
+            for item in items {
+                value_references(item, out);
+            }
+        }
+        JsonValue::Object(ref obj) => {
+            for item in obj.values() {
+                value_references(item, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+struct Tarjan<'g> {
+    graph: &'g Graph,
+    index: BTreeMap<&'g Node, usize>,
+    low: BTreeMap<&'g Node, usize>,
+    stack: Vec<&'g Node>,
+    cycles: Vec<Vec<Node>>,
+}
+
+impl<'g> Tarjan<'g> {
+    fn visit(&mut self, node: &'g Node) {
+        let index = self.index.len();
+        self.index.insert(node, index);
+        self.low.insert(node, index);
+        self.stack.push(node);
+        for dep in self.graph.dependencies(node) {
+            if !self.index.contains_key(dep) {
+                self.visit(dep);
+                let low = self.low[node].min(self.low[dep]);
+                self.low.insert(node, low);
+            } else if self.stack.contains(&dep) {
+                let low = self.low[node].min(self.index[dep]);
+                self.low.insert(node, low);
+            }
+        }
+        if self.low[node] != index {
+            return;
+        }
+        let mut component = Vec::new();
+        while let Some(member) = self.stack.pop() {
+            component.push(member.clone());
+            if member == node {
+                break;
+            }
+        }
+        let self_loop = self.graph.dependencies(node).any(|dep| dep == node);
+        if component.len() > 1 || self_loop {
+            self.cycles.push(component);
+        }
+    }
+}
+
+#[cfg(test)]
+fn config(source: &str) -> Configuration {
+    let parsed = crate::hcl2::parse_hcl2(source).unwrap();
+    Configuration::from_files(&[parsed]).unwrap()
+}
+
+#[test]
+fn graph_dependencies() {
+    let graph = Graph::new(&config(
+        r#"
+variable "region" {}
+variable "unused" {}
+
+locals {
+  name = "web-${var.region}"
+}
+
+data "aws_ami" "ubuntu" {
+  owners = [var.region]
+}
+
+resource "aws_instance" "web" {
+  count = 2
+  ami   = data.aws_ami.ubuntu.id
+  tags  = { Name = "${local.name}-${count.index}" }
+}
+
+resource "aws_eip" "ip" {
+  instance   = aws_instance.web[0].id
+  depends_on = [module.vpc]
+}
+
+module "vpc" {
+  source = "./vpc"
+  region = var.region
+}
+
+output "ip" {
+  value = aws_eip.ip.public_ip
+}
+"#,
+    ));
+    let names =
+        |nodes: Vec<&Node>| -> Vec<String> { nodes.iter().map(|n| n.to_string()).collect() };
+
+    assert_eq!(
+        vec!["local.name", "data.aws_ami.ubuntu"],
+        names(
+            graph
+                .dependencies(&Node::Resource("aws_instance.web".to_string()))
+                .collect()
+        )
+    );
+    let region = Node::Variable("region".to_string());
+    assert_eq!(
+        vec!["local.name", "data.aws_ami.ubuntu", "module.vpc"],
+        names(graph.dependents(&region))
+    );
+    assert_eq!(
+        vec![
+            "local.name",
+            "aws_eip.ip",
+            "aws_instance.web",
+            "data.aws_ami.ubuntu",
+            "module.vpc",
+            "output.ip",
+        ],
+        names(graph.transitive_dependents(&region).into_iter().collect())
+    );
+
+    let order = names(graph.topological_order().unwrap());
+    let position = |name: &str| order.iter().position(|n| n == name).unwrap();
+    assert_eq!(8, order.len());
+    assert!(position("var.region") < position("local.name"));
+    assert!(position("local.name") < position("aws_instance.web"));
+    assert!(position("module.vpc") < position("aws_eip.ip"));
+    assert!(position("aws_eip.ip") < position("output.ip"));
+    assert!(graph.cycles().is_empty());
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph {\n"));
+    assert!(dot.contains("  \"output.ip\" -> \"aws_eip.ip\";\n"));
+    assert!(dot.contains("  \"var.unused\";\n"));
+}
+
+#[test]
+fn graph_cycles() {
+    let graph = Graph::new(&config(
+        r#"
+locals {
+  a = local.b
+  b = "${local.a}"
+  c = local.c
+  d = local.a
+}
+"#,
+    ));
+    let cycles = vec![
+        vec![Node::Local("a".to_string()), Node::Local("b".to_string())],
+        vec![Node::Local("c".to_string())],
+    ];
+    assert_eq!(cycles, graph.cycles());
+    assert_eq!(Err(cycles), graph.topological_order());
+}
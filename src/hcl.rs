@@ -1,26 +1,75 @@
 use std::collections::HashMap;
+use std::io::Read;
 use std::str;
-use std::string::String;
+use std::string::{FromUtf8Error, String};
 
-use nom::IResult::Done;
-use nom::{alphanumeric, eol, multispace, not_line_ending};
+use nom::IResult::{Done, Error};
+use nom::{alphanumeric, multispace, ErrorKind, IResult};
 
-use crate::common::{boolean, number};
-use crate::types::{JsonValue, ParseError};
+use crate::common::{
+    boolean, check_size, check_text, exceeds, fail, here, line_break, nested, number, offset,
+    options, run_with,
+};
+use crate::expr::parse_expr;
+use crate::parser::{build_body, wrap_block, Interpolations, Item, Parser, ParserOptions};
+use crate::types::{JsonValue, Limit, ParseError, ParseErrorKind, ReadError};
 
 pub fn parse_hcl(config: &str) -> Result<JsonValue, ParseError> {
-    match hcl(&config.as_bytes()[..]) {
-        Done(_, c) => Ok(c),
-        _ => Err(0),
+    Parser::new().parse(config)
+}
+
+/// Parses raw bytes, see `Parser::parse_bytes`.
+pub fn parse_hcl_bytes(config: &[u8]) -> Result<JsonValue, ParseError> {
+    Parser::new().parse_bytes(config)
+}
+
+/// Parses everything `reader` produces, see `Parser::parse_reader`.
+pub fn parse_hcl_reader<R: Read>(reader: R) -> Result<JsonValue, ReadError> {
+    Parser::new().parse_reader(reader)
+}
+
+pub(crate) fn parse_with(
+    config: &[u8],
+    options: &ParserOptions,
+) -> Result<(JsonValue, Vec<ParseError>), ParseError> {
+    check_size(config, options)?;
+    check_text(config)?;
+    run_with(config, options, hcl)
+}
+
+/// Parses the top level of a file into its items, each with the offset it
+/// starts at, leaving them to the caller to combine.
+pub(crate) fn parse_items(
+    config: &str,
+    options: &ParserOptions,
+) -> Result<Vec<(usize, Item)>, ParseError> {
+    check_text(config.as_bytes())?;
+    run_with(config.as_bytes(), options, hcl_items).map(|(items, _)| items)
+}
+
+fn hcl_items(input: &[u8]) -> IResult<&[u8], Vec<(usize, Item)>> {
+    match terminated!(input, hcl_key_values, opt!(blanks)) {
+        Done(rest, items) => Done(
+            rest,
+            items
+                .into_iter()
+                .map(|(at, item)| (offset(at), item))
+                .collect(),
+        ),
+        Error(e) => Error(e),
+        IResult::Incomplete(n) => IResult::Incomplete(n),
     }
 }
 
-named!(hcl<JsonValue>, map!(hcl_top, |h| JsonValue::Object(h)));
+named!(
+    hcl<JsonValue>,
+    map!(terminated!(hcl_top, opt!(blanks)), JsonValue::Object)
+);
 
-named!(end_of_line, alt!(eof!() | eol));
+named!(end_of_line, alt_complete!(eof!() | line_break));
 
-fn to_s(i: Vec<u8>) -> String {
-    String::from_utf8_lossy(&i).into_owned()
+fn to_s(i: Vec<u8>) -> Result<String, FromUtf8Error> {
+    String::from_utf8(i)
 }
 fn slen(i: String) -> usize {
     i.len()
@@ -32,32 +81,83 @@ fn take_limited(min: usize, max: usize) -> usize {
     if max < min {
         return max;
     }
-    return min;
+    min
 }
 
-named!(
-    hcl_escaped_string<String>,
-    map!(
-        escaped_transform!(
-            is_not!("\\\"\n"),
-            '\\',
-            alt!(
-                tag!("\\")       => { |_| &b"\\"[..] } |
-                tag!("\"")       => { |_| &b"\""[..] } |
-                tag!("n")        => { |_| &b"\n"[..] }
-            )
-        ),
-        to_s
+macro_rules! escaped_string (
+    ($i:expr, $stop:expr) => (
+        map_res!(
+            $i,
+            escaped_transform!(
+                is_not!($stop),
+                '\\',
+                alt!(
+                    tag!("\\")       => { |_| &b"\\"[..] } |
+                    tag!("\"")       => { |_| &b"\""[..] } |
+                    tag!("n")        => { |_| &b"\n"[..] }
+                )
+            ),
+            to_s
+        )
     )
 );
 
-named!(
-    hcl_template_string<String>,
-    map!(
-        do_parse!(tag!("${") >> s: take_until_and_consume!("}") >> (s)),
-        |s| format!("${{{}}}", String::from_utf8_lossy(s))
-    )
-);
+named!(hcl_escaped_line<String>, escaped_string!("\\\"\r\n"));
+
+named!(hcl_escaped_lines<String>, escaped_string!("\\\""));
+
+fn hcl_escaped_string(input: &[u8]) -> IResult<&[u8], String> {
+    if options(|o| o.multiline_strings) {
+        hcl_escaped_lines(input)
+    } else {
+        hcl_escaped_line(input)
+    }
+}
+
+// `${...}` and `%{...}` sequences are kept verbatim for the template engine
+fn hcl_template_string(input: &[u8]) -> IResult<&[u8], String> {
+    if !input.starts_with(b"${") && !input.starts_with(b"%{") {
+        return Error(error_position!(ErrorKind::Custom(0), input));
+    }
+    // quotes are not tracked, HIL permits unbalanced ones inside interpolations
+    let mut depth = 0;
+    for (i, &c) in input.iter().enumerate() {
+        if c == b'{' {
+            depth += 1;
+        } else if c == b'}' {
+            depth -= 1;
+            if depth == 0 {
+                let text = match str::from_utf8(&input[..=i]) {
+                    Ok(text) => text,
+                    Err(_) => return Error(error_position!(ErrorKind::Custom(0), input)),
+                };
+                if !valid_interpolation(text) {
+                    return fail(input, ParseErrorKind::Interpolation);
+                }
+                return Done(&input[i + 1..], text.to_string());
+            }
+        }
+    }
+    Error(error_position!(ErrorKind::Custom(0), input))
+}
+
+// directives are fragments of a template, so only `${...}` is checked
+fn valid_interpolation(text: &str) -> bool {
+    if !text.starts_with("${") || options(|o| o.interpolations == Interpolations::Raw) {
+        return true;
+    }
+    let inner = &text[2..text.len() - 1];
+    let inner = inner.strip_prefix('~').unwrap_or(inner);
+    let inner = inner.strip_suffix('~').unwrap_or(inner);
+    parse_expr(inner).is_ok()
+}
+
+fn until_template(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    match input.windows(2).position(|w| w == b"${" || w == b"%{") {
+        Some(n) => Done(&input[n..], &input[..n]),
+        None => Error(error_position!(ErrorKind::Custom(0), input)),
+    }
+}
 
 named!(
     hcl_quoted_escaped_string<String>,
@@ -70,7 +170,7 @@ named!(
                         | flat_map!(
                             do_parse!(
                                 max: map!(peek!(hcl_escaped_string), slen)
-                                    >> min: map!(peek!(take_until!("${")), ulen)
+                                    >> min: map!(peek!(until_template), ulen)
                                     >> buf: take!(take_limited(min, max))
                                     >> (buf)
                             ),
@@ -90,53 +190,110 @@ named!(
     )
 );
 
-named!(
-    hcl_multiline_string<String>,
-    map!(
-        do_parse!(
-            delimiter: tag!("<<")
-                >> indent: opt!(tag!("-"))
-                >> delimiter: terminated!(alphanumeric, eol)
-                >> delimiter_str: expr_res!(str::from_utf8(delimiter))
-                >> s: take_until!(delimiter_str)
-                >> tag!(delimiter_str)
-                >> end_of_line
-                >> (indent, s)
-        ),
-        |(indent, s)| {
-            let body = String::from_utf8_lossy(s);
-            let lines: Vec<&str> = body.split("\n").collect();
-            let mut out: Vec<&str> = Vec::new();
-            let count = lines.len();
-
-            let mut min_indent = 80;
-            if let Some(_) = indent {
-                for (i, line) in lines.clone().into_iter().enumerate() {
-                    let indent_num = line.len() - line.trim_start().len();
-                    if indent_num < min_indent {
-                        min_indent = indent_num;
-                    }
-                    if i != count - 1 {
-                        if min_indent < indent_num {
-                            // NOTE this behavior is odd, and will change based on the hcl2 specs
-                            min_indent = 0;
-                        }
-                    }
+pub(crate) fn quoted_string(input: &[u8]) -> IResult<&[u8], String> {
+    hcl_quoted_escaped_string(input)
+}
+
+pub(crate) fn quoted_key(input: &[u8]) -> IResult<&[u8], String> {
+    hcl_quoted_escaped_key(input)
+}
+
+/// Parses a heredoc. `<<` keeps its lines as written; `<<-` removes the
+/// smallest indentation of any line, or with `legacy_indent` the closing
+/// marker's indentation, as HCL1 does, unless a line is indented less.
+/// Tabs and spaces count alike, CRLF and CR line endings become LF, and the
+/// closing marker may be indented in either form.
+pub(crate) fn heredoc(input: &[u8], legacy_indent: bool) -> IResult<&[u8], String> {
+    if !input.starts_with(b"<") {
+        return Error(error_position!(ErrorKind::Tag, input));
+    }
+    if !input.starts_with(b"<<") {
+        return fail(input, ParseErrorKind::HeredocIntroducer);
+    }
+    let flush = input[2..].starts_with(b"-");
+    let i = &input[if flush { 3 } else { 2 }..];
+    let marker_len = match i.first() {
+        Some(c) if c.is_ascii_alphabetic() || *c == b'_' => i
+            .iter()
+            .position(|&c| !(c.is_ascii_alphanumeric() || c == b'_' || c == b'-'))
+            .unwrap_or(i.len()),
+        _ => 0,
+    };
+    let marker = &i[..marker_len];
+    let mut rest = trim_spaces(&i[marker_len..]);
+    rest = match line_break(rest) {
+        Done(r, _) if marker_len > 0 => r,
+        _ => return fail(i, ParseErrorKind::HeredocMarker),
+    };
+
+    let mut lines: Vec<&[u8]> = Vec::new();
+    loop {
+        if rest.is_empty() {
+            let marker = String::from_utf8_lossy(marker).into_owned();
+            return fail(input, ParseErrorKind::HeredocUnterminated(marker));
+        }
+        let len = rest
+            .iter()
+            .position(|&c| c == b'\n' || c == b'\r')
+            .unwrap_or(rest.len());
+        let line = &rest[..len];
+        let indent = line.len() - trim_spaces(line).len();
+        if trim_spaces_end(&line[indent..]) == marker {
+            let closing = &line[..indent];
+            let strip = if !flush {
+                0
+            } else if legacy_indent {
+                if lines.iter().all(|l| l.starts_with(closing)) {
+                    indent
+                } else {
+                    0
                 }
-            }
-            for (i, line) in lines.into_iter().enumerate() {
-                if i != count - 1 {
-                    if let Some(_) = indent {
-                        out.push(&line[min_indent..])
-                    } else {
-                        out.push(line)
-                    }
+            } else {
+                lines
+                    .iter()
+                    .filter(|l| !trim_spaces(l).is_empty())
+                    .map(|l| l.len() - trim_spaces(l).len())
+                    .min()
+                    .unwrap_or(0)
+            };
+            let mut out = String::new();
+            for l in lines {
+                let l = &l[strip.min(l.len() - trim_spaces(l).len())..];
+                match str::from_utf8(l) {
+                    Ok(l) => out.push_str(l),
+                    Err(_) => return Error(error_position!(ErrorKind::Custom(0), input)),
                 }
+                out.push('\n');
             }
-            out.join("\n") + "\n"
+            return Done(&rest[line.len()..], out);
         }
-    )
-);
+        lines.push(line);
+        rest = match line_break(&rest[len..]) {
+            Done(r, _) => r,
+            _ => &rest[len..],
+        };
+    }
+}
+
+fn trim_spaces(i: &[u8]) -> &[u8] {
+    let n = i
+        .iter()
+        .position(|&c| c != b' ' && c != b'\t')
+        .unwrap_or(i.len());
+    &i[n..]
+}
+
+fn trim_spaces_end(i: &[u8]) -> &[u8] {
+    let n = i
+        .iter()
+        .rposition(|&c| c != b' ' && c != b'\t')
+        .map_or(0, |n| n + 1);
+    &i[..n]
+}
+
+fn hcl_multiline_string(input: &[u8]) -> IResult<&[u8], String> {
+    heredoc(input, true)
+}
 
 // close enough...
 named!(
@@ -144,9 +301,34 @@ named!(
     alt!(tag!("_") | tag!("-") | tag!(".") | alphanumeric)
 );
 
+// identifiers, for strict mode
+fn hcl_identifier(input: &[u8]) -> IResult<&[u8], String> {
+    match input.first() {
+        Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {
+            let len = input
+                .iter()
+                .position(|&c| !(c.is_ascii_alphanumeric() || c == b'_'))
+                .unwrap_or(input.len());
+            Done(
+                &input[len..],
+                String::from_utf8_lossy(&input[..len]).into_owned(),
+            )
+        }
+        _ => Error(error_position!(ErrorKind::Custom(0), input)),
+    }
+}
+
+fn hcl_bare_key(input: &[u8]) -> IResult<&[u8], String> {
+    if options(|o| o.strict) {
+        hcl_identifier(input)
+    } else {
+        hcl_unquoted_key(input)
+    }
+}
+
 named!(
     hcl_unquoted_key<String>,
-    map!(
+    map_res!(
         fold_many0!(identifier_char, Vec::new(), |mut acc: Vec<_>, item| {
             acc.extend(item);
             acc
@@ -167,10 +349,7 @@ named!(
     )
 );
 
-named!(
-    hcl_key<String>,
-    alt!(hcl_quoted_escaped_key | hcl_unquoted_key)
-);
+named!(hcl_key<String>, alt!(hcl_quoted_escaped_key | hcl_bare_key));
 
 named!(space, eat_separator!(&b" \t"[..]));
 
@@ -183,17 +362,21 @@ macro_rules! sp (
 );
 
 named!(
-    hcl_key_value<(String, JsonValue)>,
+    hcl_key_value<Item>,
     sp!(alt_complete!(
-        separated_pair!(hcl_key, tag!("="), hcl_value_nested_hash)
-            | separated_pair!(hcl_key, tag!("="), hcl_value)
-            | pair!(hcl_key, hcl_value_nested_hash)
+        separated_pair!(hcl_key, tag!("="), hcl_block) => {
+            |(k, (labels, body))| Item::Attribute(k, JsonValue::Array(vec![wrap_block(labels, body)]))
+        } |
+        // a hash was already tried as a block, parsing it again would double
+        // the work at every level of nesting
+        separated_pair!(hcl_key, tag!("="), hcl_plain_value) => { |(k, v)| Item::Attribute(k, v) } |
+        pair!(hcl_key, hcl_block) => { |(k, (labels, body))| Item::Block(k, labels, body) }
     ))
 );
 
 named!(
     comment_one_line,
-    do_parse!(alt!(tag!("//") | tag!("#")) >> opt!(not_line_ending) >> end_of_line >> (&b""[..]))
+    do_parse!(alt!(tag!("//") | tag!("#")) >> opt!(is_not!("\r\n")) >> end_of_line >> (&b""[..]))
 );
 
 named!(
@@ -201,6 +384,11 @@ named!(
     do_parse!(tag!("/*") >> take_until_and_consume!("*/") >> (&b""[..]))
 );
 
+/// A comment of either form, for the other front ends.
+pub(crate) fn comment(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    alt!(input, comment_one_line | comment_block)
+}
+
 named!(
     blanks,
     do_parse!(
@@ -211,112 +399,97 @@ named!(
 );
 
 named!(
-    hcl_key_values<Vec<(String, JsonValue)>>,
+    hcl_key_values<Vec<(usize, Item)>>,
     many0!(complete!(do_parse!(
-        opt!(blanks) >> out: hcl_key_value >> opt!(blanks) >> (out)
+        opt!(blanks) >> at: here >> out: hcl_key_value >> opt!(blanks) >> ((at, out))
     )))
 );
 
 named!(
     hcl_hash<HashMap<String, JsonValue>>,
-    do_parse!(opt!(blanks) >> tag!("{") >> out: hcl_top >> tag!("}") >> opt!(blanks) >> (out))
+    do_parse!(
+        opt!(blanks)
+            >> peek!(tag!("{"))
+            >> out: call!(nested, hcl_hash_body)
+            >> opt!(blanks)
+            >> (out)
+    )
+);
+
+named!(
+    hcl_hash_body<HashMap<String, JsonValue>>,
+    delimited!(tag!("{"), hcl_top, tag!("}"))
 );
 
 named!(
     hcl_top<HashMap<String, JsonValue>>,
-    map!(hcl_key_values, |tuple_vec| {
-        let mut top: HashMap<String, JsonValue> = HashMap::new();
-        for (k, v) in tuple_vec.into_iter().rev() {
-            if top.contains_key(&k) {
-                if let JsonValue::Array(ref v_a) = v {
-                    if let Some(current) = top.remove(&k) {
-                        if let JsonValue::Array(ref a) = current {
-                            let mut copy = v_a.to_vec();
-                            copy.extend(a.to_vec());
-                            top.insert(k, JsonValue::Array(copy));
-                            continue;
-                        }
-                    }
-                }
-            }
-            top.insert(k, v);
-        }
-        top
-    })
+    map_opt!(hcl_key_values, build_body)
 );
 
 // a bit odd if you ask me
 named!(
-    hcl_value_nested_hash<JsonValue>,
-    map!(
-        // NOTE hcl allows arbitrarily deep nesting
-        pair!(many0!(sp!(hcl_quoted_escaped_key)), hcl_value_hash),
-        |(tuple_vec, value)| {
-            let mut cur = value;
-            for parent in tuple_vec.into_iter().rev() {
-                let mut inner: Vec<JsonValue> = Vec::new();
-                inner.push(cur);
-                let mut h: HashMap<String, JsonValue> = HashMap::new();
-                h.insert(parent.to_string(), JsonValue::Array(inner));
-                cur = JsonValue::Object(h);
-            }
-            let mut outer: Vec<JsonValue> = Vec::new();
-            outer.push(cur);
-            JsonValue::Array(outer)
-        }
-    )
+    hcl_block<(Vec<String>, HashMap<String, JsonValue>)>,
+    // NOTE hcl allows arbitrarily deep nesting
+    pair!(many0!(sp!(hcl_quoted_escaped_key)), hcl_hash)
 );
 
+named!(hcl_value_hash<JsonValue>, map!(hcl_hash, JsonValue::Object));
+
 named!(
-    hcl_value_hash<JsonValue>,
-    map!(hcl_hash, |h| JsonValue::Object(h))
+    hcl_array<Vec<JsonValue>>,
+    preceded!(peek!(tag!("[")), call!(nested, hcl_array_body))
 );
 
 named!(
-    hcl_array<Vec<JsonValue>>,
+    hcl_array_body<Vec<JsonValue>>,
+    // blanks take in the commas, so each item is parsed once however deep
     delimited!(
         tag!("["),
-        do_parse!(
-            init: fold_many0!(
-                do_parse!(
-                    opt!(blanks)
-                        >> out: hcl_value
-                        >> opt!(blanks)
-                        >> tag!(",")
-                        >> opt!(blanks)
-                        >> (out)
-                ),
-                Vec::new(),
-                |mut acc: Vec<_>, item| {
-                    acc.push(item);
-                    acc
-                }
-            ) >> ret: fold_many0!(
-                do_parse!(opt!(blanks) >> out: hcl_value >> opt!(blanks) >> (out)),
-                init,
-                |mut acc: Vec<_>, item| {
-                    acc.push(item);
-                    acc
-                }
-            ) >> (ret)
-        ),
-        tag!("]")
+        many0!(complete!(do_parse!(
+            opt!(blanks) >> out: hcl_value >> opt!(blanks) >> (out)
+        ))),
+        preceded!(opt!(blanks), tag!("]"))
     )
 );
 
+fn hcl_string(input: &[u8]) -> IResult<&[u8], String> {
+    match alt!(input, hcl_quoted_escaped_string | hcl_multiline_string) {
+        Done(_, ref s) if exceeds(Limit::StringLength, s.len()) => {
+            fail(input, ParseErrorKind::LimitExceeded(Limit::StringLength))
+        }
+        result => result,
+    }
+}
+
+fn hcl_list(input: &[u8]) -> IResult<&[u8], Vec<JsonValue>> {
+    match hcl_array(input) {
+        Done(_, ref items) if exceeds(Limit::ArrayLength, items.len()) => {
+            fail(input, ParseErrorKind::LimitExceeded(Limit::ArrayLength))
+        }
+        result => result,
+    }
+}
+
 named!(
     hcl_value<JsonValue>,
     alt!(
-        hcl_hash                    => { |h|   JsonValue::Object(h)            } |
-        hcl_array                   => { |v|   JsonValue::Array(v)             } |
-        hcl_quoted_escaped_string   => { |s|   JsonValue::Str(s) } |
-        hcl_multiline_string        => { |s|   JsonValue::Str(s) } |
-        number                      => { |num| JsonValue::Num(num)             } |
-        boolean                     => { |b|   JsonValue::Boolean(b)           }
+        hcl_hash        => { JsonValue::Object } |
+        hcl_plain_value
+    )
+);
+
+named!(
+    hcl_plain_value<JsonValue>,
+    alt!(
+        hcl_list                    => { JsonValue::Array   } |
+        hcl_string                  => { JsonValue::Str     } |
+        number                      => { JsonValue::Num     } |
+        boolean                     => { JsonValue::Boolean }
     )
 );
 
 #[test]
+#[allow(clippy::needless_borrowed_reference)]
 fn hcl_hex_num() {
     let test = "foo = 0x42";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -328,6 +501,108 @@ fn hcl_hex_num() {
 }
 
 #[test]
+fn hcl_signed_and_octal_num() {
+    let test = "mode = 0644\noffset = -5\nmask = 0b1010\nscale = -0.5";
+    if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
+        assert_eq!(Some(&JsonValue::Num(420.)), dict.get("mode"));
+        assert_eq!(Some(&JsonValue::Num(-5.)), dict.get("offset"));
+        assert_eq!(Some(&JsonValue::Num(10.)), dict.get("mask"));
+        assert_eq!(Some(&JsonValue::Num(-0.5)), dict.get("scale"));
+        return;
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+fn hcl_int_overflow() {
+    assert!(parse_hcl("big = 99999999999999999999").is_err());
+    assert!(parse_hcl("bad = 09").is_err());
+}
+
+#[test]
+fn hcl_heredoc_markers() {
+    let test = "policy = <<END_POLICY\nEOF\nEND_POLICYX\nEND_POLICY\nempty = <<-EOT\n  EOT\n";
+    if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
+        assert_eq!(
+            Some(&JsonValue::Str("EOF\nEND_POLICYX\n".to_string())),
+            dict.get("policy")
+        );
+        assert_eq!(Some(&JsonValue::Str(String::new())), dict.get("empty"));
+        return;
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+fn hcl_heredoc_indentation() {
+    // tabs are indentation too, and crlf line endings are normalised
+    let test = "foo = <<-EOF\r\n\t\tbar\r\n\t\t\tbaz\r\n\t\tEOF\r\nqux = <<EOF\r\n  a\r\n  EOF\r\n";
+    if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
+        assert_eq!(
+            Some(&JsonValue::Str("bar\n\tbaz\n".to_string())),
+            dict.get("foo")
+        );
+        assert_eq!(Some(&JsonValue::Str("  a\n".to_string())), dict.get("qux"));
+        return;
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+fn hcl_heredoc_errors() {
+    assert_eq!(
+        Err(ParseError::new(
+            ParseErrorKind::HeredocUnterminated("EOF".to_string()),
+            6
+        )),
+        parse_hcl("foo = <<EOF\nbar\nEOFX\n")
+    );
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::HeredocMarker, 8)),
+        parse_hcl("foo = <<1EOF\nbar\n1EOF\n")
+    );
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::HeredocMarker, 12)),
+        parse_hcl("foo { a = <<EOF bar\n}")
+    );
+}
+
+#[test]
+fn hcl_offsets_count_bom() {
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::HeredocIntroducer, 13)),
+        parse_hcl("\u{feff}a = 1\rb = <EOF\r")
+    );
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::Syntax, 12)),
+        parse_hcl("\u{feff}a = 1\r\n\r\nb = ")
+    );
+}
+
+#[test]
+fn hcl_bytes() {
+    let test = "name = \"Grüße ☃\"\n\"été\" = 1".as_bytes();
+    if let Ok(JsonValue::Object(dict)) = parse_hcl_bytes(test) {
+        assert_eq!(
+            Some(&JsonValue::Str("Grüße ☃".to_string())),
+            dict.get("name")
+        );
+        assert_eq!(Some(&JsonValue::Num(1.)), dict.get("été"));
+    } else {
+        panic!("object did not parse");
+    }
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::InvalidUtf8, 11)),
+        parse_hcl_bytes(b"a = 1\nb = \"\xc3\x28\"")
+    );
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::Binary, 4)),
+        parse_hcl_bytes(b"\x7fELF\0\x01")
+    );
+}
+
+#[test]
+#[allow(clippy::needless_borrowed_reference)]
 fn hcl_string_empty() {
     let test = "foo = \"\"";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -339,6 +614,7 @@ fn hcl_string_empty() {
 }
 
 #[test]
+#[allow(clippy::needless_borrowed_reference)]
 fn hcl_string_with_escaped_quote_test() {
     let test = "foo = \"bar\\\"foo\"";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -350,6 +626,7 @@ fn hcl_string_with_escaped_quote_test() {
 }
 
 #[test]
+#[allow(clippy::needless_borrowed_reference)]
 fn hcl_string_with_escaped_newline_test() {
     let test = "foo = \"bar\\nfoo\"";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -361,6 +638,7 @@ fn hcl_string_with_escaped_newline_test() {
 }
 
 #[test]
+#[allow(clippy::needless_borrowed_reference)]
 fn hcl_string_with_space_test() {
     let test = "foo = \"bar foo\"";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -372,6 +650,7 @@ fn hcl_string_with_space_test() {
 }
 
 #[test]
+#[allow(clippy::needless_borrowed_reference)]
 fn hcl_string_with_template_test() {
     let test = "foo = \"${bar\"foo}\"";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -383,6 +662,7 @@ fn hcl_string_with_template_test() {
 }
 
 #[test]
+#[allow(clippy::needless_borrowed_reference)]
 fn hcl_string_with_escapes_and_template_test() {
     let test = "foo = \"wow\\\"wow${bar\"foo}\"";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -394,6 +674,18 @@ fn hcl_string_with_escapes_and_template_test() {
 }
 
 #[test]
+fn hcl_string_with_directive_test() {
+    let test = "foo = \"%{ if bar == \"baz\" }${ {a = 1}[\"a\"] }%{ endif }\"";
+    if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
+        if let Some(JsonValue::Str(resp)) = dict.get("foo") {
+            return assert_eq!("%{ if bar == \"baz\" }${ {a = 1}[\"a\"] }%{ endif }", resp);
+        }
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+#[allow(clippy::needless_borrowed_reference)]
 fn hcl_string_multi_with_template() {
     let test = "foo = \"wow\"\nbar= \"${bar\"foo}\"";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -405,6 +697,7 @@ fn hcl_string_multi_with_template() {
 }
 
 #[test]
+#[allow(clippy::get_first, clippy::needless_borrowed_reference)]
 fn hcl_block_empty_key() {
     let test = "foo \"\" {\nbar = 1\n}";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -424,6 +717,7 @@ fn hcl_block_empty_key() {
 }
 
 #[test]
+#[allow(clippy::get_first, clippy::needless_borrowed_reference)]
 fn hcl_block_key() {
     let test = "potato \"salad\\\"is\" {\nnot = \"real\"\n}";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -443,6 +737,7 @@ fn hcl_block_key() {
 }
 
 #[test]
+#[allow(clippy::get_first, clippy::needless_borrowed_reference)]
 fn hcl_block_nested_key() {
     let test = "potato \"salad\" \"is\" {\nnot = \"real\"\n}";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -467,6 +762,7 @@ fn hcl_block_nested_key() {
 }
 
 #[test]
+#[allow(clippy::needless_borrowed_reference)]
 fn hcl_key_chars() {
     let test = "foo_bar = \"bar\"";
     if let Ok(JsonValue::Object(dict)) = parse_hcl(test) {
@@ -486,6 +782,7 @@ fn hcl_key_chars() {
 }
 
 #[test]
+#[allow(clippy::get_first, clippy::needless_borrowed_reference)]
 fn hcl_slice_expand() {
     let test = "service \"foo\" {
   key = \"value\"
@@ -513,3 +810,38 @@ service \"bar\" {
         }
     }
 }
+
+#[test]
+fn hcl_reader() {
+    use std::io;
+
+    struct Broken;
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("connection reset"))
+        }
+    }
+
+    assert_eq!(
+        parse_hcl("foo = \"bar\"").ok(),
+        parse_hcl_reader(&b"foo = \"bar\""[..]).ok()
+    );
+    match parse_hcl_reader(&b"foo = "[..]) {
+        Err(ReadError::Parse(e)) => assert_eq!(ParseErrorKind::Syntax, e.kind),
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    match parse_hcl_reader(Broken) {
+        Err(ReadError::Io(e)) => assert_eq!("connection reset", e.to_string()),
+        other => panic!("expected a read error, got {:?}", other),
+    }
+    // reading stops just past the limit
+    match Parser::new()
+        .max_input_size(4)
+        .parse_reader(io::repeat(b' '))
+    {
+        Err(ReadError::Parse(e)) => {
+            assert_eq!(ParseErrorKind::LimitExceeded(Limit::InputSize), e.kind)
+        }
+        other => panic!("expected a limit error, got {:?}", other),
+    }
+}
@@ -0,0 +1,310 @@
+//! HCL2 native syntax front end.
+//!
+//! Bodies are returned in the same shape `parse_hcl` produces, so consumers can
+//! handle both dialects alike. Expressions that are not plain literals are kept
+//! as `${...}` strings, the way HCL1 keeps interpolations.
+
+use std::collections::HashMap;
+
+use nom::IResult::{Done, Error};
+use nom::{ErrorKind, IResult};
+
+use crate::common::{check_size, here, nested, offset, run_with};
+use crate::expr::{blank, expression, identifier, is_ident_start, Expr, UnaryOp};
+use crate::hcl::{heredoc, quoted_string};
+use crate::parser::{build_body, DuplicateKeys, Interpolations, Item, ParserOptions};
+use crate::template::{self, TemplatePart};
+use crate::types::{JsonValue, ParseError};
+
+pub fn parse_hcl2(config: &str) -> Result<JsonValue, ParseError> {
+    parse_hcl2_with(config, &ParserOptions::default())
+}
+
+/// Parses HCL2 with the limits in `options`; the rest do not apply.
+pub(crate) fn parse_hcl2_with(
+    config: &str,
+    options: &ParserOptions,
+) -> Result<JsonValue, ParseError> {
+    check_size(config.as_bytes(), options)?;
+    run_with(config.as_bytes(), &hcl2_options(options), hcl2)
+        .map(|(body, _)| JsonValue::Object(body))
+}
+
+/// Parses the top level of a file into its items, like `hcl::parse_items`.
+pub(crate) fn parse_items(
+    config: &str,
+    options: &ParserOptions,
+) -> Result<Vec<(usize, Item)>, ParseError> {
+    check_size(config.as_bytes(), options)?;
+    run_with(config.as_bytes(), &hcl2_options(options), hcl2_items).map(|(items, _)| items)
+}
+
+fn hcl2_options(options: &ParserOptions) -> ParserOptions {
+    // hcl2 does not allow redefinitions
+    ParserOptions {
+        duplicate_keys: DuplicateKeys::Error,
+        strict: false,
+        multiline_strings: false,
+        interpolations: Interpolations::Raw,
+        ..options.clone()
+    }
+}
+
+named!(hcl2<HashMap<String, JsonValue>>, terminated!(body, blank));
+
+fn hcl2_items(input: &[u8]) -> IResult<&[u8], Vec<(usize, Item)>> {
+    match terminated!(input, items, blank) {
+        Done(rest, items) => Done(
+            rest,
+            items
+                .into_iter()
+                .map(|(at, item)| (offset(at), item))
+                .collect(),
+        ),
+        Error(e) => Error(e),
+        IResult::Incomplete(n) => IResult::Incomplete(n),
+    }
+}
+
+named!(
+    body<HashMap<String, JsonValue>>,
+    map_opt!(items, build_body)
+);
+
+named!(
+    items<Vec<(usize, Item)>>,
+    many0!(complete!(do_parse!(
+        blank >> at: here >> item: alt_complete!(attribute | block) >> ((at, item))
+    )))
+);
+
+// spaces and tabs only; newlines are significant between body items
+fn space(input: &[u8]) -> &[u8] {
+    let n = input
+        .iter()
+        .position(|&c| c != b' ' && c != b'\t')
+        .unwrap_or(input.len());
+    &input[n..]
+}
+
+fn skip_space(input: &[u8]) -> IResult<&[u8], ()> {
+    Done(space(input), ())
+}
+
+// `=` introducing an attribute, but not the start of `==`
+fn equals(input: &[u8]) -> IResult<&[u8], ()> {
+    let i = space(input);
+    match i {
+        [b'=', b'=', ..] => Error(error_position!(ErrorKind::Custom(0), input)),
+        [b'=', ..] => Done(&i[1..], ()),
+        _ => Error(error_position!(ErrorKind::Custom(0), input)),
+    }
+}
+
+// an item must be followed by a newline, a comment, the end of its body or input
+fn item_end(input: &[u8]) -> IResult<&[u8], ()> {
+    let i = space(input);
+    let ok = i.is_empty()
+        || i.starts_with(b"\n")
+        || i.starts_with(b"\r")
+        || i.starts_with(b"#")
+        || i.starts_with(b"//")
+        || i.starts_with(b"/*")
+        || i.starts_with(b"}");
+    if ok {
+        Done(i, ())
+    } else {
+        Error(error_position!(ErrorKind::Custom(0), input))
+    }
+}
+
+fn attribute_value(input: &[u8]) -> IResult<&[u8], JsonValue> {
+    let start = space(input);
+    if start.starts_with(b"<<") {
+        return match heredoc(start, false) {
+            Done(rest, s) => Done(rest, JsonValue::Str(s)),
+            _ => Error(error_position!(ErrorKind::Custom(0), input)),
+        };
+    }
+    match expression(start) {
+        Done(rest, e) => {
+            let value = match e {
+                // keep interpolations exactly as written, as hcl1 does
+                Expr::Template(_) => match quoted_string(start) {
+                    Done(r, s) if r.len() == rest.len() => JsonValue::Str(s),
+                    _ => to_json(&e),
+                },
+                _ => to_json(&e),
+            };
+            Done(rest, value)
+        }
+        _ => Error(error_position!(ErrorKind::Custom(0), input)),
+    }
+}
+
+named!(
+    attribute<Item>,
+    do_parse!(
+        name: identifier
+            >> equals
+            >> value: attribute_value
+            >> item_end
+            >> (Item::Attribute(name, value))
+    )
+);
+
+// labels must be on the same line as the block type
+fn label(input: &[u8]) -> IResult<&[u8], String> {
+    let i = space(input);
+    match i.first() {
+        Some(&b'"') => quoted_string(i),
+        Some(&c) if is_ident_start(c) => identifier(i),
+        _ => Error(error_position!(ErrorKind::Custom(0), input)),
+    }
+}
+
+named!(
+    block<Item>,
+    do_parse!(
+        name: identifier
+            >> labels: many0!(complete!(label))
+            >> skip_space
+            >> complete!(tag!("{"))
+            >> b: call!(nested, body)
+            >> blank
+            >> complete!(tag!("}"))
+            >> item_end
+            >> (Item::Block(name, labels, b))
+    )
+);
+
+fn to_json(e: &Expr) -> JsonValue {
+    match *e {
+        Expr::Literal(ref v) => v.clone(),
+        Expr::Template(ref parts) => {
+            if let [TemplatePart::Literal(ref s)] = parts[..] {
+                JsonValue::Str(s.clone())
+            } else if parts.is_empty() {
+                JsonValue::Str(String::new())
+            } else {
+                JsonValue::Str(template::to_source(parts))
+            }
+        }
+        Expr::Tuple(ref items) => JsonValue::Array(items.iter().map(to_json).collect()),
+        Expr::Object(ref items) => {
+            let mut h = HashMap::new();
+            for (k, v) in items {
+                let key = match (k, to_json(k)) {
+                    (Expr::Variable(name), _) => name.clone(),
+                    (Expr::Template(_), JsonValue::Str(s)) => s,
+                    _ => return JsonValue::Str(format!("${{{}}}", e)),
+                };
+                h.insert(key, to_json(v));
+            }
+            JsonValue::Object(h)
+        }
+        Expr::Unary(UnaryOp::Negate, ref inner) => match **inner {
+            Expr::Literal(JsonValue::Num(n)) => JsonValue::Num(-n),
+            _ => JsonValue::Str(format!("${{{}}}", e)),
+        },
+        _ => JsonValue::Str(format!("${{{}}}", e)),
+    }
+}
+
+#[test]
+fn hcl2_expressions() {
+    let test = "
+# comment
+name    = var.prefix
+count   = 3
+neg     = -1.5
+tags    = { env = \"prod\", \"team\" = local.team }
+ids     = aws_instance.web[*].id
+message = \"hello ${ var.name }\"
+upper   = [for s in var.list : upper(s) if s != \"\"]
+";
+    if let Ok(JsonValue::Object(dict)) = parse_hcl2(test) {
+        let str_of = |k: &str| match dict.get(k) {
+            Some(JsonValue::Str(s)) => s.clone(),
+            other => panic!("{} is {:?}", k, other),
+        };
+        assert_eq!("${var.prefix}", str_of("name"));
+        // attribute-only splats print in the form hcl1 interpolations understand
+        assert_eq!("${aws_instance.web.*.id}", str_of("ids"));
+        assert_eq!("hello ${ var.name }", str_of("message"));
+        assert_eq!(
+            "${[for s in var.list : upper(s) if s != \"\"]}",
+            str_of("upper")
+        );
+        assert_eq!(Some(&JsonValue::Num(3.)), dict.get("count"));
+        assert_eq!(Some(&JsonValue::Num(-1.5)), dict.get("neg"));
+        if let Some(JsonValue::Object(tags)) = dict.get("tags") {
+            assert_eq!(Some(&JsonValue::Str("prod".to_string())), tags.get("env"));
+            assert_eq!(
+                Some(&JsonValue::Str("${local.team}".to_string())),
+                tags.get("team")
+            );
+            return;
+        }
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+fn hcl2_blocks_match_hcl1_shape() {
+    let test = "service \"foo\" {
+  key = \"value\"
+}
+
+service \"bar\" \"baz\" {
+  nested {
+    enabled = true
+  }
+}
+";
+    let hcl1 = crate::hcl::parse_hcl(test).unwrap();
+    assert_eq!(Ok(hcl1), parse_hcl2(test));
+}
+
+#[test]
+fn hcl2_heredoc() {
+    let test = "script = <<EOT\necho ${var.x}\nEOT\n";
+    if let Ok(JsonValue::Object(dict)) = parse_hcl2(test) {
+        if let Some(JsonValue::Str(resp)) = dict.get("script") {
+            return assert_eq!("echo ${var.x}\n", resp);
+        }
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+fn hcl2_flush_heredoc() {
+    // hcl2 removes the smallest indentation, wherever the marker is
+    let test = "a = <<-EOT\n    foo\n      bar\n\n  EOT\nb = 1\n";
+    if let Ok(JsonValue::Object(dict)) = parse_hcl2(test) {
+        assert_eq!(
+            Some(&JsonValue::Str("foo\n  bar\n\n".to_string())),
+            dict.get("a")
+        );
+        return;
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+fn hcl2_line_endings() {
+    let test = "\u{feff}a = 1\r\nb {\r  c = \"d\"\r\n}\r\n";
+    let expected = crate::hcl::parse_hcl(test).unwrap();
+    assert_eq!(Ok(expected), parse_hcl2(test));
+}
+
+#[test]
+fn hcl2_invalid() {
+    assert!(parse_hcl2("a = 1\na = 2").is_err());
+    assert!(parse_hcl2("a = 1 b = 2").is_err());
+    assert!(parse_hcl2("\"a\" = 1").is_err());
+    assert!(parse_hcl2("a {").is_err());
+    assert!(parse_hcl2("a = 1\na {}").is_err());
+    assert!(parse_hcl2("foo\nbar {}").is_err());
+    assert!(parse_hcl2("foo /* x */\n\"bar\" {}").is_err());
+}
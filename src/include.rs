@@ -0,0 +1,394 @@
+//! An opt-in `include` directive for composing HCL files, enabled by the
+//! `include` feature.
+//!
+//! A top-level `include "common/*.hcl"` line is resolved relative to the file
+//! it appears in, and each matching file, in lexical order, is loaded in turn.
+//! Included bodies come before the including file's own items and combine as
+//! repeated items within one file do: blocks and lists accumulate, and any
+//! other attribute takes the last value.
+//!
+//! Every included file must be inside the root, by default the directory of
+//! the file loaded first, and no file may include itself, however indirectly.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+use crate::common::TempDir;
+use crate::hcl::parse_hcl;
+use crate::lexer::{tokenize, Token, TokenKind};
+use crate::types::{JsonValue, ParseError};
+
+#[derive(Debug)]
+pub enum IncludeErrorKind {
+    Io(io::Error),
+    Parse(ParseError),
+    /// The file includes itself, possibly through others.
+    Cycle,
+    /// The file is outside of the root.
+    OutsideRoot(PathBuf),
+    /// A pattern could not be used, or has an interpolation, with the
+    /// offset of its directive.
+    Pattern(String, usize),
+    /// A pattern without wildcards names a file that does not exist.
+    NoMatch(String, usize),
+}
+
+/// An error in a file, with the include directives that led to it, the
+/// outermost first.
+#[derive(Debug)]
+pub struct IncludeError {
+    pub file: PathBuf,
+    pub kind: IncludeErrorKind,
+    pub chain: Vec<(PathBuf, usize)>,
+}
+
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.file.display())?;
+        match self.kind {
+            IncludeErrorKind::Io(ref e) => write!(f, "{}", e),
+            IncludeErrorKind::Parse(ref e) => write!(f, "{}", e),
+            IncludeErrorKind::Cycle => write!(f, "include cycle"),
+            IncludeErrorKind::OutsideRoot(ref root) => {
+                write!(f, "outside of {}", root.display())
+            }
+            IncludeErrorKind::Pattern(ref message, offset) => {
+                write!(f, "invalid include pattern at byte {}: {}", offset, message)
+            }
+            IncludeErrorKind::NoMatch(ref pattern, offset) => {
+                write!(f, "no file matches \"{}\" at byte {}", pattern, offset)
+            }
+        }?;
+        for (path, offset) in self.chain.iter().rev() {
+            write!(f, "\n  included from {} at byte {}", path.display(), offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for IncludeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self.kind {
+            IncludeErrorKind::Io(ref e) => Some(e),
+            IncludeErrorKind::Parse(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Loads HCL files, resolving their include directives.
+#[derive(Clone, Debug)]
+pub struct Includes {
+    root: Option<PathBuf>,
+    directive: String,
+}
+
+impl Default for Includes {
+    fn default() -> Includes {
+        Includes {
+            root: None,
+            directive: "include".to_string(),
+        }
+    }
+}
+
+struct Load<'r> {
+    root: PathBuf,
+    directive: &'r str,
+    // canonical paths of the files being loaded
+    stack: Vec<PathBuf>,
+    // the directives that led to the current file
+    chain: Vec<(PathBuf, usize)>,
+}
+
+impl Includes {
+    pub fn new() -> Includes {
+        Includes::default()
+    }
+
+    /// Sets the directory included files must be in.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Includes {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// Sets the name of the directive, `include` by default.
+    pub fn directive(mut self, name: &str) -> Includes {
+        self.directive = name.to_string();
+        self
+    }
+
+    /// Loads `path` and everything it includes into one body.
+    pub fn load(&self, path: &Path) -> Result<JsonValue, IncludeError> {
+        let error = |kind| IncludeError {
+            file: path.to_path_buf(),
+            kind,
+            chain: Vec::new(),
+        };
+        let root = match self.root {
+            Some(ref root) => root.clone(),
+            None => path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        };
+        let root = root
+            .canonicalize()
+            .map_err(|e| error(IncludeErrorKind::Io(e)))?;
+        let mut load = Load {
+            root,
+            directive: &self.directive,
+            stack: Vec::new(),
+            chain: Vec::new(),
+        };
+        load.file(path).map(JsonValue::Object)
+    }
+}
+
+impl<'r> Load<'r> {
+    fn error(&self, file: &Path, kind: IncludeErrorKind) -> IncludeError {
+        IncludeError {
+            file: file.to_path_buf(),
+            kind,
+            chain: self.chain.clone(),
+        }
+    }
+
+    fn file(&mut self, path: &Path) -> Result<HashMap<String, JsonValue>, IncludeError> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| self.error(path, IncludeErrorKind::Io(e)))?;
+        if !canonical.starts_with(&self.root) {
+            return Err(self.error(path, IncludeErrorKind::OutsideRoot(self.root.clone())));
+        }
+        if self.stack.contains(&canonical) {
+            return Err(self.error(path, IncludeErrorKind::Cycle));
+        }
+        let mut source =
+            fs::read_to_string(path).map_err(|e| self.error(path, IncludeErrorKind::Io(e)))?;
+        let directives = directives(&source, self.directive)
+            .map_err(|e| self.error(path, IncludeErrorKind::Parse(e)))?;
+        let mut patterns = Vec::new();
+        for &(start, end, ref pattern) in &directives {
+            let pattern = pattern.clone().ok_or_else(|| {
+                let message = "interpolations are not allowed".to_string();
+                self.error(path, IncludeErrorKind::Pattern(message, start))
+            })?;
+            patterns.push((start, pattern));
+            // blanked out, so offsets in errors still match the file
+            source.replace_range(start..end, &" ".repeat(end - start));
+        }
+        let own = parse_hcl(&source).map_err(|e| self.error(path, IncludeErrorKind::Parse(e)))?;
+
+        self.stack.push(canonical);
+        let mut body = HashMap::new();
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for (start, pattern) in patterns {
+            let matches = self.matches(path, dir, &pattern, start)?;
+            self.chain.push((path.to_path_buf(), start));
+            for included in matches {
+                let included = self.file(&included)?;
+                combine(&mut body, included);
+            }
+            self.chain.pop();
+        }
+        self.stack.pop();
+        if let JsonValue::Object(own) = own {
+            combine(&mut body, own);
+        }
+        Ok(body)
+    }
+
+    fn matches(
+        &self,
+        path: &Path,
+        dir: &Path,
+        pattern: &str,
+        at: usize,
+    ) -> Result<Vec<PathBuf>, IncludeError> {
+        let full = if dir.as_os_str().is_empty() {
+            pattern.to_string()
+        } else {
+            format!(
+                "{}/{}",
+                glob::Pattern::escape(&dir.to_string_lossy()),
+                pattern
+            )
+        };
+        let paths = glob::glob(&full)
+            .map_err(|e| self.error(path, IncludeErrorKind::Pattern(e.msg.to_string(), at)))?;
+        let mut matches = Vec::new();
+        for entry in paths {
+            let entry = entry.map_err(|e| {
+                let file = e.path().to_path_buf();
+                self.error(&file, IncludeErrorKind::Io(e.into()))
+            })?;
+            if entry.is_file() {
+                matches.push(entry);
+            }
+        }
+        let wildcard = pattern.contains(['*', '?', '[']);
+        if matches.is_empty() && !wildcard {
+            return Err(self.error(path, IncludeErrorKind::NoMatch(pattern.to_string(), at)));
+        }
+        matches.sort();
+        Ok(matches)
+    }
+}
+
+// combines bodies as repeated items of one file are
+fn combine(body: &mut HashMap<String, JsonValue>, other: HashMap<String, JsonValue>) {
+    for (key, value) in other {
+        match (body.get_mut(&key), value) {
+            (Some(&mut JsonValue::Array(ref mut items)), JsonValue::Array(more)) => {
+                items.extend(more)
+            }
+            (_, value) => {
+                body.insert(key, value);
+            }
+        }
+    }
+}
+
+// finds the top-level directives in `source`, each with the span it takes up
+// and its pattern, if it is a plain string
+type Directive = (usize, usize, Option<String>);
+
+fn directives(source: &str, name: &str) -> Result<Vec<Directive>, ParseError> {
+    let tokens = tokenize(source)?;
+    let mut found = Vec::new();
+    let mut depth = 0usize;
+    let mut line_start = true;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        match token.kind {
+            TokenKind::Punctuation if matches!(token.text, "{" | "[" | "(") => depth += 1,
+            TokenKind::Punctuation if matches!(token.text, "}" | "]" | ")") => {
+                depth = depth.saturating_sub(1)
+            }
+            TokenKind::Identifier if depth == 0 && line_start && token.text == name => {
+                if let Some(close) = directive(&tokens[i + 1..]) {
+                    let inner = &tokens[i + 2..i + 1 + close];
+                    let pattern = inner
+                        .iter()
+                        .all(|t| t.kind == TokenKind::StringFragment)
+                        .then(|| inner.iter().map(|t| t.text).collect());
+                    found.push((token.span.start, tokens[i + 1 + close].span.end, pattern));
+                    i += close + 2;
+                    line_start = false;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        line_start = token.kind == TokenKind::Newline;
+        i += 1;
+    }
+    Ok(found)
+}
+
+// the index of the closing quote when `tokens` start with a string ending
+// its line
+fn directive(tokens: &[Token]) -> Option<usize> {
+    if tokens.first()?.kind != TokenKind::StringStart {
+        return None;
+    }
+    // strings may nest inside interpolations
+    let mut depth = 0usize;
+    let close = tokens.iter().position(|t| {
+        match t.kind {
+            TokenKind::StringStart => depth += 1,
+            TokenKind::StringEnd => depth -= 1,
+            _ => {}
+        }
+        depth == 0
+    })?;
+    tokens
+        .get(close + 1)
+        .is_none_or(|t| matches!(t.kind, TokenKind::Newline | TokenKind::Comment))
+        .then_some(close)
+}
+
+#[cfg(test)]
+fn write_files(files: &[(&str, &str)]) -> TempDir {
+    let dir = TempDir::new("include");
+    dir.write(files);
+    dir
+}
+
+#[test]
+fn include_globs() {
+    let dir = write_files(&[
+        (
+            "main.hcl",
+            "include \"common/*.hcl\"\nname = \"main\"\nservice \"web\" {}\n",
+        ),
+        ("common/a.hcl", "name = \"a\"\nservice \"a\" {}\n"),
+        (
+            "common/b.hcl",
+            "include \"../extra.hcl\"\nservice \"b\" {}\n",
+        ),
+        ("extra.hcl", "tags = [\"x\"]\n"),
+    ]);
+    let loaded = Includes::new().load(&dir.join("main.hcl")).unwrap();
+    let expected = parse_hcl(
+        "service \"a\" {}\ntags = [\"x\"]\nservice \"b\" {}\n\
+         name = \"main\"\nservice \"web\" {}\n",
+    )
+    .unwrap();
+    assert_eq!(expected, loaded);
+}
+
+#[test]
+fn include_errors() {
+    let dir = write_files(&[
+        ("main.hcl", "# shared\ninclude \"a.hcl\"\n"),
+        ("a.hcl", "include \"b.hcl\"\n"),
+        ("b.hcl", "include \"a.hcl\"\n"),
+        ("jail/main.hcl", "include \"../a.hcl\"\n"),
+        ("missing.hcl", "include \"nope.hcl\"\n"),
+        ("broken.hcl", "include \"bad.hcl\"\n"),
+        ("bad.hcl", "a = \n"),
+        ("dynamic.hcl", "include \"${x}.hcl\"\n"),
+        ("nested.hcl", "include \"${lower(\"A\")}.hcl\"\n"),
+    ]);
+    let error = |file: &str| {
+        Includes::new()
+            .root(dir.path())
+            .load(&dir.join(file))
+            .unwrap_err()
+            .to_string()
+            .replace(&format!("{}/", dir.display()), "")
+            .replace(&dir.display().to_string(), "<root>")
+    };
+    assert_eq!(
+        "a.hcl: include cycle\n  \
+         included from b.hcl at byte 0\n  \
+         included from a.hcl at byte 0\n  \
+         included from main.hcl at byte 9",
+        error("main.hcl")
+    );
+    assert_eq!(
+        "missing.hcl: no file matches \"nope.hcl\" at byte 0",
+        error("missing.hcl")
+    );
+    assert!(error("broken.hcl").starts_with("bad.hcl: "));
+    assert!(error("broken.hcl").ends_with("\n  included from broken.hcl at byte 0"));
+    for file in &["dynamic.hcl", "nested.hcl"] {
+        assert_eq!(
+            format!(
+                "{}: invalid include pattern at byte 0: interpolations are not allowed",
+                file
+            ),
+            error(file)
+        );
+    }
+
+    let jailed = Includes::new()
+        .load(&dir.join("jail/main.hcl"))
+        .unwrap_err();
+    assert!(matches!(jailed.kind, IncludeErrorKind::OutsideRoot(_)));
+    assert_eq!(1, jailed.chain.len());
+}
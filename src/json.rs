@@ -2,33 +2,90 @@
 //! Copyright (c) 2015-2016 Geoffroy Couprie - MIT License
 
 use std::collections::HashMap;
+use std::io::Read;
 use std::str;
+use std::string::FromUtf8Error;
 
-use nom::IResult::Done;
+use nom::IResult;
+use nom::IResult::{Done, Error};
 
-use crate::common::{boolean, float};
-use crate::types::{JsonValue, ParseError};
+use crate::common::{boolean, check_size, check_text, float, here, nested, offset, run_with};
+use crate::parser::{build_body, read_limited, DuplicateKeys, Interpolations, Item, ParserOptions};
+#[cfg(test)]
+use crate::types::ParseErrorKind;
+use crate::types::{JsonValue, ParseError, ReadError};
 
 // NOTE this json parser is only included for internal verification purposes
 // the standard hcl parser by hashicorp includes a nonstandrd json parser
 // this is not intended to mirror that
 
 pub fn parse_json(config: &str) -> Result<JsonValue, ParseError> {
-    match json(&config.as_bytes()[..]) {
-        Done(_, c) => Ok(c),
-        _ => Err(0),
-    }
+    parse_json_bytes(config.as_bytes())
+}
+
+pub fn parse_json_bytes(config: &[u8]) -> Result<JsonValue, ParseError> {
+    // like most json parsers, the last duplicate wins
+    let options = ParserOptions {
+        duplicate_keys: DuplicateKeys::LastWins,
+        ..ParserOptions::default()
+    };
+    parse_json_with(config, &options)
+}
+
+/// Parses JSON with the limits and duplicate key policy in `options`; the
+/// rest do not apply.
+pub(crate) fn parse_json_with(
+    config: &[u8],
+    options: &ParserOptions,
+) -> Result<JsonValue, ParseError> {
+    check_size(config, options)?;
+    check_text(config)?;
+    let options = ParserOptions {
+        strict: false,
+        multiline_strings: false,
+        interpolations: Interpolations::Raw,
+        ..options.clone()
+    };
+    run_with(config, &options, json).map(|(value, _)| value)
+}
+
+/// Parses any JSON value, not only an object.
+pub(crate) fn parse_value(config: &str) -> Result<JsonValue, ParseError> {
+    check_text(config.as_bytes())?;
+    let options = ParserOptions {
+        duplicate_keys: DuplicateKeys::LastWins,
+        ..ParserOptions::default()
+    };
+    run_with(config.as_bytes(), &options, json_value).map(|(value, _)| value)
+}
+
+/// Parses the members of the top-level object, each with the offset it
+/// starts at, leaving them to the caller to combine.
+pub(crate) fn parse_items(
+    config: &str,
+    options: &ParserOptions,
+) -> Result<Vec<(usize, Item)>, ParseError> {
+    check_size(config.as_bytes(), options)?;
+    check_text(config.as_bytes())?;
+    run_with(config.as_bytes(), options, json_items).map(|(items, _)| items)
+}
+
+/// Parses everything `reader` produces; reading errors are kept apart from
+/// parse errors.
+pub fn parse_json_reader<R: Read>(reader: R) -> Result<JsonValue, ReadError> {
+    let config = read_limited(reader, None)?;
+    Ok(parse_json_bytes(&config)?)
 }
 
-named!(json<JsonValue>, map!(json_hash, |h| JsonValue::Object(h)));
+named!(json<JsonValue>, map!(json_hash, JsonValue::Object));
 
-fn to_s(i: Vec<u8>) -> String {
-    String::from_utf8_lossy(&i).into_owned()
+fn to_s(i: Vec<u8>) -> Result<String, FromUtf8Error> {
+    String::from_utf8(i)
 }
 
 named!(
     json_escaped_string<String>,
-    map!(
+    map_res!(
         escaped_transform!(
             is_not!("\\\"\n"),
             '\\',
@@ -67,40 +124,55 @@ named!(
 );
 
 named!(
-    json_key_value<(String, JsonValue)>,
-    ws!(separated_pair!(json_string, tag!(":"), json_value))
+    json_key_value<(usize, Item)>,
+    ws!(do_parse!(
+        at: here >> key: json_string >> tag!(":") >> value: json_value
+            >> ((at, Item::Attribute(key, value)))
+    ))
 );
 
-named!(
-    json_hash<HashMap<String, JsonValue>>,
-    ws!(map!(
-        delimited!(
-            tag!("{"),
-            separated_list!(tag!(","), json_key_value),
-            tag!("}")
+fn json_items(input: &[u8]) -> IResult<&[u8], Vec<(usize, Item)>> {
+    match json_members(input) {
+        Done(rest, items) => Done(
+            rest,
+            items
+                .into_iter()
+                .map(|(at, item)| (offset(at), item))
+                .collect(),
         ),
-        |tuple_vec| {
-            let mut h: HashMap<String, JsonValue> = HashMap::new();
-            for (k, v) in tuple_vec {
-                h.insert(String::from(k), v);
-            }
-            h
-        }
+        Error(e) => Error(e),
+        IResult::Incomplete(n) => IResult::Incomplete(n),
+    }
+}
+
+named!(
+    json_members<Vec<(usize, Item)>>,
+    ws!(delimited!(
+        tag!("{"),
+        separated_list!(tag!(","), json_key_value),
+        tag!("}")
     ))
 );
 
+named!(
+    json_hash<HashMap<String, JsonValue>>,
+    map_opt!(json_members, build_body)
+);
+
 named!(
     json_value<JsonValue>,
     ws!(alt!(
-        json_hash   => { |h|   JsonValue::Object(h)            } |
-        json_array  => { |v|   JsonValue::Array(v)             } |
-        json_string => { |s|   JsonValue::Str(String::from(s)) } |
-        float       => { |num| JsonValue::Num(num)             } |
-        boolean     => { |b|   JsonValue::Boolean(b)           }
+        preceded!(peek!(tag!("{")), call!(nested, json_hash))  => { JsonValue::Object } |
+        preceded!(peek!(tag!("[")), call!(nested, json_array)) => { JsonValue::Array  } |
+        json_string => { JsonValue::Str     } |
+        float       => { JsonValue::Num     } |
+        boolean     => { JsonValue::Boolean } |
+        tag!("null") => { |_| JsonValue::Null }
     ))
 );
 
 #[test]
+#[allow(clippy::bool_assert_comparison, clippy::needless_borrowed_reference)]
 fn json_bool_test() {
     let test = "  { \"a\"\t: true,
   \"b\": \"false\"
@@ -119,6 +191,7 @@ fn json_bool_test() {
 }
 
 #[test]
+#[allow(clippy::needless_borrowed_reference)]
 fn json_hash_test() {
     let test = "  { \"a\"\t: 42,
   \"b\": \"x\"
@@ -137,6 +210,7 @@ fn json_hash_test() {
 }
 
 #[test]
+#[allow(clippy::get_first, clippy::needless_borrowed_reference)]
 fn json_parse_example_test() {
     let test = "  { \"a\"\t: 42,
   \"b\": [ \"x\", \"y\", 12 ] ,
@@ -168,3 +242,43 @@ fn json_parse_example_test() {
     }
     panic!("object did not parse");
 }
+
+#[test]
+fn json_bom_and_line_endings_test() {
+    let test = "\u{feff}{\r\n  \"a\": 1,\r  \"b\": \"x\"\r\n}\r\n";
+
+    if let Ok(JsonValue::Object(dict)) = parse_json(test) {
+        assert_eq!(Some(&JsonValue::Num(1.)), dict.get("a"));
+        assert_eq!(Some(&JsonValue::Str("x".to_string())), dict.get("b"));
+        return;
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+fn json_bytes_test() {
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::InvalidUtf8, 7)),
+        parse_json_bytes(b"{\"a\": \"\xff\"}")
+    );
+    if let Ok(JsonValue::Object(dict)) = parse_json_bytes("{\"a\": \"über\"}".as_bytes()) {
+        return assert_eq!(Some(&JsonValue::Str("über".to_string())), dict.get("a"));
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+fn json_reader_test() {
+    if let Ok(JsonValue::Object(dict)) = parse_json_reader(&b"{\"a\": [1]}"[..]) {
+        assert_eq!(
+            Some(&JsonValue::Array(vec![JsonValue::Num(1.)])),
+            dict.get("a")
+        );
+    } else {
+        panic!("object did not parse");
+    }
+    match parse_json_reader(&b"{\"a\": }"[..]) {
+        Err(ReadError::Parse(_)) => (),
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
@@ -0,0 +1,515 @@
+//! Validating parsed values against JSON Schema documents.
+//!
+//! A draft-07 subset is supported: `type`, `enum`, `const`, the number,
+//! string, array and object constraints that do not need regular expressions,
+//! `allOf`, `anyOf`, `oneOf`, `not`, and `$ref` to the document or its
+//! `definitions`. Annotations such as `title` and `description` are ignored;
+//! any other keyword is rejected when the document is loaded, rather than
+//! silently not checked.
+
+use std::collections::HashMap;
+
+use crate::types::{JsonValue, TypeError};
+
+const ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "format",
+    "readOnly",
+    "writeOnly",
+    "definitions",
+];
+
+const KEYWORDS: &[&str] = &[
+    "$ref",
+    "type",
+    "enum",
+    "const",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "multipleOf",
+    "minLength",
+    "maxLength",
+    "items",
+    "additionalItems",
+    "minItems",
+    "maxItems",
+    "uniqueItems",
+    "properties",
+    "required",
+    "additionalProperties",
+    "minProperties",
+    "maxProperties",
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+];
+
+/// A loaded JSON Schema document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonSchema {
+    root: JsonValue,
+}
+
+impl JsonSchema {
+    /// Loads a schema document, failing on keywords outside the supported
+    /// subset and on references that do not resolve. Errors give the path of
+    /// the offending keyword in the document.
+    pub fn new(document: JsonValue) -> Result<JsonSchema, TypeError> {
+        let schema = JsonSchema { root: document };
+        schema.check(&schema.root, "")?;
+        Ok(schema)
+    }
+
+    /// Checks a value, reporting every violation with its path in the value.
+    pub fn validate(&self, value: &JsonValue) -> Result<(), Vec<TypeError>> {
+        let mut violations = Vec::new();
+        self.validate_at(&self.root, value, "", &mut violations);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn check(&self, schema: &JsonValue, path: &str) -> Result<(), TypeError> {
+        let keywords = match *schema {
+            JsonValue::Boolean(_) => return Ok(()),
+            JsonValue::Object(ref h) => h,
+            _ => return Err(violation(path, "a schema must be an object or a boolean")),
+        };
+        let mut names: Vec<&String> = keywords.keys().collect();
+        names.sort();
+        for name in names {
+            let value = &keywords[name];
+            let at = format!("{}/{}", path, name);
+            match name.as_str() {
+                "$ref" => match *value {
+                    JsonValue::Str(ref r) if self.resolve(r).is_some() => {
+                        if self.ref_cycle(schema, &mut Vec::new()) {
+                            return Err(violation(&at, "reference leads back to itself"));
+                        }
+                    }
+                    _ => return Err(violation(&at, "reference does not resolve")),
+                },
+                "definitions" | "properties" => match *value {
+                    JsonValue::Object(ref h) => {
+                        for (k, sub) in h {
+                            self.check(sub, &format!("{}/{}", at, k))?;
+                        }
+                    }
+                    _ => return Err(violation(&at, "must be an object")),
+                },
+                "items" | "allOf" | "anyOf" | "oneOf" => match *value {
+                    JsonValue::Array(ref items) => {
+                        for (i, sub) in items.iter().enumerate() {
+                            self.check(sub, &format!("{}/{}", at, i))?;
+                        }
+                    }
+                    _ if name == "items" => self.check(value, &at)?,
+                    _ => return Err(violation(&at, "must be an array")),
+                },
+                "additionalItems" | "additionalProperties" | "not" => self.check(value, &at)?,
+                _ if KEYWORDS.contains(&name.as_str()) || ANNOTATIONS.contains(&name.as_str()) => {}
+                _ => return Err(violation(&at, "unsupported keyword")),
+            }
+        }
+        Ok(())
+    }
+
+    // whether the references `schema` applies to the value it is given lead
+    // back to one already followed, before any keyword moves into the value
+    fn ref_cycle<'s>(&'s self, schema: &'s JsonValue, chain: &mut Vec<&'s str>) -> bool {
+        let keywords = match *schema {
+            JsonValue::Object(ref h) => h,
+            _ => return false,
+        };
+        if let Some(JsonValue::Str(r)) = keywords.get("$ref") {
+            if chain.contains(&r.as_str()) {
+                return true;
+            }
+            chain.push(r);
+            let cycle = self
+                .resolve(r)
+                .is_some_and(|target| self.ref_cycle(target, chain));
+            chain.pop();
+            return cycle;
+        }
+        let mut applied: Vec<&JsonValue> = Vec::new();
+        for name in &["allOf", "anyOf", "oneOf"] {
+            if let Some(JsonValue::Array(subs)) = keywords.get(*name) {
+                applied.extend(subs);
+            }
+        }
+        applied.extend(keywords.get("not"));
+        applied.into_iter().any(|sub| self.ref_cycle(sub, chain))
+    }
+
+    fn resolve(&self, reference: &str) -> Option<&JsonValue> {
+        let pointer = reference.strip_prefix('#')?;
+        let mut node = &self.root;
+        for part in pointer.split('/').skip(1) {
+            let part = part.replace("~1", "/").replace("~0", "~");
+            node = match *node {
+                JsonValue::Object(ref h) => h.get(&part)?,
+                JsonValue::Array(ref items) => items.get(part.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        Some(node)
+    }
+
+    fn matches(&self, schema: &JsonValue, value: &JsonValue) -> bool {
+        let mut violations = Vec::new();
+        self.validate_at(schema, value, "", &mut violations);
+        violations.is_empty()
+    }
+
+    fn validate_at(
+        &self,
+        schema: &JsonValue,
+        value: &JsonValue,
+        path: &str,
+        violations: &mut Vec<TypeError>,
+    ) {
+        let keywords = match *schema {
+            JsonValue::Boolean(true) => return,
+            JsonValue::Object(ref h) => h,
+            _ => return violations.push(violation(path, "no value is allowed here")),
+        };
+        // siblings of a reference are ignored in draft-07
+        if let Some(JsonValue::Str(r)) = keywords.get("$ref") {
+            if let Some(target) = self.resolve(r) {
+                self.validate_at(target, value, path, violations);
+            }
+            return;
+        }
+        if let Some(types) = keywords.get("type") {
+            let allowed: Vec<&str> = match *types {
+                JsonValue::Str(ref t) => vec![t.as_str()],
+                JsonValue::Array(ref ts) => ts
+                    .iter()
+                    .filter_map(|t| match *t {
+                        JsonValue::Str(ref t) => Some(t.as_str()),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            if !allowed.iter().any(|t| is_type(value, t)) {
+                let message = format!(
+                    "expected {}, found {}",
+                    allowed.join(" or "),
+                    type_name(value)
+                );
+                violations.push(violation(path, &message));
+                // the other keywords would only repeat the mismatch
+                return;
+            }
+        }
+        if let Some(JsonValue::Array(options)) = keywords.get("enum") {
+            if !options.contains(value) {
+                violations.push(violation(
+                    path,
+                    &format!("{} is not one of the allowed values", value),
+                ));
+            }
+        }
+        if let Some(expected) = keywords.get("const") {
+            if expected != value {
+                violations.push(violation(
+                    path,
+                    &format!("expected {}, found {}", expected, value),
+                ));
+            }
+        }
+        match *value {
+            JsonValue::Num(n) => self.validate_number(keywords, n, path, violations),
+            JsonValue::Str(ref s) => {
+                let len = s.chars().count() as f32;
+                if number(keywords, "minLength").is_some_and(|min| len < min) {
+                    violations.push(violation(path, "string is too short"));
+                }
+                if number(keywords, "maxLength").is_some_and(|max| len > max) {
+                    violations.push(violation(path, "string is too long"));
+                }
+            }
+            JsonValue::Array(ref items) => self.validate_array(keywords, items, path, violations),
+            JsonValue::Object(ref h) => self.validate_object(keywords, h, path, violations),
+            _ => {}
+        }
+        if let Some(JsonValue::Array(all)) = keywords.get("allOf") {
+            for sub in all {
+                self.validate_at(sub, value, path, violations);
+            }
+        }
+        if let Some(JsonValue::Array(any)) = keywords.get("anyOf") {
+            if !any.iter().any(|sub| self.matches(sub, value)) {
+                violations.push(violation(path, "does not match any of the allowed schemas"));
+            }
+        }
+        if let Some(JsonValue::Array(one)) = keywords.get("oneOf") {
+            let count = one.iter().filter(|sub| self.matches(sub, value)).count();
+            if count != 1 {
+                let message = format!("matches {} of the schemas in oneOf, not exactly one", count);
+                violations.push(violation(path, &message));
+            }
+        }
+        if let Some(not) = keywords.get("not") {
+            if self.matches(not, value) {
+                violations.push(violation(path, "matches a schema it must not"));
+            }
+        }
+    }
+
+    fn validate_number(
+        &self,
+        keywords: &HashMap<String, JsonValue>,
+        n: f32,
+        path: &str,
+        violations: &mut Vec<TypeError>,
+    ) {
+        let mut limit = |keyword: &str, fails: fn(f32, f32) -> bool, relation: &str| {
+            if let Some(limit) = number(keywords, keyword) {
+                if fails(n, limit) {
+                    let message = format!("{} is {} {}", n, relation, limit);
+                    violations.push(violation(path, &message));
+                }
+            }
+        };
+        limit("minimum", |n, m| n < m, "less than");
+        limit("exclusiveMinimum", |n, m| n <= m, "not greater than");
+        limit("maximum", |n, m| n > m, "greater than");
+        limit("exclusiveMaximum", |n, m| n >= m, "not less than");
+        if let Some(step) = number(keywords, "multipleOf") {
+            if (n / step).fract() != 0. {
+                violations.push(violation(
+                    path,
+                    &format!("{} is not a multiple of {}", n, step),
+                ));
+            }
+        }
+    }
+
+    fn validate_array(
+        &self,
+        keywords: &HashMap<String, JsonValue>,
+        items: &[JsonValue],
+        path: &str,
+        violations: &mut Vec<TypeError>,
+    ) {
+        let len = items.len() as f32;
+        if number(keywords, "minItems").is_some_and(|min| len < min) {
+            violations.push(violation(
+                path,
+                &format!("too few items, found {}", items.len()),
+            ));
+        }
+        if number(keywords, "maxItems").is_some_and(|max| len > max) {
+            violations.push(violation(
+                path,
+                &format!("too many items, found {}", items.len()),
+            ));
+        }
+        if let Some(JsonValue::Boolean(true)) = keywords.get("uniqueItems") {
+            for (i, item) in items.iter().enumerate() {
+                if items[..i].contains(item) {
+                    violations.push(violation(&format!("{}[{}]", path, i), "duplicate item"));
+                }
+            }
+        }
+        for (i, item) in items.iter().enumerate() {
+            let item_schema = match keywords.get("items") {
+                Some(JsonValue::Array(positional)) => match positional.get(i) {
+                    Some(schema) => Some(schema),
+                    None => keywords.get("additionalItems"),
+                },
+                other => other,
+            };
+            if let Some(schema) = item_schema {
+                self.validate_at(schema, item, &format!("{}[{}]", path, i), violations);
+            }
+        }
+    }
+
+    fn validate_object(
+        &self,
+        keywords: &HashMap<String, JsonValue>,
+        h: &HashMap<String, JsonValue>,
+        path: &str,
+        violations: &mut Vec<TypeError>,
+    ) {
+        if let Some(JsonValue::Array(required)) = keywords.get("required") {
+            for name in required {
+                if let JsonValue::Str(ref name) = *name {
+                    if !h.contains_key(name) {
+                        let message = format!("attribute \"{}\" is required", name);
+                        violations.push(violation(path, &message));
+                    }
+                }
+            }
+        }
+        let len = h.len() as f32;
+        if number(keywords, "minProperties").is_some_and(|min| len < min) {
+            violations.push(violation(path, "too few attributes"));
+        }
+        if number(keywords, "maxProperties").is_some_and(|max| len > max) {
+            violations.push(violation(path, "too many attributes"));
+        }
+        let properties = match keywords.get("properties") {
+            Some(JsonValue::Object(p)) => Some(p),
+            _ => None,
+        };
+        let mut names: Vec<&String> = h.keys().collect();
+        names.sort();
+        for name in names {
+            let item_path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}.{}", path, name)
+            };
+            match properties.and_then(|p| p.get(name)) {
+                Some(schema) => self.validate_at(schema, &h[name], &item_path, violations),
+                None => match keywords.get("additionalProperties") {
+                    Some(JsonValue::Boolean(false)) => {
+                        violations.push(violation(&item_path, "unexpected attribute or block"))
+                    }
+                    Some(schema) => self.validate_at(schema, &h[name], &item_path, violations),
+                    None => {}
+                },
+            }
+        }
+    }
+}
+
+fn number(keywords: &HashMap<String, JsonValue>, name: &str) -> Option<f32> {
+    match keywords.get(name) {
+        Some(&JsonValue::Num(n)) => Some(n),
+        _ => None,
+    }
+}
+
+fn is_type(value: &JsonValue, name: &str) -> bool {
+    match (name, value) {
+        ("integer", &JsonValue::Num(n)) => n.fract() == 0.,
+        _ => type_name(value) == name,
+    }
+}
+
+// the json schema names, rather than the hcl ones `type_name` gives
+fn type_name(value: &JsonValue) -> &'static str {
+    match *value {
+        JsonValue::Str(_) => "string",
+        JsonValue::Num(_) => "number",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+        JsonValue::Boolean(_) => "boolean",
+        JsonValue::Null => "null",
+    }
+}
+
+fn violation(path: &str, message: &str) -> TypeError {
+    TypeError {
+        path: path.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+fn schema(source: &str) -> JsonSchema {
+    JsonSchema::new(crate::json::parse_json(source).unwrap()).unwrap()
+}
+
+#[cfg(test)]
+fn messages(schema: &JsonSchema, value: &str) -> Vec<String> {
+    match schema.validate(&crate::json::parse_json(value).unwrap()) {
+        Ok(()) => vec![],
+        Err(violations) => violations.iter().map(|v| v.to_string()).collect(),
+    }
+}
+
+#[test]
+fn json_schema_keywords() {
+    let s = schema(
+        "{
+  \"type\": \"object\",
+  \"required\": [\"name\"],
+  \"properties\": {
+    \"name\": {\"type\": \"string\", \"minLength\": 2},
+    \"port\": {\"type\": \"integer\", \"minimum\": 1, \"maximum\": 65535},
+    \"mode\": {\"enum\": [\"a\", \"b\"]},
+    \"tags\": {\"type\": \"array\", \"items\": {\"type\": \"string\"}, \"uniqueItems\": true},
+    \"size\": {\"anyOf\": [{\"type\": \"number\"}, {\"$ref\": \"#/definitions/size\"}]}
+  },
+  \"additionalProperties\": false,
+  \"definitions\": {\"size\": {\"enum\": [\"small\", \"large\"]}}
+}",
+    );
+    assert_eq!(
+        Vec::<String>::new(),
+        messages(
+            &s,
+            "{\"name\": \"db\", \"port\": 5432, \"tags\": [\"a\"], \"size\": \"small\"}"
+        )
+    );
+    assert_eq!(
+        vec![
+            "attribute \"name\" is required",
+            "extra: unexpected attribute or block",
+            "mode: \"c\" is not one of the allowed values",
+            "port: expected integer, found number",
+            "size: does not match any of the allowed schemas",
+            "tags[1]: duplicate item",
+            "tags[2]: expected string, found number",
+        ],
+        messages(
+            &s,
+            "{\"extra\": 1, \"mode\": \"c\", \"port\": 1.5, \"size\": \"medium\", \"tags\": [\"a\", \"a\", 3]}"
+        )
+    );
+    assert_eq!(
+        vec![
+            "name: string is too short",
+            "port: 70000 is greater than 65535"
+        ],
+        messages(&s, "{\"port\": 70000, \"name\": \"x\"}")
+    );
+}
+
+#[test]
+fn json_schema_unsupported() {
+    let load = |source: &str| {
+        JsonSchema::new(crate::json::parse_json(source).unwrap())
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(
+        "/properties/a/pattern: unsupported keyword",
+        load("{\"properties\": {\"a\": {\"pattern\": \"^x\"}}}")
+    );
+    assert_eq!(
+        "/items/$ref: reference does not resolve",
+        load("{\"items\": {\"$ref\": \"#/definitions/missing\"}}")
+    );
+    assert_eq!(
+        "/$ref: reference leads back to itself",
+        load("{\"$ref\": \"#\"}")
+    );
+    assert_eq!(
+        "/definitions/a/anyOf/0/not/$ref: reference leads back to itself",
+        load(
+            "{\"definitions\": {\"a\": {\"anyOf\": [{\"not\": {\"$ref\": \"#/definitions/a\"}}]}}}"
+        )
+    );
+    // recursion through a keyword that moves into the value is fine
+    let tree = "{\"properties\": {\"children\": {\"items\": {\"$ref\": \"#\"}}}}";
+    assert!(JsonSchema::new(crate::json::parse_json(tree).unwrap()).is_ok());
+}
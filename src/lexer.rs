@@ -0,0 +1,530 @@
+//! A tokenizer for HCL source, for tools such as syntax highlighters that
+//! want every token and where it is rather than the values it makes up.
+//!
+//! Spaces and tabs between tokens are skipped; everything else, comments and
+//! newlines included, comes out as a token. Template sequences in strings and
+//! heredocs are tokenized as expressions, between interpolation markers.
+
+use crate::types::{ParseError, ParseErrorKind};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind {
+    Identifier,
+    Number,
+    Bool,
+    /// The opening quote of a string.
+    StringStart,
+    /// Literal text in a string, escapes as written.
+    StringFragment,
+    /// The closing quote of a string.
+    StringEnd,
+    /// `${` or `%{`, with any `~` strip marker.
+    InterpolationStart,
+    /// The `}` closing an interpolation, with any `~` strip marker.
+    InterpolationEnd,
+    /// `<<` or `<<-`, the marker and the newline after it.
+    HeredocStart,
+    /// Literal text in a heredoc.
+    HeredocBody,
+    /// The closing marker, with any indentation before it.
+    HeredocEnd,
+    Punctuation,
+    Comment,
+    Newline,
+}
+
+/// Byte offsets of the start of a token and of the end, exclusive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub span: Span,
+    /// The source text of the token.
+    pub text: &'a str,
+}
+
+enum Mode {
+    Code,
+    // inside `{`, whose `}` is punctuation
+    Brace,
+    // inside `${` or `%{`, whose `}` ends the interpolation
+    Interpolation,
+    Quoted,
+    // the body runs to `end`, then the closing marker to `marker_end`
+    Heredoc { end: usize, marker_end: usize },
+}
+
+const PUNCTUATION: &[&str] = &[
+    "...", "==", "!=", "<=", ">=", "&&", "||", "=>", "{", "}", "[", "]", "(", ")", "=", ",", ".",
+    ":", "?", "+", "-", "*", "/", "%", "<", ">", "!",
+];
+
+/// Yields the tokens of a source, stopping after the first error.
+pub struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+    modes: Vec<Mode>,
+    failed: bool,
+}
+
+/// Tokenizes a whole source.
+pub fn tokenize(source: &str) -> Result<Vec<Token<'_>>, ParseError> {
+    Lexer::new(source).collect()
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Lexer<'a> {
+        Lexer {
+            source,
+            // offsets still count a byte order mark
+            pos: if source.starts_with('\u{feff}') { 3 } else { 0 },
+            modes: vec![Mode::Code],
+            failed: false,
+        }
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.source.as_bytes()[self.pos..]
+    }
+
+    fn token(&mut self, kind: TokenKind, len: usize) -> Option<Result<Token<'a>, ParseError>> {
+        let span = Span {
+            start: self.pos,
+            end: self.pos + len,
+        };
+        self.pos = span.end;
+        Some(Ok(Token {
+            kind,
+            span,
+            text: &self.source[span.start..span.end],
+        }))
+    }
+
+    fn error(
+        &mut self,
+        kind: ParseErrorKind,
+        offset: usize,
+    ) -> Option<Result<Token<'a>, ParseError>> {
+        self.failed = true;
+        Some(Err(ParseError::new(kind, offset)))
+    }
+
+    // `${`, `%{` and their `~` forms, but not the `$${` and `%%{` escapes
+    fn interpolation_start(rest: &[u8]) -> Option<usize> {
+        match rest {
+            [b'$', b'{', b'~', ..] | [b'%', b'{', b'~', ..] => Some(3),
+            [b'$', b'{', ..] | [b'%', b'{', ..] => Some(2),
+            _ => None,
+        }
+    }
+
+    fn quoted(&mut self) -> Option<Result<Token<'a>, ParseError>> {
+        let rest = self.rest();
+        match rest {
+            [] | [b'\n', ..] | [b'\r', ..] => {
+                return self.error(ParseErrorKind::Syntax, self.pos);
+            }
+            [b'"', ..] => {
+                self.modes.pop();
+                return self.token(TokenKind::StringEnd, 1);
+            }
+            _ => {}
+        }
+        if let Some(len) = Lexer::interpolation_start(rest) {
+            self.modes.push(Mode::Interpolation);
+            return self.token(TokenKind::InterpolationStart, len);
+        }
+        let mut len = 0;
+        while len < rest.len() {
+            match rest[len..] {
+                [b'"', ..] | [b'\n', ..] | [b'\r', ..] => break,
+                [b'\\', _, ..] | [b'$', b'$', b'{', ..] | [b'%', b'%', b'{', ..] => {
+                    len += if rest[len] == b'\\' { 2 } else { 3 }
+                }
+                _ if Lexer::interpolation_start(&rest[len..]).is_some() => break,
+                _ => len += 1,
+            }
+        }
+        // an escaped multibyte character must not be split
+        while !self.source.is_char_boundary(self.pos + len) {
+            len += 1;
+        }
+        self.token(TokenKind::StringFragment, len)
+    }
+
+    fn heredoc_body(
+        &mut self,
+        end: usize,
+        marker_end: usize,
+    ) -> Option<Result<Token<'a>, ParseError>> {
+        if self.pos >= end {
+            self.modes.pop();
+            return self.token(TokenKind::HeredocEnd, marker_end - self.pos);
+        }
+        let rest = &self.source.as_bytes()[self.pos..end];
+        if let Some(len) = Lexer::interpolation_start(rest) {
+            self.modes.push(Mode::Interpolation);
+            return self.token(TokenKind::InterpolationStart, len);
+        }
+        let mut len = 0;
+        while len < rest.len() {
+            match rest[len..] {
+                [b'$', b'$', b'{', ..] | [b'%', b'%', b'{', ..] => len += 3,
+                _ if Lexer::interpolation_start(&rest[len..]).is_some() => break,
+                _ => len += 1,
+            }
+        }
+        self.token(TokenKind::HeredocBody, len.min(rest.len()))
+    }
+
+    fn heredoc_start(&mut self) -> Option<Result<Token<'a>, ParseError>> {
+        let rest = self.rest();
+        let introducer = if rest.starts_with(b"<<-") { 3 } else { 2 };
+        let marker_len = rest[introducer..]
+            .iter()
+            .position(|&c| !is_ident_char(c))
+            .unwrap_or(rest.len() - introducer);
+        if !rest.get(introducer).is_some_and(|&c| is_ident_start(c)) {
+            return self.error(ParseErrorKind::HeredocMarker, self.pos + introducer);
+        }
+        let marker = &self.source[self.pos + introducer..self.pos + introducer + marker_len];
+        let spaces = rest[introducer + marker_len..]
+            .iter()
+            .take_while(|&&c| c == b' ' || c == b'\t')
+            .count();
+        let line_end = match newline_len(&rest[introducer + marker_len + spaces..]) {
+            0 => return self.error(ParseErrorKind::HeredocMarker, self.pos + introducer),
+            n => introducer + marker_len + spaces + n,
+        };
+        // find the closing marker, alone on its line but for indentation and
+        // trailing spaces
+        let body = self.pos + line_end;
+        let mut line = body;
+        while line < self.source.len() {
+            let text = &self.source[line..];
+            let len = text.find(['\n', '\r']).unwrap_or(text.len());
+            let indent = text.len() - text.trim_start_matches([' ', '\t']).len();
+            if text[..len].trim_matches([' ', '\t']) == marker {
+                self.modes.push(Mode::Heredoc {
+                    end: line,
+                    marker_end: line + indent + marker.len(),
+                });
+                return self.token(TokenKind::HeredocStart, line_end);
+            }
+            line += len + newline_len(&text.as_bytes()[len..]);
+            if len == text.len() {
+                break;
+            }
+        }
+        let marker = marker.to_string();
+        self.error(ParseErrorKind::HeredocUnterminated(marker), self.pos)
+    }
+
+    fn code(&mut self) -> Option<Result<Token<'a>, ParseError>> {
+        while let [b' ', ..] | [b'\t', ..] = self.rest() {
+            self.pos += 1;
+        }
+        let rest = self.rest();
+        let in_interpolation = matches!(self.modes.last(), Some(Mode::Interpolation));
+        match rest {
+            [] if in_interpolation => self.error(ParseErrorKind::Syntax, self.pos),
+            [] => None,
+            [b'\n', ..] | [b'\r', ..] => self.token(TokenKind::Newline, newline_len(rest)),
+            [b'#', ..] | [b'/', b'/', ..] => {
+                let len = rest
+                    .iter()
+                    .position(|&c| c == b'\n' || c == b'\r')
+                    .unwrap_or(rest.len());
+                self.token(TokenKind::Comment, len)
+            }
+            [b'/', b'*', ..] => match self.source[self.pos + 2..].find("*/") {
+                Some(n) => self.token(TokenKind::Comment, n + 4),
+                None => self.error(ParseErrorKind::Syntax, self.pos),
+            },
+            [b'"', ..] => {
+                self.modes.push(Mode::Quoted);
+                self.token(TokenKind::StringStart, 1)
+            }
+            [b'<', b'<', ..] => self.heredoc_start(),
+            // hcl1 unescapes a string before looking inside its interpolations
+            [b'\\', _, ..] if in_interpolation => {
+                let len = 1 + self.source[self.pos + 1..]
+                    .chars()
+                    .next()
+                    .map_or(0, char::len_utf8);
+                self.token(TokenKind::StringFragment, len)
+            }
+            [b'~', b'}', ..] if in_interpolation => {
+                self.modes.pop();
+                self.token(TokenKind::InterpolationEnd, 2)
+            }
+            [b'}', ..] if in_interpolation => {
+                self.modes.pop();
+                self.token(TokenKind::InterpolationEnd, 1)
+            }
+            [b'{', ..] => {
+                self.modes.push(Mode::Brace);
+                self.token(TokenKind::Punctuation, 1)
+            }
+            [b'}', ..] => {
+                if let Some(Mode::Brace) = self.modes.last() {
+                    self.modes.pop();
+                }
+                self.token(TokenKind::Punctuation, 1)
+            }
+            [c, ..] if c.is_ascii_digit() => self.token(TokenKind::Number, number_len(rest)),
+            [c, ..] if is_ident_start(*c) => {
+                let len = rest
+                    .iter()
+                    .position(|&c| !is_ident_char(c))
+                    .unwrap_or(rest.len());
+                let kind = match &rest[..len] {
+                    b"true" | b"false" => TokenKind::Bool,
+                    _ => TokenKind::Identifier,
+                };
+                self.token(kind, len)
+            }
+            _ => match PUNCTUATION.iter().find(|p| rest.starts_with(p.as_bytes())) {
+                Some(p) => self.token(TokenKind::Punctuation, p.len()),
+                None => self.error(ParseErrorKind::Syntax, self.pos),
+            },
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.modes.last() {
+            Some(&Mode::Quoted) => self.quoted(),
+            Some(&Mode::Heredoc { end, marker_end }) => self.heredoc_body(end, marker_end),
+            _ => self.code(),
+        }
+    }
+}
+
+fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+fn is_ident_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'-'
+}
+
+fn newline_len(input: &[u8]) -> usize {
+    match input {
+        [b'\r', b'\n', ..] => 2,
+        [b'\n', ..] | [b'\r', ..] => 1,
+        _ => 0,
+    }
+}
+
+fn number_len(input: &[u8]) -> usize {
+    let digits = |from: usize, ok: fn(&u8) -> bool| {
+        from + input[from..].iter().take_while(|c| ok(c)).count()
+    };
+    if let [b'0', prefix, c, ..] = *input {
+        let ok: fn(&u8) -> bool = match prefix {
+            b'x' | b'X' => u8::is_ascii_hexdigit,
+            b'o' | b'O' => |c| (b'0'..=b'7').contains(c),
+            b'b' | b'B' => |c| *c == b'0' || *c == b'1',
+            _ => |_| false,
+        };
+        if ok(&c) {
+            return digits(2, ok);
+        }
+    }
+    let mut len = digits(0, u8::is_ascii_digit);
+    if let [b'.', c, ..] = input[len..] {
+        if c.is_ascii_digit() {
+            len = digits(len + 1, u8::is_ascii_digit);
+        }
+    }
+    match input[len..] {
+        [b'e', c, ..] | [b'E', c, ..] if c.is_ascii_digit() => digits(len + 1, u8::is_ascii_digit),
+        [b'e', b'+', c, ..] | [b'e', b'-', c, ..] | [b'E', b'+', c, ..] | [b'E', b'-', c, ..]
+            if c.is_ascii_digit() =>
+        {
+            digits(len + 2, u8::is_ascii_digit)
+        }
+        _ => len,
+    }
+}
+
+#[cfg(test)]
+fn kinds(source: &str) -> Vec<(TokenKind, &str)> {
+    tokenize(source)
+        .unwrap()
+        .into_iter()
+        .map(|t| (t.kind, t.text))
+        .collect()
+}
+
+#[test]
+fn lexer_attributes() {
+    use self::TokenKind::*;
+    assert_eq!(
+        vec![
+            (Comment, "# note"),
+            (Newline, "\n"),
+            (Identifier, "count"),
+            (Punctuation, "="),
+            (Number, "1.5e3"),
+            (Newline, "\r\n"),
+            (Identifier, "enabled"),
+            (Punctuation, "="),
+            (Bool, "true"),
+            (Comment, "/* on */"),
+            (Newline, "\n"),
+            (Identifier, "ports"),
+            (Punctuation, "="),
+            (Punctuation, "["),
+            (Number, "0x1F"),
+            (Punctuation, ","),
+            (Punctuation, "-"),
+            (Number, "2"),
+            (Punctuation, "]"),
+        ],
+        kinds("# note\ncount = 1.5e3\r\nenabled = true/* on */\nports = [0x1F, -2]")
+    );
+    assert_eq!(
+        vec![
+            (Number, "0b1010"),
+            (Number, "0o644"),
+            (Number, "0B1"),
+            (Number, "0O7"),
+            (Number, "0"),
+            (Identifier, "b2"),
+        ],
+        kinds("0b1010 0o644 0B1 0O7 0b2")
+    );
+}
+
+#[test]
+fn lexer_strings() {
+    use self::TokenKind::*;
+    assert_eq!(
+        vec![
+            (Identifier, "a"),
+            (Punctuation, "="),
+            (StringStart, "\""),
+            (StringFragment, "x \\\"$${y} "),
+            (InterpolationStart, "${"),
+            (Punctuation, "{"),
+            (Identifier, "b"),
+            (Punctuation, "="),
+            (StringStart, "\""),
+            (StringFragment, "}"),
+            (StringEnd, "\""),
+            (Punctuation, "}"),
+            (Punctuation, "."),
+            (Identifier, "b"),
+            (InterpolationEnd, "~}"),
+            (StringFragment, "!"),
+            (StringEnd, "\""),
+        ],
+        kinds("a = \"x \\\"$${y} ${{b = \"}\"}.b ~}!\"")
+    );
+    assert_eq!(
+        vec![
+            (StringStart, "\""),
+            (InterpolationStart, "${"),
+            (Identifier, "HH"),
+            (StringFragment, "\\\\"),
+            (Punctuation, ":"),
+            (Identifier, "mm"),
+            (InterpolationEnd, "}"),
+            (StringEnd, "\""),
+        ],
+        kinds("\"${HH\\\\:mm}\"")
+    );
+}
+
+#[test]
+fn lexer_heredocs() {
+    use self::TokenKind::*;
+    assert_eq!(
+        vec![
+            (Identifier, "a"),
+            (Punctuation, "="),
+            (HeredocStart, "<<-EOT\n"),
+            (HeredocBody, "  hello "),
+            (InterpolationStart, "${"),
+            (Identifier, "name"),
+            (InterpolationEnd, "}"),
+            (HeredocBody, "\n"),
+            (HeredocEnd, "  EOT"),
+            (Newline, "\n"),
+            (Identifier, "b"),
+            (Punctuation, "="),
+            (HeredocStart, "<<EOF\r\n"),
+            (HeredocEnd, "EOF"),
+        ],
+        kinds("a = <<-EOT\n  hello ${name}\n  EOT\nb = <<EOF\r\nEOF")
+    );
+    assert_eq!(
+        vec![
+            (HeredocStart, "<<EOT\n"),
+            (HeredocBody, "x\n"),
+            (HeredocEnd, "EOT"),
+            (Newline, "\n"),
+        ],
+        kinds("<<EOT\nx\nEOT  \n")
+    );
+    assert_eq!(
+        vec![
+            (Identifier, "a"),
+            (Punctuation, "="),
+            (HeredocStart, "<<EOF \t\n"),
+            (HeredocBody, "x\n"),
+            (HeredocEnd, "EOF"),
+        ],
+        kinds("a = <<EOF \t\nx\nEOF")
+    );
+}
+
+#[test]
+fn lexer_spans() {
+    let tokens = tokenize("\u{feff}key = \"v\"").unwrap();
+    let spans: Vec<_> = tokens.iter().map(|t| (t.span.start, t.span.end)).collect();
+    assert_eq!(vec![(3, 6), (7, 8), (9, 10), (10, 11), (11, 12)], spans);
+}
+
+#[test]
+fn lexer_errors() {
+    let error = |source: &str| tokenize(source).unwrap_err();
+    assert_eq!(
+        ParseError::new(ParseErrorKind::Syntax, 6),
+        error("a = \"b\nc\"")
+    );
+    assert_eq!(ParseError::new(ParseErrorKind::Syntax, 4), error("a = @"));
+    assert_eq!(
+        ParseError::new(ParseErrorKind::Syntax, 4),
+        error("a = /* b")
+    );
+    assert_eq!(
+        ParseError::new(ParseErrorKind::Syntax, 8),
+        error("a = \"${b")
+    );
+    assert_eq!(
+        ParseError::new(ParseErrorKind::HeredocUnterminated("EOT".to_string()), 4),
+        error("a = <<EOT\nb\n")
+    );
+    assert_eq!(
+        ParseError::new(ParseErrorKind::HeredocMarker, 6),
+        error("a = <<\nEOT")
+    );
+    // nothing follows an error
+    let mut lexer = Lexer::new("@ a");
+    assert!(lexer.next().unwrap().is_err());
+    assert_eq!(None, lexer.next());
+}
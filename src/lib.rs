@@ -3,7 +3,26 @@ extern crate nom;
 
 pub mod types;
 
+#[cfg(feature = "async")]
+pub mod async_io;
+
 #[macro_use]
 mod common;
+pub mod comments;
+pub mod config;
+pub mod dialect;
+pub mod expr;
+pub mod graph;
 pub mod hcl;
+pub mod hcl2;
+#[cfg(feature = "include")]
+pub mod include;
 pub mod json;
+pub mod json_schema;
+pub mod lexer;
+pub mod merge;
+pub mod parser;
+pub mod schema;
+pub mod template;
+pub mod terraform;
+pub mod tfvars;
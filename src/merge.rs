@@ -0,0 +1,531 @@
+//! Merging the files of a configuration into one body, the way Terraform
+//! and Nomad treat every file in a directory as part of a single body.
+//!
+//! Files are taken in lexical order of their names. Blocks from different
+//! files are concatenated, as repeated blocks within a file are, but an
+//! attribute, or a labeled block, may only be defined once.
+//!
+//! Override files, named `override.tf` or `*_override.tf` (and likewise for
+//! other extensions), are applied afterwards, following Terraform's rules:
+//! each of their blocks patches the existing block with the same type and
+//! labels. An attribute in the override replaces the original one, and
+//! nested blocks of a type replace all the original nested blocks of that
+//! type. An unlabeled block, such as `locals`, patches whichever block of its
+//! type defines each attribute. Top-level attributes simply replace.
+//!
+//! `.tf` files are parsed as HCL2 and `.tf.json` files as JSON, where the
+//! blocks of Terraform's top-level block types are objects keyed by their
+//! labels; any other file is parsed as HCL1.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::parser::{wrap_block, Item, ParserOptions};
+use crate::types::{JsonValue, ParseError};
+use crate::{hcl, hcl2, json};
+
+// Terraform's top-level block types and how many labels each has
+const JSON_BLOCKS: &[(&str, usize)] = &[
+    ("check", 1),
+    ("data", 2),
+    ("import", 0),
+    ("locals", 0),
+    ("module", 1),
+    ("moved", 0),
+    ("output", 1),
+    ("provider", 1),
+    ("removed", 0),
+    ("resource", 2),
+    ("terraform", 0),
+    ("variable", 1),
+];
+
+/// A parsed file, with its items kept apart until merged.
+pub struct File {
+    name: String,
+    items: Vec<(usize, Item)>,
+}
+
+impl File {
+    /// Parses a file; `name` identifies it in conflicts, decides whether it
+    /// is an override file and, by its extension, which syntax it is in.
+    pub fn parse(name: &str, source: &str) -> Result<File, ParseError> {
+        let options = ParserOptions::default();
+        let lower = name.to_lowercase();
+        let items = if lower.ends_with(".tf.json") {
+            json_blocks(json::parse_items(source, &options)?)
+        } else if lower.ends_with(".tf") {
+            hcl2::parse_items(source, &options)?
+        } else {
+            hcl::parse_items(source, &options)?
+        };
+        Ok(File {
+            name: name.to_string(),
+            items,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_override(&self) -> bool {
+        let base = self.name.rsplit(['/', '\\']).next().unwrap_or(&self.name);
+        let stem = base.split('.').next().unwrap_or(base);
+        stem == "override" || stem.ends_with("_override")
+    }
+}
+
+// turns the members of a `.tf.json` file naming block types into their blocks
+fn json_blocks(items: Vec<(usize, Item)>) -> Vec<(usize, Item)> {
+    let mut out = Vec::new();
+    for (offset, item) in items {
+        let labels = match item {
+            Item::Attribute(ref name, _) => JSON_BLOCKS
+                .iter()
+                .find(|&&(block_type, _)| block_type == name)
+                .map(|&(_, labels)| labels),
+            Item::Block(..) => None,
+        };
+        match (item, labels) {
+            (Item::Attribute(block_type, value), Some(labels)) => {
+                let mut blocks = Vec::new();
+                collect(value, labels, Vec::new(), &mut blocks);
+                for (labels, body) in blocks {
+                    out.push((offset, Item::Block(block_type.clone(), labels, body)));
+                }
+            }
+            (item, _) => out.push((offset, item)),
+        }
+    }
+    out
+}
+
+// the bodies under `value` with their labels, whether blocks are given as
+// objects or lists of them
+fn collect(
+    value: JsonValue,
+    labels: usize,
+    found: Vec<String>,
+    out: &mut Vec<(Vec<String>, HashMap<String, JsonValue>)>,
+) {
+    match value {
+        JsonValue::Array(items) => {
+            for item in items {
+                collect(item, labels, found.clone(), out);
+            }
+        }
+        JsonValue::Object(body) if labels == 0 => out.push((found, body)),
+        JsonValue::Object(labeled) => {
+            let mut labeled: Vec<_> = labeled.into_iter().collect();
+            labeled.sort_by(|a, b| a.0.cmp(&b.0));
+            for (label, value) in labeled {
+                let mut found = found.clone();
+                found.push(label);
+                collect(value, labels - 1, found, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Where an item is defined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub offset: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.file, self.offset)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConflictKind {
+    /// An attribute, or a name used both for an attribute and blocks.
+    DuplicateAttribute(String),
+    /// A labeled block, such as `resource["aws_instance"]["web"]`.
+    DuplicateBlock(String),
+    /// An override block with no block of its type and labels to patch, or
+    /// an attribute of an unlabeled override block, such as `locals.a`, that
+    /// no block defines.
+    NothingToOverride(String),
+}
+
+/// Why files could not be merged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    pub at: Location,
+    /// The definition this one conflicts with.
+    pub previous: Option<Location>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ConflictKind::DuplicateAttribute(ref name) => {
+                write!(f, "duplicate attribute `{}`", name)
+            }
+            ConflictKind::DuplicateBlock(ref path) => write!(f, "duplicate block `{}`", path),
+            ConflictKind::NothingToOverride(ref path) => {
+                write!(f, "no `{}` to override", path)
+            }
+        }?;
+        write!(f, " in {}", self.at)?;
+        if let Some(ref previous) = self.previous {
+            write!(f, ", first defined in {}", previous)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+struct Block {
+    block_type: String,
+    labels: Vec<String>,
+    body: HashMap<String, JsonValue>,
+    at: Location,
+}
+
+#[derive(Default)]
+struct Merged {
+    attributes: HashMap<String, (JsonValue, Location)>,
+    blocks: Vec<Block>,
+}
+
+/// Merges files into one body, in the shape `parse_hcl` gives a single file.
+pub fn merge(mut files: Vec<File>) -> Result<JsonValue, Vec<Conflict>> {
+    files.sort_by(|a, b| (a.is_override(), &a.name).cmp(&(b.is_override(), &b.name)));
+    let mut merged = Merged::default();
+    let mut conflicts = Vec::new();
+    for file in files {
+        let is_override = file.is_override();
+        for (offset, item) in file.items {
+            let at = Location {
+                file: file.name.clone(),
+                offset,
+            };
+            let conflict = if is_override {
+                merged.apply_override(item, at)
+            } else {
+                merged.add(item, at)
+            };
+            conflicts.extend(conflict);
+        }
+    }
+    if !conflicts.is_empty() {
+        return Err(conflicts);
+    }
+    let mut body = HashMap::new();
+    for (name, (value, _)) in merged.attributes {
+        body.insert(name, value);
+    }
+    for block in merged.blocks {
+        let blocks = body
+            .entry(block.block_type)
+            .or_insert_with(|| JsonValue::Array(Vec::new()));
+        if let JsonValue::Array(ref mut blocks) = *blocks {
+            blocks.push(wrap_block(block.labels, block.body));
+        }
+    }
+    Ok(JsonValue::Object(body))
+}
+
+fn block_path(block_type: &str, labels: &[String]) -> String {
+    let mut path = block_type.to_string();
+    for label in labels {
+        path.push_str(&format!("[\"{}\"]", label));
+    }
+    path
+}
+
+impl Merged {
+    fn add(&mut self, item: Item, at: Location) -> Option<Conflict> {
+        match item {
+            Item::Attribute(name, value) => {
+                let previous = match self.attributes.get(&name) {
+                    Some((_, previous)) => Some(previous.clone()),
+                    None => self.find(&name, None).map(|b| b.at.clone()),
+                };
+                if previous.is_some() {
+                    return Some(Conflict {
+                        kind: ConflictKind::DuplicateAttribute(name),
+                        at,
+                        previous,
+                    });
+                }
+                self.attributes.insert(name, (value, at));
+            }
+            Item::Block(block_type, labels, body) => {
+                if let Some((_, previous)) = self.attributes.get(&block_type) {
+                    return Some(Conflict {
+                        kind: ConflictKind::DuplicateAttribute(block_type),
+                        at,
+                        previous: Some(previous.clone()),
+                    });
+                }
+                // unlabeled blocks may repeat
+                if !labels.is_empty() {
+                    if let Some(previous) = self.find(&block_type, Some(&labels)) {
+                        return Some(Conflict {
+                            kind: ConflictKind::DuplicateBlock(block_path(&block_type, &labels)),
+                            at,
+                            previous: Some(previous.at.clone()),
+                        });
+                    }
+                }
+                self.blocks.push(Block {
+                    block_type,
+                    labels,
+                    body,
+                    at,
+                });
+            }
+        }
+        None
+    }
+
+    fn apply_override(&mut self, item: Item, at: Location) -> Option<Conflict> {
+        match item {
+            Item::Attribute(name, value) => {
+                if let Some(block) = self.find(&name, None) {
+                    return Some(Conflict {
+                        kind: ConflictKind::DuplicateAttribute(name),
+                        at,
+                        previous: Some(block.at.clone()),
+                    });
+                }
+                self.attributes.insert(name, (value, at));
+            }
+            Item::Block(block_type, labels, body) if labels.is_empty() => {
+                if self.find(&block_type, None).is_none() {
+                    return Some(Conflict {
+                        kind: ConflictKind::NothingToOverride(block_type),
+                        at,
+                        previous: None,
+                    });
+                }
+                for (name, value) in body {
+                    let target = match self
+                        .blocks
+                        .iter()
+                        .position(|b| b.block_type == block_type && b.body.contains_key(&name))
+                    {
+                        Some(i) => i,
+                        None => {
+                            return Some(Conflict {
+                                kind: ConflictKind::NothingToOverride(format!(
+                                    "{}.{}",
+                                    block_type, name
+                                )),
+                                at,
+                                previous: None,
+                            })
+                        }
+                    };
+                    self.blocks[target].body.insert(name, value);
+                }
+            }
+            Item::Block(block_type, labels, body) => {
+                match self
+                    .blocks
+                    .iter_mut()
+                    .find(|b| b.block_type == block_type && b.labels == labels)
+                {
+                    // attributes and nested block types replace theirs wholesale
+                    Some(block) => block.body.extend(body),
+                    None => {
+                        return Some(Conflict {
+                            kind: ConflictKind::NothingToOverride(block_path(&block_type, &labels)),
+                            at,
+                            previous: None,
+                        })
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn find(&self, block_type: &str, labels: Option<&[String]>) -> Option<&Block> {
+        self.blocks
+            .iter()
+            .find(|b| b.block_type == block_type && labels.is_none_or(|l| b.labels == l))
+    }
+}
+
+#[cfg(test)]
+fn files(sources: &[(&str, &str)]) -> Vec<File> {
+    sources
+        .iter()
+        .map(|&(name, source)| File::parse(name, source).unwrap())
+        .collect()
+}
+
+#[test]
+fn merge_concatenates_files() {
+    let a = "region = \"eu\"\nvariable \"a\" {\n  default = 1\n}\nlocals {\n  x = 1\n}\n";
+    let b = "variable \"b\" {\n  default = 2\n}\nlocals {\n  y = 2\n}\n";
+    let merged = merge(files(&[("b.tf", b), ("a.tf", a)])).unwrap();
+    let together = crate::hcl::parse_hcl(&format!("{}{}", a, b)).unwrap();
+    assert_eq!(together, merged);
+}
+
+#[test]
+fn merge_conflicts() {
+    let a = "region = \"eu\"\nresource \"aws_instance\" \"web\" {}\n";
+    let b = "\nregion = \"us\"\n\nresource \"aws_instance\" \"web\" {}\nresource \"aws_instance\" \"db\" {}\n";
+    let conflicts: Vec<String> = merge(files(&[("a.tf", a), ("b.tf", b)]))
+        .unwrap_err()
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+    assert_eq!(
+        vec![
+            "duplicate attribute `region` in b.tf at byte 1, first defined in a.tf at byte 0",
+            "duplicate block `resource[\"aws_instance\"][\"web\"]` in b.tf at byte 16, \
+             first defined in a.tf at byte 14",
+        ],
+        conflicts
+    );
+}
+
+#[test]
+fn merge_overrides() {
+    let main = "resource \"aws_instance\" \"web\" {
+  ami           = \"ami-1\"
+  instance_type = \"t2.micro\"
+  ebs_block_device {
+    device_name = \"sda\"
+  }
+  ebs_block_device {
+    device_name = \"sdb\"
+  }
+}
+locals {
+  a = 1
+}
+locals {
+  b = 2
+}
+";
+    let over = "resource \"aws_instance\" \"web\" {
+  ami = \"ami-2\"
+  ebs_block_device {
+    device_name = \"sdc\"
+  }
+}
+locals {
+  b = 3
+}
+";
+    // override files apply last, whatever their name
+    let merged = merge(files(&[("main_override.tf", over), ("main.tf", main)])).unwrap();
+    let expected = crate::hcl::parse_hcl(
+        "resource \"aws_instance\" \"web\" {
+  ami           = \"ami-2\"
+  instance_type = \"t2.micro\"
+  ebs_block_device {
+    device_name = \"sdc\"
+  }
+}
+locals {
+  a = 1
+}
+locals {
+  b = 3
+}
+",
+    )
+    .unwrap();
+    assert_eq!(expected, merged);
+
+    let conflicts = merge(files(&[
+        ("main.tf", main),
+        (
+            "override.tf",
+            "resource \"aws_instance\" \"db\" {}\nmodule {}\n",
+        ),
+    ]))
+    .unwrap_err();
+    let messages: Vec<String> = conflicts.iter().map(|c| c.to_string()).collect();
+    assert_eq!(
+        vec![
+            "no `resource[\"aws_instance\"][\"db\"]` to override in override.tf at byte 0",
+            "no `module` to override in override.tf at byte 32",
+        ],
+        messages
+    );
+
+    let conflicts = merge(files(&[
+        (
+            "main.tf",
+            "ingress {\n  port = 1\n}\nlocals {\n  a = 1\n}\n",
+        ),
+        ("override.tf", "ingress = [1]\nlocals {\n  c = 3\n}\n"),
+    ]))
+    .unwrap_err();
+    let messages: Vec<String> = conflicts.iter().map(|c| c.to_string()).collect();
+    assert_eq!(
+        vec![
+            "duplicate attribute `ingress` in override.tf at byte 0, \
+             first defined in main.tf at byte 0",
+            "no `locals.c` to override in override.tf at byte 14",
+        ],
+        messages
+    );
+}
+
+#[test]
+fn merge_hcl2_and_json() {
+    let main = "variable \"region\" {
+  default = \"eu\"
+}
+resource \"aws_instance\" \"web\" {
+  ami  = var.ami
+  tags = { Name = \"web\" }
+}
+";
+    let zone = r#"{"variable": {"zone": {}}, "locals": [{"a": 1}]}"#;
+    let over = r#"{"resource": {"aws_instance": {"web": {"ami": "ami-2"}}}}"#;
+    let merged = merge(files(&[
+        ("main.tf", main),
+        ("zone.tf.json", zone),
+        ("main_override.tf.json", over),
+    ]))
+    .unwrap();
+    let expected = crate::hcl2::parse_hcl2(
+        "variable \"region\" {
+  default = \"eu\"
+}
+variable \"zone\" {}
+resource \"aws_instance\" \"web\" {
+  ami  = \"ami-2\"
+  tags = { Name = \"web\" }
+}
+locals {
+  a = 1
+}
+",
+    )
+    .unwrap();
+    assert_eq!(expected, merged);
+
+    let region = r#"{"variable": {"region": {}}}"#;
+    let conflicts: Vec<String> = merge(files(&[("main.tf", main), ("region.tf.json", region)]))
+        .unwrap_err()
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+    assert_eq!(
+        vec![
+            "duplicate block `variable[\"region\"]` in region.tf.json at byte 1, \
+             first defined in main.tf at byte 0"
+        ],
+        conflicts
+    );
+}
@@ -0,0 +1,557 @@
+//! The `Parser` builder, and the body building its options control.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+
+use crate::comments::{extract_comments, Comments};
+use crate::common::{fail_at, offset, options, warn_at};
+use crate::types::{JsonValue, ParseError, ParseErrorKind, ReadError};
+use crate::{hcl, hcl2, json};
+
+/// What to do when a body defines the same attribute twice, or two blocks
+/// with the same type and labels. Unlabeled blocks may always repeat.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicateKeys {
+    /// Fail, reporting where the key was first defined.
+    Error,
+    /// Keep the first definition, extended by the lists and blocks that
+    /// directly follow it if it is one, as HCL1 always has, and report a
+    /// warning.
+    Warn,
+    /// Keep the first definition.
+    FirstWins,
+    /// Keep the last definition.
+    LastWins,
+    /// Merge objects and blocks key by key, later definitions winning.
+    MergeObjects,
+}
+
+/// What becomes of `${...}` interpolations in strings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolations {
+    /// Kept as written, without looking inside.
+    Raw,
+    /// Parsed as expressions, so syntax errors are reported with the rest of
+    /// the file. Values still hold the source text, ready for the template engine.
+    Parsed,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ParserOptions {
+    pub duplicate_keys: DuplicateKeys,
+    pub strict: bool,
+    pub multiline_strings: bool,
+    pub max_depth: Option<usize>,
+    pub max_input_size: Option<usize>,
+    pub max_string_length: Option<usize>,
+    pub max_array_length: Option<usize>,
+    pub interpolations: Interpolations,
+}
+
+/// A depth limit for untrusted input: deep enough for any real
+/// configuration, shallow enough not to overflow the 2 MiB stack of a
+/// spawned thread, even in a debug build.
+pub const SAFE_MAX_DEPTH: usize = 24;
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions {
+            duplicate_keys: DuplicateKeys::Warn,
+            strict: false,
+            multiline_strings: false,
+            max_depth: None,
+            max_input_size: None,
+            max_string_length: None,
+            max_array_length: None,
+            interpolations: Interpolations::Raw,
+        }
+    }
+}
+
+/// Configures and runs the HCL1 parser. `Parser::new()` parses the way
+/// `parse_hcl` does.
+#[derive(Clone, Debug, Default)]
+pub struct Parser {
+    pub(crate) options: ParserOptions,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser::default()
+    }
+
+    /// In strict mode unquoted keys are identifiers: a letter or underscore,
+    /// then letters, digits and underscores. Otherwise `-` and `.` are also
+    /// accepted anywhere in them, as older HCL1 files expect.
+    pub fn strict(mut self, strict: bool) -> Parser {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Whether quoted strings may span lines.
+    pub fn multiline_strings(mut self, allow: bool) -> Parser {
+        self.options.multiline_strings = allow;
+        self
+    }
+
+    /// How deeply objects, lists and blocks may nest, unlimited unless set.
+    /// Parsing recurses, so hostile input can overflow the stack unless the
+    /// depth is limited, to `SAFE_MAX_DEPTH` for instance.
+    pub fn max_depth(mut self, depth: Option<usize>) -> Parser {
+        self.options.max_depth = depth;
+        self
+    }
+
+    /// The largest input, in bytes, that will be parsed.
+    pub fn max_input_size(mut self, size: usize) -> Parser {
+        self.options.max_input_size = Some(size);
+        self
+    }
+
+    /// The longest string, in bytes once unescaped, that will be accepted.
+    pub fn max_string_length(mut self, len: usize) -> Parser {
+        self.options.max_string_length = Some(len);
+        self
+    }
+
+    /// The most items a list may have.
+    pub fn max_array_length(mut self, len: usize) -> Parser {
+        self.options.max_array_length = Some(len);
+        self
+    }
+
+    pub fn interpolations(mut self, interpolations: Interpolations) -> Parser {
+        self.options.interpolations = interpolations;
+        self
+    }
+
+    pub fn duplicate_keys(mut self, duplicate_keys: DuplicateKeys) -> Parser {
+        self.options.duplicate_keys = duplicate_keys;
+        self
+    }
+
+    pub fn parse(&self, config: &str) -> Result<JsonValue, ParseError> {
+        self.parse_bytes(config.as_bytes())
+    }
+
+    /// Parses raw bytes, which must be UTF-8 text; binary and git-crypt
+    /// encrypted files are recognised and reported as such.
+    pub fn parse_bytes(&self, config: &[u8]) -> Result<JsonValue, ParseError> {
+        hcl::parse_with(config, &self.options).map(|(value, _)| value)
+    }
+
+    /// Reads `reader` to the end and parses what it produced. With an input
+    /// size limit, no more than one byte past the limit is read.
+    pub fn parse_reader<R: Read>(&self, reader: R) -> Result<JsonValue, ReadError> {
+        let config = read_limited(reader, self.options.max_input_size)?;
+        Ok(self.parse_bytes(&config)?)
+    }
+
+    /// Parses JSON with this parser's limits and duplicate key policy;
+    /// `parse_json` lets the last duplicate key win.
+    pub fn parse_json(&self, config: &str) -> Result<JsonValue, ParseError> {
+        json::parse_json_with(config.as_bytes(), &self.options)
+    }
+
+    /// Parses HCL2 native syntax with this parser's limits. Like
+    /// `parse_hcl2`, duplicate keys are errors.
+    pub fn parse_hcl2(&self, config: &str) -> Result<JsonValue, ParseError> {
+        hcl2::parse_hcl2_with(config, &self.options)
+    }
+
+    /// Parses, also returning the comments documenting attributes and blocks.
+    pub fn parse_with_comments(&self, config: &str) -> Result<(JsonValue, Comments), ParseError> {
+        let value = self.parse(config)?;
+        Ok((value, extract_comments(config)?))
+    }
+
+    /// Parses, also returning problems that did not stop the parse, such as
+    /// duplicate keys under `DuplicateKeys::Warn`.
+    pub fn parse_with_warnings(
+        &self,
+        config: &str,
+    ) -> Result<(JsonValue, Vec<ParseError>), ParseError> {
+        hcl::parse_with(config.as_bytes(), &self.options)
+    }
+}
+
+pub(crate) fn read_limited<R: Read>(reader: R, max: Option<usize>) -> io::Result<Vec<u8>> {
+    let mut config = Vec::new();
+    match max {
+        Some(max) => reader.take(max as u64 + 1).read_to_end(&mut config)?,
+        None => {
+            let mut reader = reader;
+            reader.read_to_end(&mut config)?
+        }
+    };
+    Ok(config)
+}
+
+pub(crate) enum Item {
+    Attribute(String, JsonValue),
+    Block(String, Vec<String>, HashMap<String, JsonValue>),
+}
+
+/// Wraps a block body in its labels, the way HCL1 represents blocks.
+pub(crate) fn wrap_block(labels: Vec<String>, body: HashMap<String, JsonValue>) -> JsonValue {
+    let mut cur = JsonValue::Object(body);
+    for label in labels.into_iter().rev() {
+        let mut h = HashMap::new();
+        h.insert(label, JsonValue::Array(vec![cur]));
+        cur = JsonValue::Object(h);
+    }
+    cur
+}
+
+/// Merges `b` into `a`. Objects merge key by key, as do the single object
+/// lists HCL1 makes of blocks and assigned objects; anything else is replaced.
+pub(crate) fn merge_objects(a: &mut JsonValue, b: JsonValue) {
+    match (a, b) {
+        (&mut JsonValue::Object(ref mut a), JsonValue::Object(b)) => {
+            for (k, v) in b {
+                match a.get_mut(&k) {
+                    Some(existing) => merge_objects(existing, v),
+                    None => {
+                        a.insert(k, v);
+                    }
+                }
+            }
+        }
+        (&mut JsonValue::Array(ref mut a), JsonValue::Array(mut b))
+            if a.len() == 1
+                && b.len() == 1
+                && matches!(a[0], JsonValue::Object(_))
+                && matches!(b[0], JsonValue::Object(_)) =>
+        {
+            merge_objects(&mut a[0], b.remove(0))
+        }
+        (a, b) => *a = b,
+    }
+}
+
+fn duplicate(key: &str, first: usize) -> ParseErrorKind {
+    ParseErrorKind::DuplicateKey(key.to_string(), offset(first))
+}
+
+/// Builds a body from its items, at the addresses they start at, applying the
+/// duplicate key policy. Returns `None` when the policy fails the parse.
+pub(crate) fn build_body(items: Vec<(usize, Item)>) -> Option<HashMap<String, JsonValue>> {
+    let policy = options(|o| o.duplicate_keys);
+    let mut top: HashMap<String, JsonValue> = HashMap::new();
+    let mut attributes: HashMap<String, usize> = HashMap::new();
+    let mut blocks: HashMap<(String, Vec<String>), (usize, usize)> = HashMap::new();
+    let mut block_names: HashMap<String, usize> = HashMap::new();
+    // names whose value later lists may no longer extend, under `Warn`
+    let mut sealed: HashSet<String> = HashSet::new();
+
+    for (addr, item) in items {
+        match item {
+            Item::Attribute(name, value) => {
+                let first = attributes
+                    .get(&name)
+                    .or_else(|| block_names.get(&name))
+                    .cloned();
+                let first = match first {
+                    Some(first) => first,
+                    None => {
+                        attributes.insert(name.clone(), addr);
+                        top.insert(name, value);
+                        continue;
+                    }
+                };
+                let existing = top.get_mut(&name).expect("defined names have values");
+                match policy {
+                    DuplicateKeys::Error => {
+                        fail_at(addr, duplicate(&name, first));
+                        return None;
+                    }
+                    DuplicateKeys::Warn => {
+                        warn_at(addr, duplicate(&name, first));
+                        match (existing, value) {
+                            (&mut JsonValue::Array(ref mut a), JsonValue::Array(b))
+                                if !sealed.contains(&name) =>
+                            {
+                                a.extend(b)
+                            }
+                            _ => {
+                                sealed.insert(name);
+                            }
+                        }
+                    }
+                    DuplicateKeys::FirstWins => {}
+                    DuplicateKeys::LastWins => *existing = value,
+                    DuplicateKeys::MergeObjects => merge_objects(existing, value),
+                }
+            }
+            Item::Block(name, labels, body) => {
+                if let Some(&first) = attributes.get(&name) {
+                    match policy {
+                        DuplicateKeys::Error => {
+                            fail_at(addr, duplicate(&name, first));
+                            return None;
+                        }
+                        DuplicateKeys::Warn => warn_at(addr, duplicate(&name, first)),
+                        _ => {}
+                    }
+                    if policy == DuplicateKeys::FirstWins {
+                        continue;
+                    }
+                    if policy == DuplicateKeys::Warn
+                        && (sealed.contains(&name) || !matches!(top[&name], JsonValue::Array(_)))
+                    {
+                        sealed.insert(name);
+                        continue;
+                    }
+                    // the attribute gives way to blocks, unless it was a list they extend
+                    if !matches!(top[&name], JsonValue::Array(_))
+                        || policy == DuplicateKeys::LastWins
+                    {
+                        top.insert(name.clone(), JsonValue::Array(Vec::new()));
+                    }
+                    attributes.remove(&name);
+                }
+
+                if sealed.contains(&name) {
+                    continue;
+                }
+
+                let key = (name.clone(), labels.clone());
+                let list = match top
+                    .entry(name.clone())
+                    .or_insert_with(|| JsonValue::Array(Vec::new()))
+                {
+                    JsonValue::Array(ref mut list) => list,
+                    _ => unreachable!("blocks are kept in lists"),
+                };
+                block_names.entry(name.clone()).or_insert(addr);
+
+                let previous = if labels.is_empty() {
+                    None
+                } else {
+                    blocks.get(&key).cloned()
+                };
+                let (first, index) = match previous {
+                    Some(previous) => previous,
+                    None => {
+                        blocks.insert(key, (addr, list.len()));
+                        list.push(wrap_block(labels, body));
+                        continue;
+                    }
+                };
+                let path = format!("{} \"{}\"", name, labels.join("\" \""));
+                match policy {
+                    DuplicateKeys::Error => {
+                        fail_at(addr, duplicate(&path, first));
+                        return None;
+                    }
+                    DuplicateKeys::Warn => {
+                        warn_at(addr, duplicate(&path, first));
+                        list.push(wrap_block(labels, body));
+                    }
+                    DuplicateKeys::FirstWins => {}
+                    DuplicateKeys::LastWins => list[index] = wrap_block(labels, body),
+                    DuplicateKeys::MergeObjects => {
+                        merge_objects(&mut list[index], wrap_block(labels, body))
+                    }
+                }
+            }
+        }
+    }
+    Some(top)
+}
+
+#[cfg(test)]
+fn parse_with(config: &str, duplicate_keys: DuplicateKeys) -> Result<JsonValue, ParseError> {
+    Parser::new().duplicate_keys(duplicate_keys).parse(config)
+}
+
+#[cfg(test)]
+const POLICY: &str = "key \"foo/\" {
+  policy = \"read\"
+  policy = \"write\"
+}
+
+key \"foo/\" {
+  policy = \"deny\"
+  comment = \"again\"
+}
+";
+
+#[test]
+fn duplicate_keys_error() {
+    assert_eq!(
+        Err(ParseError::new(
+            ParseErrorKind::DuplicateKey("policy".to_string(), 15),
+            33
+        )),
+        parse_with(POLICY, DuplicateKeys::Error)
+    );
+    assert_eq!(
+        Err(ParseError::new(
+            ParseErrorKind::DuplicateKey("key \"foo/\"".to_string(), 0),
+            34
+        )),
+        parse_with(
+            &POLICY.replace("  policy = \"write\"\n", ""),
+            DuplicateKeys::Error
+        )
+    );
+    // repeated unlabeled blocks and differently labeled blocks are fine
+    let test = "ingress { port = 80 }\ningress { port = 443 }\nkey \"a\" {}\nkey \"b\" {}";
+    assert!(parse_with(test, DuplicateKeys::Error).is_ok());
+}
+
+#[test]
+fn duplicate_keys_warn() {
+    let (value, warnings) = Parser::new().parse_with_warnings(POLICY).unwrap();
+    assert_eq!(
+        vec![
+            ParseError::new(ParseErrorKind::DuplicateKey("policy".to_string(), 15), 33),
+            ParseError::new(
+                ParseErrorKind::DuplicateKey("key \"foo/\"".to_string(), 0),
+                53
+            ),
+        ],
+        warnings
+    );
+    let expected = crate::hcl::parse_hcl(
+        "key \"foo/\" { policy = \"read\" }\nkey \"foo/\" {\npolicy = \"deny\"\ncomment = \"again\"\n}",
+    );
+    assert_eq!(expected, Ok(value));
+}
+
+#[test]
+fn duplicate_keys_default() {
+    // the first definition wins, as it always has
+    let parse = |config: &str| crate::hcl::parse_hcl(config).unwrap();
+    assert_eq!(parse("a = 1"), parse("a = 1\na = 2"));
+    assert_eq!(parse("a { b = 1 }"), parse("a { b = 1 }\na = 2"));
+    assert_eq!(parse("a = 1"), parse("a = 1\na = [2]"));
+    assert_eq!(
+        parse("a = [1, 2]"),
+        parse("a = [1]\na = [2]\na = 3\na = [4]")
+    );
+}
+
+#[test]
+fn duplicate_keys_resolved() {
+    let policy = |config: &str, duplicate_keys| {
+        let expected = crate::hcl::parse_hcl(config).unwrap();
+        assert_eq!(Ok(expected), parse_with(POLICY, duplicate_keys));
+    };
+    policy(
+        "key \"foo/\" { policy = \"read\" }",
+        DuplicateKeys::FirstWins,
+    );
+    policy(
+        "key \"foo/\" {\npolicy = \"deny\"\ncomment = \"again\"\n}",
+        DuplicateKeys::LastWins,
+    );
+    policy(
+        "key \"foo/\" {\npolicy = \"deny\"\ncomment = \"again\"\n}",
+        DuplicateKeys::MergeObjects,
+    );
+
+    let test = "tags = { a = 1 }\ntags = { b = 2 }";
+    assert_eq!(
+        crate::hcl::parse_hcl("tags = { a = 1\nb = 2 }"),
+        parse_with(test, DuplicateKeys::MergeObjects)
+    );
+}
+
+#[test]
+fn duplicate_keys_json() {
+    if let Ok(JsonValue::Object(dict)) = crate::json::parse_json("{\"a\": 1, \"a\": 2}") {
+        assert_eq!(Some(&JsonValue::Num(2.)), dict.get("a"));
+        // a parser keeps its own policy
+        let test = "{\"a\": 1, \"a\": 2}";
+        assert_eq!(
+            Err(ParseError::new(
+                ParseErrorKind::DuplicateKey("a".to_string(), 1),
+                9
+            )),
+            Parser::new()
+                .duplicate_keys(DuplicateKeys::Error)
+                .parse_json(test)
+        );
+        let mut first = HashMap::new();
+        first.insert("a".to_string(), JsonValue::Num(1.));
+        return assert_eq!(Ok(JsonValue::Object(first)), Parser::new().parse_json(test));
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+fn parser_strict_keys() {
+    let lenient = Parser::new();
+    let strict = Parser::new().strict(true);
+    for test in &["foo.bar = 1", "foo-bar = 1", "1up = 1"] {
+        assert!(lenient.parse(test).is_ok());
+        assert!(strict.parse(test).is_err());
+    }
+    assert!(strict.parse("_foo_1 = 1\n\"foo.bar\" = 2").is_ok());
+}
+
+#[test]
+fn parser_multiline_strings() {
+    let test = "a = \"hello\n  world\"";
+    assert!(Parser::new().parse(test).is_err());
+    if let Ok(JsonValue::Object(dict)) = Parser::new().multiline_strings(true).parse(test) {
+        return assert_eq!(
+            Some(&JsonValue::Str("hello\n  world".to_string())),
+            dict.get("a")
+        );
+    }
+    panic!("object did not parse");
+}
+
+#[test]
+fn parser_limits() {
+    let test = "a { b = [[1], 2] }";
+    assert!(Parser::new().max_depth(Some(3)).parse(test).is_ok());
+    assert_eq!(
+        Err(ParseError::new(
+            ParseErrorKind::LimitExceeded(crate::types::Limit::Depth),
+            9
+        )),
+        Parser::new().max_depth(Some(2)).parse(test)
+    );
+    assert!(Parser::new().max_input_size(18).parse(test).is_ok());
+    assert_eq!(
+        Err(ParseError::new(
+            ParseErrorKind::LimitExceeded(crate::types::Limit::InputSize),
+            17
+        )),
+        Parser::new().max_input_size(17).parse(test)
+    );
+    let test = "a = \"abc\"\nb = [1, 2, 3]";
+    assert!(Parser::new().max_string_length(3).parse(test).is_ok());
+    assert_eq!(
+        Err(ParseError::new(
+            ParseErrorKind::LimitExceeded(crate::types::Limit::StringLength),
+            4
+        )),
+        Parser::new().max_string_length(2).parse(test)
+    );
+    assert!(Parser::new().max_array_length(3).parse(test).is_ok());
+    assert_eq!(
+        Err(ParseError::new(
+            ParseErrorKind::LimitExceeded(crate::types::Limit::ArrayLength),
+            14
+        )),
+        Parser::new().max_array_length(2).parse(test)
+    );
+}
+
+#[test]
+fn parser_interpolations() {
+    let test = "a = \"${upper(var.name)} and ${~ b ~}\"\nc = \"x ${foo(} y\"";
+    assert!(Parser::new().parse(test).is_ok());
+    let parsed = Parser::new().interpolations(Interpolations::Parsed);
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::Interpolation, 45)),
+        parsed.parse(test)
+    );
+    let (value, _) = parsed.parse_with_warnings(&test[..37]).unwrap();
+    assert_eq!(crate::hcl::parse_hcl(&test[..37]), Ok(value));
+}
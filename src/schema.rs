@@ -0,0 +1,652 @@
+//! Checking parsed bodies against a declared schema.
+//!
+//! A schema lists the attributes a body may have, with their types, and the
+//! block types it may contain, with their label counts and own schemas.
+//! Validation reports every violation found, each with the key path it was
+//! found at, such as `variable["amis"].default`.
+//!
+//! A `Body` can also be decoded a part at a time: each layer of tooling takes
+//! the attributes and blocks its schema describes and hands the remaining
+//! body on to the next.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::types::{JsonValue, Type, TypeError};
+
+#[derive(Clone, Debug, PartialEq)]
+struct AttributeSchema {
+    ty: Type,
+    required: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct BlockSchema {
+    labels: usize,
+    body: Schema,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    attributes: BTreeMap<String, AttributeSchema>,
+    blocks: BTreeMap<String, BlockSchema>,
+}
+
+impl Schema {
+    pub fn new() -> Schema {
+        Schema::default()
+    }
+
+    /// Allows an attribute, which must be present.
+    pub fn required(mut self, name: &str, ty: Type) -> Schema {
+        let attribute = AttributeSchema { ty, required: true };
+        self.attributes.insert(name.to_string(), attribute);
+        self
+    }
+
+    /// Allows an attribute, which may be left out.
+    pub fn optional(mut self, name: &str, ty: Type) -> Schema {
+        let attribute = AttributeSchema {
+            ty,
+            required: false,
+        };
+        self.attributes.insert(name.to_string(), attribute);
+        self
+    }
+
+    /// Allows any number of blocks of a type, each with `labels` labels and
+    /// a body following `body`.
+    pub fn block(mut self, name: &str, labels: usize, body: Schema) -> Schema {
+        self.blocks
+            .insert(name.to_string(), BlockSchema { labels, body });
+        self
+    }
+
+    // the label count and body of a block type
+    pub(crate) fn block_schema(&self, name: &str) -> Option<(usize, &Schema)> {
+        self.blocks.get(name).map(|b| (b.labels, &b.body))
+    }
+
+    /// Checks a parsed body, as returned by `parse_hcl` or `parse_json`.
+    pub fn validate(&self, value: &JsonValue) -> Result<(), Vec<TypeError>> {
+        let mut violations = Vec::new();
+        match *value {
+            JsonValue::Object(ref body) => self.check_body("", body, &mut violations),
+            _ => violations.push(violation(
+                "",
+                format!("expected a body, found {}", value.type_name()),
+            )),
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn check_body(
+        &self,
+        path: &str,
+        body: &HashMap<String, JsonValue>,
+        violations: &mut Vec<TypeError>,
+    ) {
+        for (name, attribute) in &self.attributes {
+            if attribute.required && !body.contains_key(name) {
+                violations.push(violation(
+                    path,
+                    format!("attribute \"{}\" is required", name),
+                ));
+            }
+        }
+        let mut keys: Vec<&String> = body.keys().collect();
+        keys.sort();
+        for key in keys {
+            let value = &body[key];
+            let item_path = item_path(path, key);
+            if let Some(attribute) = self.attributes.get(key) {
+                if let Err(e) = attribute
+                    .ty
+                    .convert_at(&item_path, unwrap_object(&attribute.ty, value))
+                {
+                    violations.push(e);
+                }
+            } else if let Some(block) = self.blocks.get(key) {
+                block.check(&item_path, value, violations);
+            } else {
+                violations.push(violation(
+                    &item_path,
+                    "unexpected attribute or block".to_string(),
+                ));
+            }
+        }
+    }
+}
+
+impl BlockSchema {
+    fn check(&self, path: &str, value: &JsonValue, violations: &mut Vec<TypeError>) {
+        for (labels, body) in self.instances(path, value, violations) {
+            self.body
+                .check_body(&labeled_path(path, &labels), body, violations);
+        }
+    }
+
+    // the bodies of the blocks in `value` with their labels, whether in the
+    // lists hcl1 makes of them or plain objects
+    fn instances<'a>(
+        &self,
+        path: &str,
+        value: &'a JsonValue,
+        violations: &mut Vec<TypeError>,
+    ) -> Vec<(Vec<String>, &'a HashMap<String, JsonValue>)> {
+        let mut out = Vec::new();
+        self.collect(path, value, &mut Vec::new(), &mut out, violations);
+        out
+    }
+
+    fn collect<'a>(
+        &self,
+        path: &str,
+        value: &'a JsonValue,
+        labels: &mut Vec<String>,
+        out: &mut Vec<(Vec<String>, &'a HashMap<String, JsonValue>)>,
+        violations: &mut Vec<TypeError>,
+    ) {
+        match *value {
+            JsonValue::Array(ref items) => {
+                for item in items {
+                    self.collect(path, item, labels, out, violations);
+                }
+            }
+            JsonValue::Object(ref body) if labels.len() == self.labels => {
+                out.push((labels.clone(), body))
+            }
+            JsonValue::Object(ref h) => {
+                let mut keys: Vec<&String> = h.keys().collect();
+                keys.sort();
+                for label in keys {
+                    labels.push(label.clone());
+                    self.collect(path, &h[label], labels, out, violations);
+                    labels.pop();
+                }
+            }
+            // the last label taken was really an attribute, or a label is missing
+            _ => {
+                let parent = &labels[..labels.len().saturating_sub(1)];
+                let noun = if self.labels == 1 { "label" } else { "labels" };
+                violations.push(violation(
+                    &labeled_path(path, parent),
+                    format!("expected a block with {} {}", self.labels, noun),
+                ));
+            }
+        }
+    }
+}
+
+pub(crate) fn labeled_path(path: &str, labels: &[String]) -> String {
+    let mut out = path.to_string();
+    for label in labels {
+        out.push_str(&format!("[\"{}\"]", label));
+    }
+    out
+}
+
+pub(crate) fn item_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// A body, or what remains of one after partial decoding, with the path it
+/// is found at for reporting.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Body {
+    path: String,
+    items: HashMap<String, JsonValue>,
+}
+
+/// The part of a body a schema describes, attribute values converted to
+/// their types. Block bodies are left for the caller to decode in turn.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BodyContent {
+    pub attributes: BTreeMap<String, JsonValue>,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub block_type: String,
+    pub labels: Vec<String>,
+    pub body: Body,
+}
+
+impl Body {
+    /// The body of a parsed file, as returned by `parse_hcl` or `parse_json`.
+    pub fn new(value: &JsonValue) -> Result<Body, TypeError> {
+        match *value {
+            JsonValue::Object(ref items) => Ok(Body {
+                path: String::new(),
+                items: items.clone(),
+            }),
+            _ => Err(violation(
+                "",
+                format!("expected a body, found {}", value.type_name()),
+            )),
+        }
+    }
+
+    /// Decodes what `schema` describes, returning the rest of the body
+    /// untouched for another decoder.
+    pub fn partial_content(&self, schema: &Schema) -> Result<(BodyContent, Body), Vec<TypeError>> {
+        let mut violations = Vec::new();
+        let mut content = BodyContent::default();
+        let mut remaining = Body {
+            path: self.path.clone(),
+            items: HashMap::new(),
+        };
+        for (name, attribute) in &schema.attributes {
+            if attribute.required && !self.items.contains_key(name) {
+                violations.push(violation(
+                    &self.path,
+                    format!("attribute \"{}\" is required", name),
+                ));
+            }
+        }
+        for key in self.keys() {
+            let value = &self.items[key];
+            let path = item_path(&self.path, key);
+            if let Some(attribute) = schema.attributes.get(key) {
+                match attribute
+                    .ty
+                    .convert_at(&path, unwrap_object(&attribute.ty, value))
+                {
+                    Ok(v) => {
+                        content.attributes.insert(key.to_string(), v);
+                    }
+                    Err(e) => violations.push(e),
+                }
+            } else if let Some(block) = schema.blocks.get(key) {
+                for (labels, body) in block.instances(&path, value, &mut violations) {
+                    content.blocks.push(Block {
+                        block_type: key.to_string(),
+                        body: Body {
+                            path: labeled_path(&path, &labels),
+                            items: body.clone(),
+                        },
+                        labels,
+                    });
+                }
+            } else {
+                remaining.items.insert(key.to_string(), value.clone());
+            }
+        }
+        if violations.is_empty() {
+            Ok((content, remaining))
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Decodes the whole body; anything `schema` does not describe is a
+    /// violation.
+    pub fn content(&self, schema: &Schema) -> Result<BodyContent, Vec<TypeError>> {
+        let (content, remaining) = self.partial_content(schema)?;
+        let unexpected: Vec<TypeError> = remaining
+            .keys()
+            .into_iter()
+            .map(|key| {
+                violation(
+                    &item_path(&self.path, key),
+                    "unexpected attribute or block".to_string(),
+                )
+            })
+            .collect();
+        if unexpected.is_empty() {
+            Ok(content)
+        } else {
+            Err(unexpected)
+        }
+    }
+
+    /// The names of the attributes and block types in the body, sorted.
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.items.keys().map(|k| k.as_str()).collect();
+        keys.sort();
+        keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The body in the shape it was parsed in.
+    pub fn to_value(&self) -> JsonValue {
+        JsonValue::Object(self.items.clone())
+    }
+}
+
+impl Schema {
+    /// A draft-07 JSON Schema for bodies following this schema, in the shape
+    /// `parse_hcl` returns them and `hcl2json` prints them: blocks and
+    /// assigned objects are lists, and each block label nests a level.
+    pub fn to_json_schema(&self) -> JsonValue {
+        let mut document = match self.body_json_schema() {
+            JsonValue::Object(h) => h,
+            _ => unreachable!(),
+        };
+        document.insert(
+            "$schema".to_string(),
+            JsonValue::Str("http://json-schema.org/draft-07/schema#".to_string()),
+        );
+        JsonValue::Object(document)
+    }
+
+    fn body_json_schema(&self) -> JsonValue {
+        let mut properties = HashMap::new();
+        for (name, attribute) in &self.attributes {
+            properties.insert(name.clone(), type_json_schema(&attribute.ty));
+        }
+        for (name, block) in &self.blocks {
+            let mut level = block.body.body_json_schema();
+            for _ in 0..block.labels {
+                level = json_object(vec![
+                    ("type", JsonValue::Str("object".to_string())),
+                    ("additionalProperties", list_of(level, false)),
+                ]);
+            }
+            properties.insert(name.clone(), list_of(level, false));
+        }
+        let required = self
+            .attributes
+            .iter()
+            .filter(|&(_, a)| a.required)
+            .map(|(name, _)| JsonValue::Str(name.clone()))
+            .collect();
+        json_object(vec![
+            ("type", JsonValue::Str("object".to_string())),
+            ("properties", JsonValue::Object(properties)),
+            ("required", JsonValue::Array(required)),
+            ("additionalProperties", JsonValue::Boolean(false)),
+        ])
+    }
+}
+
+fn type_json_schema(ty: &Type) -> JsonValue {
+    let named = |name: &str| json_object(vec![("type", JsonValue::Str(name.to_string()))]);
+    match *ty {
+        Type::String => named("string"),
+        Type::Number => named("number"),
+        Type::Bool => named("boolean"),
+        Type::Any => JsonValue::Boolean(true),
+        Type::List(ref t) => json_object(vec![
+            ("type", JsonValue::Str("array".to_string())),
+            ("items", type_json_schema(t)),
+        ]),
+        Type::Set(ref t) => json_object(vec![
+            ("type", JsonValue::Str("array".to_string())),
+            ("items", type_json_schema(t)),
+            ("uniqueItems", JsonValue::Boolean(true)),
+        ]),
+        Type::Tuple(ref ts) => json_object(vec![
+            ("type", JsonValue::Str("array".to_string())),
+            (
+                "items",
+                JsonValue::Array(ts.iter().map(type_json_schema).collect()),
+            ),
+            ("minItems", JsonValue::Num(ts.len() as f32)),
+            ("maxItems", JsonValue::Num(ts.len() as f32)),
+        ]),
+        // assigned objects come out as single object lists
+        Type::Map(ref t) => list_of(
+            json_object(vec![
+                ("type", JsonValue::Str("object".to_string())),
+                ("additionalProperties", type_json_schema(t)),
+            ]),
+            true,
+        ),
+        Type::Object(ref attrs) => {
+            let properties = attrs
+                .iter()
+                .map(|(name, t)| (name.clone(), type_json_schema(t)))
+                .collect();
+            let required = attrs
+                .keys()
+                .map(|name| JsonValue::Str(name.clone()))
+                .collect();
+            list_of(
+                json_object(vec![
+                    ("type", JsonValue::Str("object".to_string())),
+                    ("properties", JsonValue::Object(properties)),
+                    ("required", JsonValue::Array(required)),
+                ]),
+                true,
+            )
+        }
+    }
+}
+
+fn list_of(items: JsonValue, single: bool) -> JsonValue {
+    let mut pairs = vec![
+        ("type", JsonValue::Str("array".to_string())),
+        ("items", items),
+    ];
+    if single {
+        pairs.push(("minItems", JsonValue::Num(1.)));
+        pairs.push(("maxItems", JsonValue::Num(1.)));
+    }
+    json_object(pairs)
+}
+
+fn json_object(pairs: Vec<(&str, JsonValue)>) -> JsonValue {
+    JsonValue::Object(pairs.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+// hcl1 makes a single object list of an assigned object; only list types
+// see it as a list
+fn unwrap_object<'a>(ty: &Type, value: &'a JsonValue) -> &'a JsonValue {
+    match (ty, value) {
+        (&Type::List(_), _) | (&Type::Set(_), _) | (&Type::Tuple(_), _) | (&Type::Any, _) => value,
+        (_, JsonValue::Array(items)) if items.len() == 1 => match items[0] {
+            JsonValue::Object(_) => &items[0],
+            _ => value,
+        },
+        _ => value,
+    }
+}
+
+fn violation(path: &str, message: String) -> TypeError {
+    TypeError {
+        path: path.to_string(),
+        message,
+    }
+}
+
+#[cfg(test)]
+fn messages(result: Result<(), Vec<TypeError>>) -> Vec<String> {
+    result.unwrap_err().iter().map(|e| e.to_string()).collect()
+}
+
+#[test]
+fn schema_attributes() {
+    let schema = Schema::new()
+        .required("region", Type::String)
+        .optional("count", Type::Number)
+        .optional("tags", Type::Map(Box::new(Type::String)));
+    let valid = crate::hcl::parse_hcl("region = \"eu\"\ntags = { env = \"prod\" }").unwrap();
+    assert_eq!(Ok(()), schema.validate(&valid));
+
+    let invalid =
+        crate::hcl::parse_hcl("count = \"many\"\ntags = { env = [] }\nextra = 1").unwrap();
+    assert_eq!(
+        vec![
+            "attribute \"region\" is required",
+            "count: a number is required, found \"many\"",
+            "extra: unexpected attribute or block",
+            "tags[\"env\"]: expected string, found tuple",
+        ],
+        messages(schema.validate(&invalid))
+    );
+}
+
+#[test]
+fn schema_blocks() {
+    let schema = Schema::new().block(
+        "resource",
+        2,
+        Schema::new().required("ami", Type::String).block(
+            "tag",
+            0,
+            Schema::new().required("key", Type::String),
+        ),
+    );
+    let test = "resource \"aws_instance\" \"web\" {
+  ami = \"ami-1\"
+  tag { key = \"a\" }
+  tag { value = \"b\" }
+}
+resource \"aws_instance\" {
+  ami = \"ami-2\"
+}
+";
+    let parsed = crate::hcl::parse_hcl(test).unwrap();
+    assert_eq!(
+        vec![
+            "resource[\"aws_instance\"]: expected a block with 2 labels",
+            "resource[\"aws_instance\"][\"web\"].tag: attribute \"key\" is required",
+            "resource[\"aws_instance\"][\"web\"].tag.value: unexpected attribute or block",
+        ],
+        messages(schema.validate(&parsed))
+    );
+
+    // the same bodies written as plain json objects
+    let json = crate::json::parse_json(
+        "{\"resource\": {\"aws_instance\": {\"web\": {\"ami\": \"ami-1\", \"tag\": {\"key\": \"a\"}}}}}",
+    )
+    .unwrap();
+    assert_eq!(Ok(()), schema.validate(&json));
+}
+
+#[test]
+fn schema_json_schema_export() {
+    let schema = Schema::new()
+        .optional("tags", Type::Map(Box::new(Type::String)))
+        .block(
+            "variable",
+            1,
+            Schema::new().required("default", Type::String),
+        );
+    let exported = schema.to_json_schema();
+    let expected = crate::json::parse_json(
+        "{
+  \"$schema\": \"http://json-schema.org/draft-07/schema#\",
+  \"type\": \"object\",
+  \"properties\": {
+    \"tags\": {
+      \"type\": \"array\", \"minItems\": 1, \"maxItems\": 1,
+      \"items\": {\"type\": \"object\", \"additionalProperties\": {\"type\": \"string\"}}
+    },
+    \"variable\": {
+      \"type\": \"array\",
+      \"items\": {
+        \"type\": \"object\",
+        \"additionalProperties\": {
+          \"type\": \"array\",
+          \"items\": {
+            \"type\": \"object\",
+            \"properties\": {\"default\": {\"type\": \"string\"}},
+            \"required\": [\"default\"],
+            \"additionalProperties\": false
+          }
+        }
+      }
+    }
+  },
+  \"required\": [],
+  \"additionalProperties\": false
+}",
+    )
+    .unwrap();
+    assert_eq!(expected, exported);
+
+    // the exported schema checks hcl2json output like the schema itself
+    let json_schema = crate::json_schema::JsonSchema::new(exported).unwrap();
+    let valid = crate::hcl::parse_hcl("tags = { a = \"b\" }\nvariable \"x\" { default = \"y\" }");
+    assert_eq!(Ok(()), json_schema.validate(&valid.unwrap()));
+    let invalid = crate::hcl::parse_hcl("variable \"x\" { default = 1 }\nother = 2").unwrap();
+    assert_eq!(
+        vec![
+            "other: unexpected attribute or block",
+            "variable[0].x[0].default: expected string, found number",
+        ],
+        json_schema
+            .validate(&invalid)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn schema_partial_content() {
+    let test = "variable \"region\" {
+  default = \"eu\"
+}
+output \"ip\" {
+  value = \"${aws_instance.web.ip}\"
+}
+resource \"aws_instance\" \"web\" {}
+terraform {
+  required_version = \">= 0.12\"
+}
+";
+    let body = Body::new(&crate::hcl::parse_hcl(test).unwrap()).unwrap();
+    let variables =
+        Schema::new()
+            .block("variable", 1, Schema::new())
+            .block("output", 1, Schema::new());
+    let (content, remaining) = body.partial_content(&variables).unwrap();
+    assert_eq!(vec!["resource", "terraform"], remaining.keys());
+    let blocks: Vec<(&str, Vec<String>)> = content
+        .blocks
+        .iter()
+        .map(|b| (b.block_type.as_str(), b.labels.clone()))
+        .collect();
+    assert_eq!(
+        vec![
+            ("output", vec!["ip".to_string()]),
+            ("variable", vec!["region".to_string()]),
+        ],
+        blocks
+    );
+
+    // the next layer takes its part of what is left
+    let settings = Schema::new().block(
+        "terraform",
+        0,
+        Schema::new().required("required_version", Type::String),
+    );
+    let (content, remaining) = remaining.partial_content(&settings).unwrap();
+    assert_eq!(vec!["resource"], remaining.keys());
+    let terraform = content.blocks[0]
+        .body
+        .content(&Schema::new().required("required_version", Type::String))
+        .unwrap();
+    assert_eq!(
+        Some(&JsonValue::Str(">= 0.12".to_string())),
+        terraform.attributes.get("required_version")
+    );
+
+    // block bodies report violations at their own paths
+    let variable = &body.partial_content(&variables).unwrap().0.blocks[1];
+    assert_eq!(
+        vec!["variable[\"region\"].default: unexpected attribute or block".to_string()],
+        variable
+            .body
+            .content(&Schema::new())
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+    );
+}
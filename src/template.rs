@@ -0,0 +1,499 @@
+//! String templates: `${}` interpolations and `%{ if }` / `%{ for }` directives.
+//!
+//! `parse_hcl` keeps template sequences in strings and heredocs verbatim, so
+//! the values it returns can be rendered here once variables are known.
+
+use std::fmt;
+use std::str;
+
+use nom::IResult::{Done, Error};
+use nom::{ErrorKind, IResult};
+
+use crate::common::run;
+use crate::expr::{self, blank, expression, identifier, keyword, Context, EvalError, Expr, Scope};
+use crate::types::{JsonValue, ParseError};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TemplatePart {
+    Literal(String),
+    Interpolation(Expr),
+    If(Expr, Vec<TemplatePart>, Vec<TemplatePart>),
+    For(Option<String>, String, Expr, Vec<TemplatePart>),
+}
+
+/// Parses a bare template, such as the contents of a heredoc.
+pub fn parse_template(source: &str) -> Result<Vec<TemplatePart>, ParseError> {
+    run(source.as_bytes(), |i| template_parts(i, false))
+}
+
+pub(crate) fn collect_references(
+    parts: &[TemplatePart],
+    bound: &mut Vec<String>,
+    out: &mut Vec<expr::Reference>,
+) {
+    for part in parts {
+        match *part {
+            TemplatePart::Literal(_) => {}
+            TemplatePart::Interpolation(ref e) => e.collect_references(bound, out),
+            TemplatePart::If(ref cond, ref then, ref otherwise) => {
+                cond.collect_references(bound, out);
+                collect_references(then, bound, out);
+                collect_references(otherwise, bound, out);
+            }
+            TemplatePart::For(ref key_var, ref value_var, ref collection, ref body) => {
+                collection.collect_references(bound, out);
+                let depth = bound.len();
+                bound.extend(key_var.clone());
+                bound.push(value_var.clone());
+                collect_references(body, bound, out);
+                bound.truncate(depth);
+            }
+        }
+    }
+}
+
+/// Renders a template to a string.
+pub fn render(parts: &[TemplatePart], ctx: &Context) -> Result<String, EvalError> {
+    let mut out = String::new();
+    render_parts(parts, ctx, &mut Vec::new(), &mut out)?;
+    Ok(out)
+}
+
+pub(crate) fn evaluate(
+    parts: &[TemplatePart],
+    ctx: &Context,
+    scope: &mut Scope,
+) -> Result<JsonValue, EvalError> {
+    // a lone interpolation yields its value unchanged rather than a string
+    if let [TemplatePart::Interpolation(ref e)] = parts[..] {
+        return e.eval(ctx, scope);
+    }
+    let mut out = String::new();
+    render_parts(parts, ctx, scope, &mut out)?;
+    Ok(JsonValue::Str(out))
+}
+
+fn render_parts(
+    parts: &[TemplatePart],
+    ctx: &Context,
+    scope: &mut Scope,
+    out: &mut String,
+) -> Result<(), EvalError> {
+    for part in parts {
+        match *part {
+            TemplatePart::Literal(ref s) => out.push_str(s),
+            TemplatePart::Interpolation(ref e) => {
+                out.push_str(&expr::stringify(&e.eval(ctx, scope)?)?)
+            }
+            TemplatePart::If(ref cond, ref then, ref otherwise) => {
+                if expr::to_bool(&cond.eval(ctx, scope)?)? {
+                    render_parts(then, ctx, scope, out)?;
+                } else {
+                    render_parts(otherwise, ctx, scope, out)?;
+                }
+            }
+            TemplatePart::For(ref key_var, ref value_var, ref collection, ref body) => {
+                for (k, v) in expr::iterate(collection.eval(ctx, scope)?)? {
+                    let depth = scope.len();
+                    if let Some(ref name) = *key_var {
+                        scope.push((name.clone(), k));
+                    }
+                    scope.push((value_var.clone(), v));
+                    let result = render_parts(body, ctx, scope, out);
+                    scope.truncate(depth);
+                    result?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes template parts back as source, quoted and escaped for use inside
+/// an expression when `quoted` is set.
+pub(crate) fn write_template(
+    f: &mut fmt::Formatter,
+    parts: &[TemplatePart],
+    quoted: bool,
+) -> fmt::Result {
+    if quoted {
+        write!(f, "\"")?;
+    }
+    write_parts(f, parts, quoted)?;
+    if quoted {
+        write!(f, "\"")?;
+    }
+    Ok(())
+}
+
+struct Source<'a>(&'a [TemplatePart]);
+
+impl<'a> fmt::Display for Source<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_parts(f, self.0, false)
+    }
+}
+
+/// Prints template parts back as bare template source.
+pub fn to_source(parts: &[TemplatePart]) -> String {
+    Source(parts).to_string()
+}
+
+fn write_parts(f: &mut fmt::Formatter, parts: &[TemplatePart], quoted: bool) -> fmt::Result {
+    for part in parts {
+        match *part {
+            TemplatePart::Literal(ref s) => {
+                let mut escaped = s.replace("${", "$${").replace("%{", "%%{");
+                if quoted {
+                    escaped = escaped
+                        .replace('\\', "\\\\")
+                        .replace('"', "\\\"")
+                        .replace('\n', "\\n")
+                        .replace('\r', "\\r")
+                        .replace('\t', "\\t");
+                }
+                write!(f, "{}", escaped)?;
+            }
+            TemplatePart::Interpolation(ref e) => write!(f, "${{{}}}", e)?,
+            TemplatePart::If(ref cond, ref then, ref otherwise) => {
+                write!(f, "%{{ if {} }}", cond)?;
+                write_parts(f, then, quoted)?;
+                if !otherwise.is_empty() {
+                    write!(f, "%{{ else }}")?;
+                    write_parts(f, otherwise, quoted)?;
+                }
+                write!(f, "%{{ endif }}")?;
+            }
+            TemplatePart::For(ref key_var, ref value_var, ref collection, ref body) => {
+                write!(f, "%{{ for ")?;
+                if let Some(ref k) = *key_var {
+                    write!(f, "{}, ", k)?;
+                }
+                write!(f, "{} in {} }}", value_var, collection)?;
+                write_parts(f, body, quoted)?;
+                write!(f, "%{{ endfor }}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+enum Directive {
+    If(Expr),
+    Else,
+    EndIf,
+    For(Option<String>, String, Expr),
+    EndFor,
+}
+
+// a template sequence, with its leading and trailing strip markers
+enum Token {
+    Literal(String),
+    Interpolation(Expr, bool, bool),
+    Directive(Directive, bool, bool),
+}
+
+named!(
+    strip_open<bool>,
+    map!(opt!(complete!(tag!("~"))), |s| s.is_some())
+);
+
+named!(
+    strip_close<bool>,
+    do_parse!(blank >> strip: opt!(complete!(tag!("~"))) >> complete!(tag!("}")) >> (strip.is_some()))
+);
+
+named!(
+    interpolation<Token>,
+    do_parse!(
+        complete!(tag!("${"))
+            >> l: strip_open
+            >> e: expression
+            >> r: strip_close
+            >> (Token::Interpolation(e, l, r))
+    )
+);
+
+named!(
+    directive<Token>,
+    do_parse!(
+        complete!(tag!("%{"))
+            >> l: strip_open
+            >> d: alt_complete!(
+                preceded!(call!(keyword, "if"), expression) => { Directive::If } |
+                call!(keyword, "else")   => { |_| Directive::Else } |
+                call!(keyword, "endif")  => { |_| Directive::EndIf } |
+                call!(keyword, "endfor") => { |_| Directive::EndFor } |
+                do_parse!(
+                    call!(keyword, "for")
+                        >> first: identifier
+                        >> second: opt!(complete!(preceded!(preceded!(blank, tag!(",")), identifier)))
+                        >> call!(keyword, "in")
+                        >> collection: expression
+                        >> (match second {
+                            Some(value) => Directive::For(Some(first), value, collection),
+                            None => Directive::For(None, first, collection),
+                        })
+                )
+            )
+            >> r: strip_close
+            >> (Token::Directive(d, l, r))
+    )
+);
+
+fn push_char(out: &mut String, code: &[u8]) -> bool {
+    str::from_utf8(code)
+        .ok()
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .and_then(std::char::from_u32)
+        .map(|c| out.push(c))
+        .is_some()
+}
+
+// literal text up to the next template sequence; quoted templates also end at
+// a closing quote or newline and support backslash escapes
+fn literal(input: &[u8], quoted: bool) -> IResult<&[u8], String> {
+    let mut out: Vec<u8> = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'"' | b'\n' if quoted => break,
+            b'$' | b'%' if input.get(i + 1) == Some(&b'{') => break,
+            b'$' | b'%'
+                if input.get(i + 1) == Some(&input[i]) && input.get(i + 2) == Some(&b'{') =>
+            {
+                out.push(input[i]);
+                out.push(b'{');
+                i += 3;
+            }
+            b'\\' if quoted => {
+                let escaped = match input.get(i + 1) {
+                    Some(&b'n') => b'\n',
+                    Some(&b'r') => b'\r',
+                    Some(&b't') => b'\t',
+                    Some(&b'"') => b'"',
+                    Some(&b'\\') => b'\\',
+                    Some(&c) if c == b'u' || c == b'U' => {
+                        let len = if c == b'u' { 4 } else { 8 };
+                        text.push_str(&String::from_utf8_lossy(&out));
+                        out.clear();
+                        match input.get(i + 2..i + 2 + len) {
+                            Some(code) if push_char(&mut text, code) => {
+                                i += 2 + len;
+                                continue;
+                            }
+                            _ => return Error(error_position!(ErrorKind::Custom(0), input)),
+                        }
+                    }
+                    _ => return Error(error_position!(ErrorKind::Custom(0), input)),
+                };
+                out.push(escaped);
+                i += 2;
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    if i == 0 {
+        return Error(error_position!(ErrorKind::Custom(0), input));
+    }
+    text.push_str(&String::from_utf8_lossy(&out));
+    Done(&input[i..], text)
+}
+
+named_args!(
+    tokens(quoted: bool)<Vec<Token>>,
+    many0!(alt_complete!(
+        interpolation |
+        directive |
+        call!(literal, quoted) => { Token::Literal }
+    ))
+);
+
+pub(crate) fn quoted_template_parts(input: &[u8]) -> IResult<&[u8], Vec<TemplatePart>> {
+    template_parts(input, true)
+}
+
+fn template_parts(input: &[u8], quoted: bool) -> IResult<&[u8], Vec<TemplatePart>> {
+    let (rest, tokens) = match tokens(input, quoted) {
+        Done(rest, tokens) => (rest, tokens),
+        _ => return Error(error_position!(ErrorKind::Custom(0), input)),
+    };
+    let mut tokens = strip(tokens).into_iter();
+    match build(&mut tokens) {
+        Some((parts, None)) => Done(rest, parts),
+        _ => Error(error_position!(ErrorKind::Custom(0), input)),
+    }
+}
+
+// applies `~` markers by trimming whitespace from the neighbouring literals
+fn strip(tokens: Vec<Token>) -> Vec<Token> {
+    let mut out: Vec<Token> = Vec::new();
+    let mut strip_next = false;
+    for token in tokens {
+        let (strip_prev, strip_after) = match token {
+            Token::Literal(ref s) => {
+                let s = if strip_next { s.trim_start() } else { s };
+                strip_next = false;
+                out.push(Token::Literal(s.to_string()));
+                continue;
+            }
+            Token::Interpolation(_, l, r) | Token::Directive(_, l, r) => (l, r),
+        };
+        if strip_prev {
+            if let Some(&mut Token::Literal(ref mut s)) = out.last_mut() {
+                let len = s.trim_end().len();
+                s.truncate(len);
+            }
+        }
+        strip_next = strip_after;
+        out.push(token);
+    }
+    out
+}
+
+// nests directive bodies, returning the closing directive that ended this level
+fn build<I: Iterator<Item = Token>>(
+    tokens: &mut I,
+) -> Option<(Vec<TemplatePart>, Option<Directive>)> {
+    let mut parts = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Literal(s) => {
+                if !s.is_empty() {
+                    parts.push(TemplatePart::Literal(s))
+                }
+            }
+            Token::Interpolation(e, _, _) => parts.push(TemplatePart::Interpolation(e)),
+            Token::Directive(Directive::If(cond), _, _) => {
+                let (then, end) = build(tokens)?;
+                let otherwise = match end {
+                    Some(Directive::EndIf) => Vec::new(),
+                    Some(Directive::Else) => match build(tokens)? {
+                        (otherwise, Some(Directive::EndIf)) => otherwise,
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+                parts.push(TemplatePart::If(cond, then, otherwise));
+            }
+            Token::Directive(Directive::For(key_var, value_var, collection), _, _) => {
+                match build(tokens)? {
+                    (body, Some(Directive::EndFor)) => {
+                        parts.push(TemplatePart::For(key_var, value_var, collection, body))
+                    }
+                    _ => return None,
+                }
+            }
+            Token::Directive(end, _, _) => return Some((parts, Some(end))),
+        }
+    }
+    Some((parts, None))
+}
+
+#[cfg(test)]
+fn render_with(source: &str, vars: &[(&str, JsonValue)]) -> Result<String, EvalError> {
+    let mut ctx = Context::new();
+    for &(name, ref value) in vars {
+        ctx.set_variable(name, value.clone());
+    }
+    render(
+        &parse_template(source).expect("template did not parse"),
+        &ctx,
+    )
+}
+
+#[test]
+fn template_if_else() {
+    let test = "Hello, %{ if name != \"\" }${name}%{ else }unnamed%{ endif }!";
+    assert_eq!(
+        Ok("Hello, Ava!".to_string()),
+        render_with(test, &[("name", JsonValue::Str("Ava".to_string()))])
+    );
+    assert_eq!(
+        Ok("Hello, unnamed!".to_string()),
+        render_with(test, &[("name", JsonValue::Str("".to_string()))])
+    );
+}
+
+#[test]
+fn template_for_with_strip_markers() {
+    let test = "users:
+%{ for name in names ~}
+- ${name}
+%{ endfor ~}
+done";
+    let names = JsonValue::Array(vec![
+        JsonValue::Str("a".to_string()),
+        JsonValue::Str("b".to_string()),
+    ]);
+    assert_eq!(
+        Ok("users:\n- a\n- b\ndone".to_string()),
+        render_with(test, &[("names", names)])
+    );
+}
+
+#[test]
+fn template_interpolation_strip_markers() {
+    assert_eq!(
+        Ok("ab".to_string()),
+        render_with("a \n ${~ \"\" ~} \n b", &[])
+    );
+}
+
+#[test]
+fn template_escaped_sequences() {
+    assert_eq!(
+        Ok("${x} %{ if }".to_string()),
+        render_with("$${x} %%{ if }", &[])
+    );
+}
+
+#[test]
+fn template_unbalanced_directives() {
+    assert!(parse_template("%{ if true }a").is_err());
+    assert!(parse_template("a%{ endif }").is_err());
+    assert!(parse_template("%{ for x in y }a%{ endif }").is_err());
+}
+
+#[test]
+fn template_quoted_directive() {
+    let e = expr::parse_expr("\"%{ for k, v in m }${k}=${v};%{ endfor }\"").unwrap();
+    let mut m = std::collections::HashMap::new();
+    m.insert("b".to_string(), JsonValue::Num(2.));
+    m.insert("a".to_string(), JsonValue::Num(1.));
+    let mut ctx = Context::new();
+    ctx.set_variable("m", JsonValue::Object(m));
+    assert_eq!(Ok(JsonValue::Str("a=1;b=2;".to_string())), e.evaluate(&ctx));
+}
+
+#[test]
+fn template_from_hcl_heredoc() {
+    let test = "user_data = <<EOF
+#cloud-config
+packages:
+%{ for p in packages ~}
+- ${p}
+%{ endfor ~}
+EOF
+";
+    let parsed = crate::hcl::parse_hcl(test).unwrap();
+    let body = match parsed {
+        JsonValue::Object(ref h) => match h.get("user_data") {
+            Some(JsonValue::Str(s)) => s.clone(),
+            _ => panic!("missing user_data"),
+        },
+        _ => panic!("object did not parse"),
+    };
+    let packages = JsonValue::Array(vec![
+        JsonValue::Str("git".to_string()),
+        JsonValue::Str("curl".to_string()),
+    ]);
+    assert_eq!(
+        Ok("#cloud-config\npackages:\n- git\n- curl\n".to_string()),
+        render_with(&body, &[("packages", packages)])
+    );
+}
@@ -0,0 +1,666 @@
+//! A typed model of a Terraform configuration.
+//!
+//! Files are taken as parsed, from HCL in the shape `parse_hcl` and
+//! `parse_hcl2` give, or from `.tf.json`, where blocks are objects keyed by
+//! their labels. Expressions are kept as the `${...}` strings they are parsed
+//! into, except for references in meta-arguments such as `depends_on`, which
+//! are kept as their text, such as `aws_instance.web`.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::schema::{item_path, labeled_path};
+use crate::types::{parse_type, JsonValue, Type, TypeError};
+
+type Body = HashMap<String, JsonValue>;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Configuration {
+    pub variables: BTreeMap<String, Variable>,
+    pub outputs: BTreeMap<String, Output>,
+    pub locals: BTreeMap<String, JsonValue>,
+    pub providers: Vec<Provider>,
+    /// Resources and data sources by address, such as `aws_instance.web` or
+    /// `data.aws_ami.ubuntu`.
+    pub resources: BTreeMap<String, Resource>,
+    pub modules: BTreeMap<String, ModuleCall>,
+    pub backend: Option<Backend>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Variable {
+    pub ty: Option<Type>,
+    pub default: Option<JsonValue>,
+    pub description: Option<String>,
+    pub sensitive: bool,
+    pub validations: Vec<Validation>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validation {
+    pub condition: String,
+    pub error_message: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Output {
+    pub value: JsonValue,
+    pub description: Option<String>,
+    pub sensitive: bool,
+    pub depends_on: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Provider {
+    pub name: String,
+    pub alias: Option<String>,
+    pub config: BTreeMap<String, JsonValue>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceMode {
+    Managed,
+    Data,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resource {
+    pub mode: ResourceMode,
+    pub resource_type: String,
+    pub name: String,
+    pub count: Option<JsonValue>,
+    pub for_each: Option<JsonValue>,
+    /// The provider configuration, such as `aws.west`, when not the default.
+    pub provider: Option<String>,
+    pub depends_on: Vec<String>,
+    pub lifecycle: Lifecycle,
+    /// Every other attribute and block, provisioners included.
+    pub config: BTreeMap<String, JsonValue>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lifecycle {
+    pub create_before_destroy: bool,
+    pub prevent_destroy: bool,
+    pub ignore_changes: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleCall {
+    pub source: String,
+    pub version: Option<String>,
+    pub count: Option<JsonValue>,
+    pub for_each: Option<JsonValue>,
+    /// The `providers` mapping, from the module's names to the caller's.
+    pub providers: BTreeMap<String, String>,
+    pub depends_on: Vec<String>,
+    pub inputs: BTreeMap<String, JsonValue>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backend {
+    pub backend_type: String,
+    pub config: BTreeMap<String, JsonValue>,
+}
+
+impl Configuration {
+    /// Builds the model of a module from the parsed files it is made of.
+    pub fn from_files(files: &[JsonValue]) -> Result<Configuration, Vec<TypeError>> {
+        let mut config = Configuration::default();
+        let mut errors = Vec::new();
+        for file in files {
+            config.add(file, &mut errors);
+        }
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn add(&mut self, file: &JsonValue, errors: &mut Vec<TypeError>) {
+        let top = match *file {
+            JsonValue::Object(ref top) => top,
+            _ => {
+                errors.push(error(
+                    "",
+                    format!("expected object, found {}", file.type_name()),
+                ));
+                return;
+            }
+        };
+        for (labels, body) in blocks(top, "variable", 1) {
+            let path = labeled_path("variable", &labels);
+            let name = labels.into_iter().next().unwrap_or_default();
+            if self.variables.contains_key(&name) {
+                errors.push(duplicate(&path));
+                continue;
+            }
+            match variable(&path, body) {
+                Ok(v) => {
+                    self.variables.insert(name, v);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        for (labels, body) in blocks(top, "output", 1) {
+            let path = labeled_path("output", &labels);
+            let name = labels.into_iter().next().unwrap_or_default();
+            if self.outputs.contains_key(&name) {
+                errors.push(duplicate(&path));
+                continue;
+            }
+            match output(&path, body) {
+                Ok(o) => {
+                    self.outputs.insert(name, o);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        for (_, body) in blocks(top, "locals", 0) {
+            for (name, value) in sorted(body) {
+                if self.locals.contains_key(name) {
+                    errors.push(duplicate(&item_path("locals", name)));
+                    continue;
+                }
+                self.locals.insert(name.clone(), value.clone());
+            }
+        }
+        for (labels, body) in blocks(top, "provider", 1) {
+            let path = labeled_path("provider", &labels);
+            match provider(&path, labels, body) {
+                Ok(p) => self.providers.push(p),
+                Err(e) => errors.push(e),
+            }
+        }
+        for &(block_type, mode) in &[
+            ("resource", ResourceMode::Managed),
+            ("data", ResourceMode::Data),
+        ] {
+            for (labels, body) in blocks(top, block_type, 2) {
+                let path = labeled_path(block_type, &labels);
+                let resource = match resource(&path, mode, labels, body) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        errors.push(e);
+                        continue;
+                    }
+                };
+                let address = resource.address();
+                if self.resources.contains_key(&address) {
+                    errors.push(duplicate(&path));
+                    continue;
+                }
+                self.resources.insert(address, resource);
+            }
+        }
+        for (labels, body) in blocks(top, "module", 1) {
+            let path = labeled_path("module", &labels);
+            let name = labels.into_iter().next().unwrap_or_default();
+            if self.modules.contains_key(&name) {
+                errors.push(duplicate(&path));
+                continue;
+            }
+            match module(&path, body) {
+                Ok(m) => {
+                    self.modules.insert(name, m);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        for (_, body) in blocks(top, "terraform", 0) {
+            for (labels, backend) in blocks(body, "backend", 1) {
+                let path = labeled_path("terraform.backend", &labels);
+                if self.backend.is_some() {
+                    errors.push(duplicate(&path));
+                    continue;
+                }
+                self.backend = Some(Backend {
+                    backend_type: labels.into_iter().next().unwrap_or_default(),
+                    config: rest(backend, &[]),
+                });
+            }
+        }
+    }
+}
+
+impl Resource {
+    /// The address of the resource, such as `data.aws_ami.ubuntu`.
+    pub fn address(&self) -> String {
+        match self.mode {
+            ResourceMode::Managed => format!("{}.{}", self.resource_type, self.name),
+            ResourceMode::Data => format!("data.{}.{}", self.resource_type, self.name),
+        }
+    }
+}
+
+fn error(path: &str, message: String) -> TypeError {
+    TypeError {
+        path: path.to_string(),
+        message,
+    }
+}
+
+fn duplicate(path: &str) -> TypeError {
+    error(path, "defined more than once".to_string())
+}
+
+fn sorted(body: &Body) -> Vec<(&String, &JsonValue)> {
+    let mut items: Vec<_> = body.iter().collect();
+    items.sort_by(|a, b| a.0.cmp(b.0));
+    items
+}
+
+// the bodies of the blocks of a type, with their labels, whether labels nest
+// in lists of objects, as from hcl, or directly in objects, as in json
+fn blocks<'a>(body: &'a Body, block_type: &str, labels: usize) -> Vec<(Vec<String>, &'a Body)> {
+    let mut out = Vec::new();
+    if let Some(value) = body.get(block_type) {
+        collect(value, labels, Vec::new(), &mut out);
+    }
+    out
+}
+
+fn collect<'a>(
+    value: &'a JsonValue,
+    labels: usize,
+    found: Vec<String>,
+    out: &mut Vec<(Vec<String>, &'a Body)>,
+) {
+    match *value {
+        JsonValue::Array(ref items) => {
+            for item in items {
+                collect(item, labels, found.clone(), out);
+            }
+        }
+        JsonValue::Object(ref body) if labels == 0 => out.push((found, body)),
+        JsonValue::Object(ref labeled) => {
+            for (label, value) in sorted(labeled) {
+                let mut found = found.clone();
+                found.push(label.clone());
+                collect(value, labels - 1, found, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+// the first body of a nested block, such as `lifecycle`
+fn nested<'a>(body: &'a Body, block_type: &str) -> Option<&'a Body> {
+    blocks(body, block_type, 0)
+        .into_iter()
+        .next()
+        .map(|(_, b)| b)
+}
+
+fn string(body: &Body, path: &str, key: &str) -> Result<Option<String>, TypeError> {
+    match body.get(key) {
+        Some(value) => match Type::String.convert_at(&item_path(path, key), value)? {
+            JsonValue::Str(s) => Ok(Some(s)),
+            _ => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+fn boolean(body: &Body, path: &str, key: &str) -> Result<bool, TypeError> {
+    match body.get(key) {
+        Some(value) => match Type::Bool.convert_at(&item_path(path, key), value)? {
+            JsonValue::Boolean(b) => Ok(b),
+            _ => Ok(false),
+        },
+        None => Ok(false),
+    }
+}
+
+/// The text of a reference or expression kept as `${...}`.
+pub(crate) fn unwrap_expression(s: &str) -> &str {
+    s.strip_prefix("${")
+        .and_then(|s| s.strip_suffix('}'))
+        .unwrap_or(s)
+}
+
+// a list of references, such as `depends_on`
+fn references(body: &Body, path: &str, key: &str) -> Result<Vec<String>, TypeError> {
+    let path = item_path(path, key);
+    match body.get(key) {
+        Some(JsonValue::Array(items)) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| match *item {
+                JsonValue::Str(ref s) => Ok(unwrap_expression(s).to_string()),
+                _ => Err(error(
+                    &format!("{}[{}]", path, i),
+                    format!("expected reference, found {}", item.type_name()),
+                )),
+            })
+            .collect(),
+        Some(value) => Err(error(
+            &path,
+            format!("expected list of references, found {}", value.type_name()),
+        )),
+        None => Ok(Vec::new()),
+    }
+}
+
+// every attribute and block but the given ones
+fn rest(body: &Body, skip: &[&str]) -> BTreeMap<String, JsonValue> {
+    body.iter()
+        .filter(|(k, _)| !skip.contains(&k.as_str()))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// A type constraint, written as in Terraform 0.12 or as the quoted names
+/// of Terraform 0.11.
+pub(crate) fn declared_type(ty: &JsonValue) -> Option<Type> {
+    let source = match *ty {
+        JsonValue::Str(ref s) => unwrap_expression(s),
+        _ => return None,
+    };
+    match source {
+        "list" => Some(Type::List(Box::new(Type::Any))),
+        "map" => Some(Type::Map(Box::new(Type::Any))),
+        _ => parse_type(source).ok(),
+    }
+}
+
+fn variable(path: &str, body: &Body) -> Result<Variable, TypeError> {
+    let ty = match body.get("type") {
+        Some(ty) => Some(declared_type(ty).ok_or_else(|| {
+            error(
+                &item_path(path, "type"),
+                "invalid type constraint".to_string(),
+            )
+        })?),
+        None => None,
+    };
+    let mut validations = Vec::new();
+    for (_, validation) in blocks(body, "validation", 0) {
+        let path = item_path(path, "validation");
+        let required = |key: &str| {
+            string(validation, &path, key)?
+                .ok_or_else(|| error(&path, format!("attribute \"{}\" is required", key)))
+        };
+        validations.push(Validation {
+            condition: required("condition")?,
+            error_message: required("error_message")?,
+        });
+    }
+    Ok(Variable {
+        ty,
+        default: body.get("default").cloned(),
+        description: string(body, path, "description")?,
+        sensitive: boolean(body, path, "sensitive")?,
+        validations,
+    })
+}
+
+fn output(path: &str, body: &Body) -> Result<Output, TypeError> {
+    Ok(Output {
+        value: body
+            .get("value")
+            .cloned()
+            .ok_or_else(|| error(path, "attribute \"value\" is required".to_string()))?,
+        description: string(body, path, "description")?,
+        sensitive: boolean(body, path, "sensitive")?,
+        depends_on: references(body, path, "depends_on")?,
+    })
+}
+
+fn provider(path: &str, labels: Vec<String>, body: &Body) -> Result<Provider, TypeError> {
+    Ok(Provider {
+        name: labels.into_iter().next().unwrap_or_default(),
+        alias: string(body, path, "alias")?,
+        config: rest(body, &["alias"]),
+    })
+}
+
+fn resource(
+    path: &str,
+    mode: ResourceMode,
+    labels: Vec<String>,
+    body: &Body,
+) -> Result<Resource, TypeError> {
+    let mut labels = labels.into_iter();
+    let lifecycle = match nested(body, "lifecycle") {
+        Some(lifecycle) => {
+            let path = item_path(path, "lifecycle");
+            Lifecycle {
+                create_before_destroy: boolean(lifecycle, &path, "create_before_destroy")?,
+                prevent_destroy: boolean(lifecycle, &path, "prevent_destroy")?,
+                ignore_changes: references(lifecycle, &path, "ignore_changes")?,
+            }
+        }
+        None => Lifecycle::default(),
+    };
+    Ok(Resource {
+        mode,
+        resource_type: labels.next().unwrap_or_default(),
+        name: labels.next().unwrap_or_default(),
+        count: body.get("count").cloned(),
+        for_each: body.get("for_each").cloned(),
+        provider: string(body, path, "provider")?.map(|p| unwrap_expression(&p).to_string()),
+        depends_on: references(body, path, "depends_on")?,
+        lifecycle,
+        config: rest(
+            body,
+            &["count", "for_each", "provider", "depends_on", "lifecycle"],
+        ),
+    })
+}
+
+fn module(path: &str, body: &Body) -> Result<ModuleCall, TypeError> {
+    let mut providers = BTreeMap::new();
+    if let Some((_, mapping)) = blocks(body, "providers", 0).into_iter().next() {
+        let path = item_path(path, "providers");
+        for (name, value) in sorted(mapping) {
+            match *value {
+                JsonValue::Str(ref s) => {
+                    providers.insert(
+                        unwrap_expression(name).to_string(),
+                        unwrap_expression(s).to_string(),
+                    );
+                }
+                _ => {
+                    return Err(error(
+                        &item_path(&path, name),
+                        format!("expected reference, found {}", value.type_name()),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(ModuleCall {
+        source: string(body, path, "source")?
+            .ok_or_else(|| error(path, "attribute \"source\" is required".to_string()))?,
+        version: string(body, path, "version")?,
+        count: body.get("count").cloned(),
+        for_each: body.get("for_each").cloned(),
+        providers,
+        depends_on: references(body, path, "depends_on")?,
+        inputs: rest(
+            body,
+            &[
+                "source",
+                "version",
+                "count",
+                "for_each",
+                "providers",
+                "depends_on",
+            ],
+        ),
+    })
+}
+
+#[test]
+fn terraform_model_from_hcl() {
+    let main = crate::hcl2::parse_hcl2(
+        r#"
+terraform {
+  backend "s3" {
+    bucket = "state"
+  }
+}
+
+variable "region" {
+  type        = string
+  default     = "eu-west-1"
+  description = "Where to deploy"
+  validation {
+    condition     = length(var.region) > 0
+    error_message = "Region must not be empty."
+  }
+}
+
+variable "password" {
+  sensitive = true
+}
+
+provider "aws" {
+  region = var.region
+}
+
+locals {
+  name = "web"
+}
+
+data "aws_ami" "ubuntu" {
+  most_recent = true
+}
+
+resource "aws_instance" "web" {
+  count      = 2
+  ami        = data.aws_ami.ubuntu.id
+  provider   = aws.west
+  depends_on = [module.vpc]
+  lifecycle {
+    create_before_destroy = true
+    ignore_changes        = [tags]
+  }
+}
+
+module "vpc" {
+  source  = "terraform-aws-modules/vpc/aws"
+  version = "3.0.0"
+  cidr    = "10.0.0.0/16"
+  providers = {
+    aws = aws.west
+  }
+}
+
+output "ip" {
+  value     = aws_instance.web[0].private_ip
+  sensitive = true
+}
+"#,
+    )
+    .unwrap();
+    let west = crate::hcl2::parse_hcl2(
+        "provider \"aws\" {\n  alias  = \"west\"\n  region = \"us-west-2\"\n}\n",
+    )
+    .unwrap();
+    let config = Configuration::from_files(&[main, west]).unwrap();
+
+    let region = &config.variables["region"];
+    assert_eq!(Some(Type::String), region.ty);
+    assert_eq!(
+        Some(JsonValue::Str("eu-west-1".to_string())),
+        region.default
+    );
+    assert_eq!(Some("Where to deploy".to_string()), region.description);
+    assert_eq!(
+        vec![Validation {
+            condition: "${length(var.region) > 0}".to_string(),
+            error_message: "Region must not be empty.".to_string(),
+        }],
+        region.validations
+    );
+    assert!(config.variables["password"].sensitive);
+
+    assert_eq!(
+        vec![None, Some("west".to_string())],
+        config
+            .providers
+            .iter()
+            .map(|p| p.alias.clone())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        Some(&JsonValue::Str("web".to_string())),
+        config.locals.get("name")
+    );
+
+    let addresses: Vec<&String> = config.resources.keys().collect();
+    assert_eq!(vec!["aws_instance.web", "data.aws_ami.ubuntu"], addresses);
+    let web = &config.resources["aws_instance.web"];
+    assert_eq!(Some(JsonValue::Num(2.0)), web.count);
+    assert_eq!(Some("aws.west".to_string()), web.provider);
+    assert_eq!(vec!["module.vpc".to_string()], web.depends_on);
+    assert!(web.lifecycle.create_before_destroy);
+    assert_eq!(vec!["tags".to_string()], web.lifecycle.ignore_changes);
+    assert_eq!(vec!["ami"], web.config.keys().collect::<Vec<_>>());
+
+    let vpc = &config.modules["vpc"];
+    assert_eq!("terraform-aws-modules/vpc/aws", vpc.source);
+    assert_eq!(Some("3.0.0".to_string()), vpc.version);
+    assert_eq!(Some(&"aws.west".to_string()), vpc.providers.get("aws"));
+    assert_eq!(vec!["cidr"], vpc.inputs.keys().collect::<Vec<_>>());
+
+    assert!(config.outputs["ip"].sensitive);
+    let backend = config.backend.unwrap();
+    assert_eq!("s3", backend.backend_type);
+    assert_eq!(
+        Some(&JsonValue::Str("state".to_string())),
+        backend.config.get("bucket")
+    );
+}
+
+#[test]
+fn terraform_model_from_json() {
+    let hcl = crate::hcl::parse_hcl(
+        "variable \"zones\" {\n  type = \"list\"\n}\n\
+         resource \"aws_instance\" \"web\" {\n  ami = \"ami-1\"\n  depends_on = [\"aws_eip.ip\"]\n}\n",
+    )
+    .unwrap();
+    let json = crate::json::parse_json(
+        r#"{
+  "variable": { "zones": { "type": "list(string)" } },
+  "resource": { "aws_instance": { "web": { "ami": "ami-1", "depends_on": ["aws_eip.ip"] } } },
+  "module": { "db": { "version": "1.0" } }
+}"#,
+    )
+    .unwrap();
+    let from_hcl = Configuration::from_files(&[hcl]).unwrap();
+    assert_eq!(
+        Some(Type::List(Box::new(Type::Any))),
+        from_hcl.variables["zones"].ty
+    );
+
+    let errors: Vec<String> = Configuration::from_files(std::slice::from_ref(&json))
+        .unwrap_err()
+        .iter()
+        .map(|e| e.to_string())
+        .collect();
+    assert_eq!(
+        vec!["module[\"db\"]: attribute \"source\" is required"],
+        errors
+    );
+
+    let mut json = json;
+    if let JsonValue::Object(ref mut top) = json {
+        top.remove("module");
+    }
+    let from_json = Configuration::from_files(&[json]).unwrap();
+    assert_eq!(
+        Some(Type::List(Box::new(Type::String))),
+        from_json.variables["zones"].ty
+    );
+    assert_eq!(from_hcl.resources, from_json.resources);
+
+    let zones = crate::hcl::parse_hcl("variable \"zones\" {}\n").unwrap();
+    let errors = Configuration::from_files(&[zones.clone(), zones]).unwrap_err();
+    assert_eq!(
+        "variable[\"zones\"]: defined more than once",
+        errors[0].to_string()
+    );
+}
@@ -0,0 +1,463 @@
+//! Resolving the values of Terraform input variables.
+//!
+//! Values are taken from the sources Terraform reads, each overriding the
+//! ones before it: a variable's default, `TF_VAR_` environment variables,
+//! `terraform.tfvars`, `terraform.tfvars.json`, `*.auto.tfvars` and
+//! `*.auto.tfvars.json` files in lexical order, then `-var` and `-var-file`
+//! arguments in the order given.
+//!
+//! Every value is converted to the variable's declared type, or to the
+//! general type of its default. Strings from the command line or the
+//! environment are parsed as HCL first when that type is a collection.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+use crate::common::TempDir;
+use crate::hcl::parse_hcl;
+use crate::json::parse_json;
+use crate::terraform::declared_type;
+use crate::types::{JsonValue, ReadError, Type, TypeError};
+
+/// Where the value of a variable came from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Origin {
+    Default,
+    Env(String),
+    File(PathBuf),
+    /// A `-var` argument.
+    Var,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Origin::Default => write!(f, "default"),
+            Origin::Env(ref var) => write!(f, "environment variable {}", var),
+            Origin::File(ref path) => write!(f, "{}", path.display()),
+            Origin::Var => write!(f, "-var"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TfvarsError {
+    /// A variable block that cannot be understood.
+    Declaration(String, String),
+    Read(PathBuf, ReadError),
+    /// A `-var` argument without `=`.
+    InvalidVar(String),
+    /// A `-var` argument for a variable that is not declared.
+    Undeclared(String, Origin),
+    /// A value that does not convert to the variable's type.
+    Type(String, Origin, TypeError),
+    /// A variable without a default that is given no value.
+    Required(String),
+}
+
+impl fmt::Display for TfvarsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TfvarsError::Declaration(ref name, ref message) => {
+                write!(f, "variable \"{}\": {}", name, message)
+            }
+            TfvarsError::Read(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            TfvarsError::InvalidVar(ref arg) => {
+                write!(f, "-var \"{}\": expected a name, `=` and a value", arg)
+            }
+            TfvarsError::Undeclared(ref name, ref origin) => {
+                write!(f, "{}: variable \"{}\" is not declared", origin, name)
+            }
+            TfvarsError::Type(ref name, ref origin, ref e) => {
+                write!(f, "{}: variable \"{}\": {}", origin, name, e)
+            }
+            TfvarsError::Required(ref name) => {
+                write!(f, "no value for required variable \"{}\"", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TfvarsError {}
+
+/// A resolved value with its origin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assignment {
+    pub value: JsonValue,
+    pub origin: Origin,
+}
+
+/// The values of every variable, and warnings about values given for
+/// variables that are not declared, as Terraform gives for files.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Variables {
+    pub values: BTreeMap<String, Assignment>,
+    pub warnings: Vec<String>,
+}
+
+impl Variables {
+    pub fn get(&self, name: &str) -> Option<&JsonValue> {
+        self.values.get(name).map(|a| &a.value)
+    }
+
+    pub fn origin(&self, name: &str) -> Option<&Origin> {
+        self.values.get(name).map(|a| &a.origin)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Declaration {
+    ty: Type,
+    default: Option<JsonValue>,
+}
+
+#[derive(Clone, Debug)]
+enum Arg {
+    Var(String),
+    VarFile(PathBuf),
+}
+
+/// Resolves the variables a configuration declares.
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    declarations: BTreeMap<String, Declaration>,
+    dir: Option<PathBuf>,
+    args: Vec<Arg>,
+    env: Vec<(String, String)>,
+}
+
+impl Resolver {
+    /// Takes the `variable` blocks of a configuration, as parsed from HCL
+    /// or `.tf.json`.
+    pub fn new(config: &JsonValue) -> Result<Resolver, TfvarsError> {
+        let mut declarations = BTreeMap::new();
+        for (name, body) in variable_blocks(config) {
+            let error = |message: String| TfvarsError::Declaration(name.clone(), message);
+            let default = body.get("default").cloned();
+            let ty = match body.get("type") {
+                Some(ty) => declared_type(ty).ok_or_else(|| error("invalid type".to_string()))?,
+                None => default.as_ref().map_or(Type::Any, loose_type),
+            };
+            let default = match default {
+                Some(default) => Some(ty.convert(&default).map_err(|e| error(e.to_string()))?),
+                None => None,
+            };
+            declarations.insert(name, Declaration { ty, default });
+        }
+        Ok(Resolver {
+            declarations,
+            dir: None,
+            args: Vec::new(),
+            env: std::env::vars().collect(),
+        })
+    }
+
+    /// Sets the directory to find `terraform.tfvars` and `*.auto.tfvars` in.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Resolver {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Adds a `-var-file` argument.
+    pub fn var_file(mut self, path: impl Into<PathBuf>) -> Resolver {
+        self.args.push(Arg::VarFile(path.into()));
+        self
+    }
+
+    /// Adds a `-var` argument, such as `region=eu-west-1`.
+    pub fn var(mut self, arg: &str) -> Resolver {
+        self.args.push(Arg::Var(arg.to_string()));
+        self
+    }
+
+    /// Sets the environment to take `TF_VAR_` variables from, instead of the
+    /// process environment.
+    pub fn env<I>(mut self, vars: I) -> Resolver
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        self.env = vars.into_iter().collect();
+        self
+    }
+
+    pub fn resolve(&self) -> Result<Variables, TfvarsError> {
+        let mut variables = Variables::default();
+        for (name, declaration) in &self.declarations {
+            if let Some(ref default) = declaration.default {
+                let assignment = Assignment {
+                    value: default.clone(),
+                    origin: Origin::Default,
+                };
+                variables.values.insert(name.clone(), assignment);
+            }
+        }
+
+        let mut env: Vec<&(String, String)> = self.env.iter().collect();
+        env.sort();
+        for (var, raw) in env {
+            let name = match var.strip_prefix("TF_VAR_") {
+                Some(name) if self.declarations.contains_key(name) => name,
+                _ => continue,
+            };
+            self.assign_raw(&mut variables, name, raw, Origin::Env(var.clone()))?;
+        }
+
+        if let Some(ref dir) = self.dir {
+            for path in auto_files(dir)? {
+                self.assign_file(&mut variables, &path)?;
+            }
+        }
+
+        for arg in &self.args {
+            match *arg {
+                Arg::VarFile(ref path) => self.assign_file(&mut variables, path)?,
+                Arg::Var(ref arg) => {
+                    let (name, raw) = arg
+                        .split_once('=')
+                        .ok_or_else(|| TfvarsError::InvalidVar(arg.clone()))?;
+                    if !self.declarations.contains_key(name) {
+                        return Err(TfvarsError::Undeclared(name.to_string(), Origin::Var));
+                    }
+                    self.assign_raw(&mut variables, name, raw, Origin::Var)?;
+                }
+            }
+        }
+
+        for name in self.declarations.keys() {
+            if !variables.values.contains_key(name) {
+                return Err(TfvarsError::Required(name.clone()));
+            }
+        }
+        Ok(variables)
+    }
+
+    fn assign(
+        &self,
+        variables: &mut Variables,
+        name: &str,
+        value: &JsonValue,
+        origin: Origin,
+    ) -> Result<(), TfvarsError> {
+        let declaration = &self.declarations[name];
+        let value = declaration
+            .ty
+            .convert(value)
+            .map_err(|e| TfvarsError::Type(name.to_string(), origin.clone(), e))?;
+        variables
+            .values
+            .insert(name.to_string(), Assignment { value, origin });
+        Ok(())
+    }
+
+    // a string from the command line or the environment, which is HCL for
+    // collections and taken literally otherwise
+    fn assign_raw(
+        &self,
+        variables: &mut Variables,
+        name: &str,
+        raw: &str,
+        origin: Origin,
+    ) -> Result<(), TfvarsError> {
+        let value = match self.declarations[name].ty {
+            Type::String | Type::Number | Type::Bool | Type::Any => JsonValue::Str(raw.to_string()),
+            _ => {
+                let parsed = parse_hcl(&format!("value = {}", raw)).map_err(|e| {
+                    let e = TypeError {
+                        path: String::new(),
+                        message: e.to_string(),
+                    };
+                    TfvarsError::Type(name.to_string(), origin.clone(), e)
+                })?;
+                match parsed {
+                    JsonValue::Object(mut top) => top.remove("value").unwrap_or(JsonValue::Null),
+                    _ => JsonValue::Null,
+                }
+            }
+        };
+        self.assign(variables, name, &value, origin)
+    }
+
+    fn assign_file(&self, variables: &mut Variables, path: &Path) -> Result<(), TfvarsError> {
+        let error = |e: ReadError| TfvarsError::Read(path.to_path_buf(), e);
+        let source = fs::read_to_string(path).map_err(|e| error(e.into()))?;
+        let parsed = if path.to_string_lossy().ends_with(".json") {
+            parse_json(&source)
+        } else {
+            parse_hcl(&source)
+        };
+        let values = match parsed.map_err(|e| error(e.into()))? {
+            JsonValue::Object(values) => values,
+            _ => return Ok(()),
+        };
+        let mut names: Vec<&String> = values.keys().collect();
+        names.sort();
+        for name in names {
+            if !self.declarations.contains_key(name) {
+                variables.warnings.push(format!(
+                    "{}: value for undeclared variable \"{}\"",
+                    path.display(),
+                    name
+                ));
+                continue;
+            }
+            let origin = Origin::File(path.to_path_buf());
+            self.assign(variables, name, &values[name], origin)?;
+        }
+        Ok(())
+    }
+}
+
+// terraform.tfvars, then the auto files, in the order Terraform loads them
+fn auto_files(dir: &Path) -> Result<Vec<PathBuf>, TfvarsError> {
+    let mut files = Vec::new();
+    for name in &["terraform.tfvars", "terraform.tfvars.json"] {
+        let path = dir.join(name);
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    let entries = fs::read_dir(dir).map_err(|e| TfvarsError::Read(dir.to_path_buf(), e.into()))?;
+    let mut auto = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| TfvarsError::Read(dir.to_path_buf(), e.into()))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".auto.tfvars") || name.ends_with(".auto.tfvars.json") {
+            auto.push(entry.path());
+        }
+    }
+    auto.sort();
+    files.extend(auto);
+    Ok(files)
+}
+
+// each `variable` block's name and body, in either the hcl1 or the json shape
+fn variable_blocks(config: &JsonValue) -> Vec<(String, BTreeMap<String, JsonValue>)> {
+    let mut out = Vec::new();
+    let blocks = match *config {
+        JsonValue::Object(ref top) => match top.get("variable") {
+            Some(JsonValue::Array(blocks)) => blocks.iter().collect(),
+            Some(block @ &JsonValue::Object(_)) => vec![block],
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+    for block in blocks {
+        if let JsonValue::Object(ref labeled) = *block {
+            for (name, body) in labeled {
+                let body = match *body {
+                    JsonValue::Array(ref bodies) => bodies.first(),
+                    ref body => Some(body),
+                };
+                if let Some(JsonValue::Object(body)) = body {
+                    let body = body.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                    out.push((name.clone(), body));
+                }
+            }
+        }
+    }
+    out
+}
+
+// the type a default suggests, without fixing the length of a list or the
+// keys of a map
+fn loose_type(default: &JsonValue) -> Type {
+    match *default {
+        JsonValue::Array(ref items) if items.len() == 1 && Type::is_object(&items[0]) => {
+            Type::Map(Box::new(Type::Any))
+        }
+        JsonValue::Array(_) => Type::List(Box::new(Type::Any)),
+        JsonValue::Object(_) => Type::Map(Box::new(Type::Any)),
+        ref v => Type::of(v),
+    }
+}
+
+#[test]
+fn tfvars_precedence() {
+    let config = parse_hcl(
+        "variable \"region\" {\n  default = \"us-east-1\"\n}\n\
+         variable \"count\" {\n  type = \"number\"\n}\n\
+         variable \"zones\" {\n  type = \"list(string)\"\n  default = []\n}\n\
+         variable \"tags\" {\n  default = {\n    team = \"core\"\n  }\n}\n\
+         variable \"debug\" {\n  default = false\n}\n",
+    )
+    .unwrap();
+    let dir = TempDir::new("tfvars");
+    dir.write(&[
+        (
+            "terraform.tfvars",
+            "region = \"eu-west-1\"\ncount = \"2\"\n",
+        ),
+        ("b.auto.tfvars", "count = 4\nunknown = 1\n"),
+        ("a.auto.tfvars.json", "{\"count\": 3}"),
+        ("extra.tfvars", "region = \"eu-north-1\"\n"),
+    ]);
+
+    let env = vec![
+        ("TF_VAR_region".to_string(), "ap-south-1".to_string()),
+        ("TF_VAR_debug".to_string(), "true".to_string()),
+    ];
+    let resolved = Resolver::new(&config)
+        .unwrap()
+        .dir(dir.path())
+        .env(env)
+        .var("zones=[\"a\", \"b\"]")
+        .var_file(dir.join("extra.tfvars"))
+        .var("tags={ team = \"infra\" }")
+        .resolve();
+    let resolved = resolved.unwrap();
+
+    assert_eq!(
+        Some(&JsonValue::Str("eu-north-1".to_string())),
+        resolved.get("region")
+    );
+    assert_eq!(
+        Some(&Origin::File(dir.join("extra.tfvars"))),
+        resolved.origin("region")
+    );
+    assert_eq!(Some(&JsonValue::Num(4.0)), resolved.get("count"));
+    assert_eq!(
+        Some(&Origin::File(dir.join("b.auto.tfvars"))),
+        resolved.origin("count")
+    );
+    assert_eq!(
+        Some(&JsonValue::Array(vec![
+            JsonValue::Str("a".to_string()),
+            JsonValue::Str("b".to_string()),
+        ])),
+        resolved.get("zones")
+    );
+    let mut tags = std::collections::HashMap::new();
+    tags.insert("team".to_string(), JsonValue::Str("infra".to_string()));
+    assert_eq!(Some(&JsonValue::Object(tags)), resolved.get("tags"));
+    assert_eq!(Some(&Origin::Var), resolved.origin("tags"));
+    assert_eq!(Some(&JsonValue::Boolean(true)), resolved.get("debug"));
+    assert_eq!(
+        Some(&Origin::Env("TF_VAR_debug".to_string())),
+        resolved.origin("debug")
+    );
+    assert_eq!(
+        vec![format!(
+            "{}: value for undeclared variable \"unknown\"",
+            dir.join("b.auto.tfvars").display()
+        )],
+        resolved.warnings
+    );
+
+    let error = |resolver: Resolver| resolver.resolve().unwrap_err().to_string();
+    let resolver = Resolver::new(&config).unwrap();
+    assert_eq!(
+        "no value for required variable \"count\"",
+        error(resolver.clone())
+    );
+    assert_eq!(
+        "-var: variable \"count\": a number is required, found \"many\"",
+        error(resolver.clone().var("count=many"))
+    );
+    assert_eq!(
+        "-var: variable \"nope\" is not declared",
+        error(resolver.var("nope=1"))
+    );
+}
@@ -1,5 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::io;
+
+use crate::expr::{parse_expr, Expr};
+use crate::schema::Schema;
+use crate::template::TemplatePart;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum JsonValue {
@@ -8,6 +13,141 @@ pub enum JsonValue {
     Array(Vec<JsonValue>),
     Object(HashMap<String, JsonValue>),
     Boolean(bool),
+    Null,
+}
+
+impl JsonValue {
+    /// Returns the HCL name of this value's type, for use in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            JsonValue::Str(_) => "string",
+            JsonValue::Num(_) => "number",
+            JsonValue::Array(_) => "tuple",
+            JsonValue::Object(_) => "object",
+            JsonValue::Boolean(_) => "bool",
+            JsonValue::Null => "null",
+        }
+    }
+}
+
+/// How `JsonValue::merge` combines arrays. Objects are always merged key by
+/// key, and anything else is replaced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MergeStrategy {
+    /// The other array replaces this one.
+    Replace,
+    /// The other array's items follow this one's.
+    Append,
+    /// Arrays of objects are treated as the unlabeled blocks `parse_hcl`
+    /// produces, each body merging into the first. Labeled blocks are only
+    /// matched by label by `JsonValue::merge_with_schema`, which knows how
+    /// many labels they have. Other arrays are replaced.
+    Blocks,
+}
+
+impl JsonValue {
+    /// Merges `other` into this value, as when layering an environment's
+    /// configuration over the defaults.
+    pub fn merge(&mut self, other: JsonValue, strategy: MergeStrategy) {
+        self.merge_in(other, strategy, None)
+    }
+
+    /// Merges `other` into this value like `MergeStrategy::Blocks`, merging
+    /// the blocks `schema` declares into the blocks with the same labels.
+    pub fn merge_with_schema(&mut self, other: JsonValue, schema: &Schema) {
+        self.merge_in(other, MergeStrategy::Blocks, Some(schema))
+    }
+
+    fn merge_in(&mut self, other: JsonValue, strategy: MergeStrategy, schema: Option<&Schema>) {
+        match (self, other) {
+            (&mut JsonValue::Object(ref mut base), JsonValue::Object(other)) => {
+                for (key, value) in other {
+                    let existing = match base.get_mut(&key) {
+                        Some(existing) => existing,
+                        None => {
+                            base.insert(key, value);
+                            continue;
+                        }
+                    };
+                    if strategy != MergeStrategy::Blocks {
+                        existing.merge_in(value, strategy, None);
+                        continue;
+                    }
+                    match schema.and_then(|s| s.block_schema(&key)) {
+                        Some((labels, body)) => merge_labeled(existing, value, labels, Some(body)),
+                        None => merge_labeled(existing, value, 0, None),
+                    }
+                }
+            }
+            (&mut JsonValue::Array(ref mut base), JsonValue::Array(other)) => match strategy {
+                MergeStrategy::Append => base.extend(other),
+                _ => *base = other,
+            },
+            (this, other) => *this = other,
+        }
+    }
+}
+
+fn is_blocks(items: &[JsonValue]) -> bool {
+    items
+        .iter()
+        .all(|item| matches!(*item, JsonValue::Object(_)))
+}
+
+// merges blocks with `labels` labels, in the shape hcl1 gives them or as
+// json objects, keeping other values to `MergeStrategy::Blocks`
+fn merge_labeled(base: &mut JsonValue, other: JsonValue, labels: usize, body: Option<&Schema>) {
+    let strategy = MergeStrategy::Blocks;
+    let base_is_object = matches!(*base, JsonValue::Object(_));
+    let base_is_blocks = matches!(*base, JsonValue::Array(ref items) if is_blocks(items));
+    let other = match other {
+        JsonValue::Array(other) if is_blocks(&other) && (base_is_blocks || base_is_object) => other,
+        other @ JsonValue::Object(_) if base_is_blocks || (base_is_object && labels > 0) => {
+            vec![other]
+        }
+        other => return base.merge_in(other, strategy, body),
+    };
+    for item in other {
+        if labels == 0 {
+            // an unlabeled body merges into the first
+            match *base {
+                JsonValue::Array(ref mut items) if !items.is_empty() => {
+                    items[0].merge_in(item, strategy, body)
+                }
+                JsonValue::Array(ref mut items) => items.push(item),
+                _ => base.merge_in(item, strategy, body),
+            }
+            continue;
+        }
+        let item = match item {
+            JsonValue::Object(item) => item,
+            _ => unreachable!("blocks are objects"),
+        };
+        for (label, inner) in item {
+            let existing = match *base {
+                JsonValue::Array(ref mut items) => items.iter_mut().find_map(|b| match *b {
+                    JsonValue::Object(ref mut b) => b.get_mut(&label),
+                    _ => None,
+                }),
+                JsonValue::Object(ref mut b) => b.get_mut(&label),
+                _ => None,
+            };
+            match existing {
+                Some(existing) => merge_labeled(existing, inner, labels - 1, body),
+                None => match *base {
+                    JsonValue::Array(ref mut items) => {
+                        let mut block = HashMap::new();
+                        block.insert(label, inner);
+                        items.push(JsonValue::Object(block));
+                    }
+                    JsonValue::Object(ref mut b) => {
+                        b.insert(label, inner);
+                    }
+                    _ => unreachable!("blocks are objects or lists of them"),
+                },
+            }
+        }
+    }
 }
 
 #[allow(unused_must_use)]
@@ -40,9 +180,777 @@ impl fmt::Display for JsonValue {
                 Result::Ok(())
             }
             JsonValue::Str(ref string) => write!(f, "\"{}\"", string.escape_default()),
-            JsonValue::Num(number) => write!(f, "{}", number.to_string()),
-            JsonValue::Boolean(boolean) => write!(f, "{}", boolean.to_string()),
+            JsonValue::Num(number) => write!(f, "{}", number),
+            JsonValue::Boolean(boolean) => write!(f, "{}", boolean),
+            JsonValue::Null => write!(f, "null"),
         }
     }
 }
-pub type ParseError = u32;
+/// An HCL value type, following the cty type system used by HCL2.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    String,
+    Number,
+    Bool,
+    List(Box<Type>),
+    Set(Box<Type>),
+    Map(Box<Type>),
+    Object(BTreeMap<String, Type>),
+    Tuple(Vec<Type>),
+    Any,
+}
+
+/// A value that may not be known yet, such as a resource attribute at plan time.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Known(JsonValue),
+    Unknown(Type),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+fn type_error(path: &str, message: String) -> TypeError {
+    TypeError {
+        path: path.to_string(),
+        message,
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Type::String => write!(f, "string"),
+            Type::Number => write!(f, "number"),
+            Type::Bool => write!(f, "bool"),
+            Type::Any => write!(f, "any"),
+            Type::List(ref t) => write!(f, "list({})", t),
+            Type::Set(ref t) => write!(f, "set({})", t),
+            Type::Map(ref t) => write!(f, "map({})", t),
+            Type::Object(ref attrs) => {
+                write!(f, "object({{")?;
+                for (n, (name, t)) in attrs.iter().enumerate() {
+                    let sep = if n == 0 { " " } else { ", " };
+                    write!(f, "{}{} = {}", sep, name, t)?;
+                }
+                if attrs.is_empty() {
+                    write!(f, "}})")
+                } else {
+                    write!(f, " }})")
+                }
+            }
+            Type::Tuple(ref elems) => {
+                write!(f, "tuple([")?;
+                for (n, t) in elems.iter().enumerate() {
+                    if n != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", t)?;
+                }
+                write!(f, "])")
+            }
+        }
+    }
+}
+
+/// Parses a type constraint such as `list(object({ name = string }))`.
+///
+/// The quoted legacy forms `"string"`, `"list"` and `"map"` are also accepted.
+pub fn parse_type(source: &str) -> Result<Type, ParseError> {
+    type_from_expr(&parse_expr(source)?).ok_or_else(|| ParseError::new(ParseErrorKind::Syntax, 0))
+}
+
+fn type_from_expr(e: &Expr) -> Option<Type> {
+    match *e {
+        Expr::Variable(ref name) => match name.as_str() {
+            "string" => Some(Type::String),
+            "number" => Some(Type::Number),
+            "bool" => Some(Type::Bool),
+            "any" => Some(Type::Any),
+            _ => None,
+        },
+        Expr::Template(ref parts) => match parts[..] {
+            [TemplatePart::Literal(ref s)] => match s.as_str() {
+                "string" => Some(Type::String),
+                "list" => Some(Type::List(Box::new(Type::Any))),
+                "map" => Some(Type::Map(Box::new(Type::Any))),
+                _ => None,
+            },
+            _ => None,
+        },
+        Expr::FunctionCall(ref name, ref args, false) if args.len() == 1 => {
+            match (name.as_str(), &args[0]) {
+                ("list", arg) => Some(Type::List(Box::new(type_from_expr(arg)?))),
+                ("set", arg) => Some(Type::Set(Box::new(type_from_expr(arg)?))),
+                ("map", arg) => Some(Type::Map(Box::new(type_from_expr(arg)?))),
+                ("object", Expr::Object(items)) => {
+                    let mut attrs = BTreeMap::new();
+                    for (k, v) in items {
+                        let name = match *k {
+                            Expr::Variable(ref name) => name.clone(),
+                            Expr::Template(ref parts) => match parts[..] {
+                                [TemplatePart::Literal(ref s)] => s.clone(),
+                                _ => return None,
+                            },
+                            _ => return None,
+                        };
+                        attrs.insert(name, type_from_expr(v)?);
+                    }
+                    Some(Type::Object(attrs))
+                }
+                ("tuple", Expr::Tuple(items)) => items
+                    .iter()
+                    .map(type_from_expr)
+                    .collect::<Option<Vec<_>>>()
+                    .map(Type::Tuple),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+impl Type {
+    /// Infers the most specific type of a value. `null` has type `any`.
+    pub fn of(v: &JsonValue) -> Type {
+        match *v {
+            JsonValue::Str(_) => Type::String,
+            JsonValue::Num(_) => Type::Number,
+            JsonValue::Boolean(_) => Type::Bool,
+            JsonValue::Null => Type::Any,
+            JsonValue::Array(ref items) => Type::Tuple(items.iter().map(Type::of).collect()),
+            JsonValue::Object(ref h) => {
+                Type::Object(h.iter().map(|(k, v)| (k.clone(), Type::of(v))).collect())
+            }
+        }
+    }
+
+    /// Converts a value to this type, or explains why it cannot be converted.
+    pub fn convert(&self, v: &JsonValue) -> Result<JsonValue, TypeError> {
+        self.convert_at("", v)
+    }
+
+    pub(crate) fn convert_at(&self, path: &str, v: &JsonValue) -> Result<JsonValue, TypeError> {
+        let mismatch = || type_error(path, format!("expected {}, found {}", self, v.type_name()));
+        match (self, v) {
+            (&Type::Any, _) | (_, &JsonValue::Null) => Ok(v.clone()),
+            (&Type::String, &JsonValue::Str(_)) => Ok(v.clone()),
+            (&Type::String, &JsonValue::Num(n)) => Ok(JsonValue::Str(n.to_string())),
+            (&Type::String, &JsonValue::Boolean(b)) => Ok(JsonValue::Str(b.to_string())),
+            (&Type::Number, &JsonValue::Num(_)) => Ok(v.clone()),
+            (&Type::Number, JsonValue::Str(s)) => s
+                .parse()
+                .map(JsonValue::Num)
+                .map_err(|_| type_error(path, format!("a number is required, found \"{}\"", s))),
+            (&Type::Bool, &JsonValue::Boolean(_)) => Ok(v.clone()),
+            (&Type::Bool, JsonValue::Str(s)) if s == "true" || s == "false" => {
+                Ok(JsonValue::Boolean(s == "true"))
+            }
+            (Type::List(t), JsonValue::Array(items)) => items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| t.convert_at(&format!("{}[{}]", path, i), item))
+                .collect::<Result<Vec<_>, _>>()
+                .map(JsonValue::Array),
+            (Type::Set(t), JsonValue::Array(items)) => {
+                let mut out: Vec<JsonValue> = Vec::new();
+                for (i, item) in items.iter().enumerate() {
+                    let item = t.convert_at(&format!("{}[{}]", path, i), item)?;
+                    if !out.contains(&item) {
+                        out.push(item);
+                    }
+                }
+                Ok(JsonValue::Array(out))
+            }
+            // hcl1 represents nested objects as a single-element list of objects
+            (&Type::Map(_), &JsonValue::Array(ref items))
+            | (&Type::Object(_), &JsonValue::Array(ref items))
+                if items.len() == 1 && Type::is_object(&items[0]) =>
+            {
+                self.convert_at(path, &items[0])
+            }
+            (Type::Map(t), JsonValue::Object(h)) => {
+                let mut out = HashMap::new();
+                for (k, item) in h {
+                    out.insert(
+                        k.clone(),
+                        t.convert_at(&format!("{}[\"{}\"]", path, k), item)?,
+                    );
+                }
+                Ok(JsonValue::Object(out))
+            }
+            (Type::Object(attrs), JsonValue::Object(h)) => {
+                let mut out = HashMap::new();
+                for (name, t) in attrs {
+                    let attr_path = format!("{}.{}", path, name);
+                    match h.get(name) {
+                        Some(item) => {
+                            out.insert(name.clone(), t.convert_at(&attr_path, item)?);
+                        }
+                        None => {
+                            return Err(type_error(
+                                path,
+                                format!("attribute \"{}\" is required", name),
+                            ))
+                        }
+                    }
+                }
+                Ok(JsonValue::Object(out))
+            }
+            (Type::Tuple(elems), JsonValue::Array(items)) => {
+                if elems.len() != items.len() {
+                    return Err(type_error(
+                        path,
+                        format!(
+                            "tuple of {} elements is required, found {}",
+                            elems.len(),
+                            items.len()
+                        ),
+                    ));
+                }
+                elems
+                    .iter()
+                    .zip(items)
+                    .enumerate()
+                    .map(|(i, (t, item))| t.convert_at(&format!("{}[{}]", path, i), item))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(JsonValue::Array)
+            }
+            _ => Err(mismatch()),
+        }
+    }
+
+    pub(crate) fn is_object(v: &JsonValue) -> bool {
+        matches!(*v, JsonValue::Object(_))
+    }
+
+    /// Reports whether values of type `from` can always be converted to this type.
+    pub fn accepts(&self, from: &Type) -> bool {
+        match (self, from) {
+            (&Type::Any, _) | (_, &Type::Any) => true,
+            (&Type::String, &Type::Number) | (&Type::String, &Type::Bool) => true,
+            (&Type::List(ref t), &Type::List(ref f))
+            | (&Type::List(ref t), &Type::Set(ref f))
+            | (&Type::Set(ref t), &Type::Set(ref f))
+            | (&Type::Set(ref t), &Type::List(ref f))
+            | (&Type::Map(ref t), &Type::Map(ref f)) => t.accepts(f),
+            (&Type::List(ref t), &Type::Tuple(ref fs))
+            | (&Type::Set(ref t), &Type::Tuple(ref fs)) => fs.iter().all(|f| t.accepts(f)),
+            (Type::Map(t), Type::Object(fs)) => fs.values().all(|f| t.accepts(f)),
+            (Type::Object(ts), Type::Object(fs)) => ts
+                .iter()
+                .all(|(name, t)| fs.get(name).is_some_and(|f| t.accepts(f))),
+            (Type::Tuple(ts), Type::Tuple(fs)) => {
+                ts.len() == fs.len() && ts.iter().zip(fs).all(|(t, f)| t.accepts(f))
+            }
+            (t, f) => t == f,
+        }
+    }
+
+    /// Finds a single type that all of the given types can be converted to.
+    pub fn unify(types: &[Type]) -> Option<Type> {
+        let first = types.first()?;
+        if types.iter().all(|t| t == first) {
+            return Some(first.clone());
+        }
+        if types.contains(&Type::Any) {
+            return Some(Type::Any);
+        }
+        if types.iter().all(Type::is_primitive) {
+            return Some(Type::String);
+        }
+        if types.iter().all(|t| matches!(*t, Type::Object(_))) {
+            if let Some(t) = Type::unify_objects(types) {
+                return Some(t);
+            }
+        }
+        if types.iter().all(|t| matches!(*t, Type::Tuple(_))) {
+            if let Some(t) = Type::unify_tuples(types) {
+                return Some(t);
+            }
+        }
+        // otherwise fall back to a collection of a single unified element type
+        let mut kind = None;
+        let mut elems = Vec::new();
+        for t in types {
+            let (k, mut inner) = match *t {
+                Type::List(ref e) => ("list", vec![(**e).clone()]),
+                Type::Set(ref e) => ("set", vec![(**e).clone()]),
+                Type::Tuple(ref es) => ("list", es.clone()),
+                Type::Map(ref e) => ("map", vec![(**e).clone()]),
+                Type::Object(ref attrs) => ("map", attrs.values().cloned().collect()),
+                _ => return None,
+            };
+            match kind {
+                None => kind = Some(k),
+                Some(prev) if prev == k => {}
+                // sets may be converted to lists but not to maps
+                Some("list") if k == "set" => {}
+                Some("set") if k == "list" => kind = Some("list"),
+                _ => return None,
+            }
+            elems.append(&mut inner);
+        }
+        let elem = if elems.is_empty() {
+            Type::Any
+        } else {
+            Type::unify(&elems)?
+        };
+        match kind {
+            Some("list") => Some(Type::List(Box::new(elem))),
+            Some("set") => Some(Type::Set(Box::new(elem))),
+            Some("map") => Some(Type::Map(Box::new(elem))),
+            _ => None,
+        }
+    }
+
+    fn is_primitive(t: &Type) -> bool {
+        matches!(*t, Type::String | Type::Number | Type::Bool)
+    }
+
+    fn unify_objects(types: &[Type]) -> Option<Type> {
+        let mut names: Option<Vec<&String>> = None;
+        for t in types {
+            if let Type::Object(ref attrs) = *t {
+                let keys: Vec<&String> = attrs.keys().collect();
+                match names {
+                    None => names = Some(keys),
+                    Some(ref n) if *n == keys => {}
+                    _ => return None,
+                }
+            }
+        }
+        let mut out = BTreeMap::new();
+        for name in names? {
+            let attr_types: Vec<Type> = types
+                .iter()
+                .filter_map(|t| match *t {
+                    Type::Object(ref attrs) => attrs.get(name).cloned(),
+                    _ => None,
+                })
+                .collect();
+            out.insert(name.clone(), Type::unify(&attr_types)?);
+        }
+        Some(Type::Object(out))
+    }
+
+    fn unify_tuples(types: &[Type]) -> Option<Type> {
+        let elems: Vec<&Vec<Type>> = types
+            .iter()
+            .filter_map(|t| match *t {
+                Type::Tuple(ref es) => Some(es),
+                _ => None,
+            })
+            .collect();
+        let len = elems[0].len();
+        if elems.iter().any(|es| es.len() != len) {
+            return None;
+        }
+        (0..len)
+            .map(|i| Type::unify(&elems.iter().map(|es| es[i].clone()).collect::<Vec<_>>()))
+            .collect::<Option<Vec<_>>>()
+            .map(Type::Tuple)
+    }
+}
+
+impl Value {
+    pub fn type_of(&self) -> Type {
+        match *self {
+            Value::Known(ref v) => Type::of(v),
+            Value::Unknown(ref t) => t.clone(),
+        }
+    }
+
+    /// Converts a known value, or checks that an unknown one will be convertible.
+    pub fn convert(&self, ty: &Type) -> Result<Value, TypeError> {
+        match *self {
+            Value::Known(ref v) => ty.convert(v).map(Value::Known),
+            Value::Unknown(ref t) if ty.accepts(t) => Ok(Value::Unknown(ty.clone())),
+            Value::Unknown(ref t) => Err(type_error(
+                "",
+                format!("expected {}, found unknown {}", ty, t),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    /// Input the grammar does not allow.
+    Syntax,
+    /// A single `<` where a heredoc's `<<` was expected.
+    HeredocIntroducer,
+    /// `<<` or `<<-` not followed by an identifier and a newline.
+    HeredocMarker,
+    /// No line closes the heredoc with its marker.
+    HeredocUnterminated(String),
+    /// Bytes that are not UTF-8 text.
+    InvalidUtf8,
+    /// A NUL byte, which only binary files contain.
+    Binary,
+    /// A file encrypted by git-crypt, checked out without its key.
+    Encrypted,
+    /// A key defined again, with the offset of its first definition.
+    DuplicateKey(String, usize),
+    /// An interpolation that is not a valid expression.
+    Interpolation,
+    /// Input past one of the limits a `Parser` is configured with.
+    LimitExceeded(Limit),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Depth,
+    InputSize,
+    StringLength,
+    ArrayLength,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Limit::Depth => write!(f, "nesting depth"),
+            Limit::InputSize => write!(f, "input size"),
+            Limit::StringLength => write!(f, "string length"),
+            Limit::ArrayLength => write!(f, "array length"),
+        }
+    }
+}
+
+/// Why and where parsing failed; `offset` is a byte offset into the input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub offset: usize,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, offset: usize) -> ParseError {
+        ParseError { kind, offset }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::Syntax => write!(f, "syntax error"),
+            ParseErrorKind::HeredocIntroducer => {
+                write!(f, "heredoc must be introduced with `<<` or `<<-`")
+            }
+            ParseErrorKind::HeredocMarker => write!(
+                f,
+                "heredoc marker must be an identifier followed by a newline"
+            ),
+            ParseErrorKind::HeredocUnterminated(ref marker) => {
+                write!(f, "heredoc is not closed by a `{}` line", marker)
+            }
+            ParseErrorKind::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            ParseErrorKind::Binary => write!(f, "binary content"),
+            ParseErrorKind::Encrypted => write!(f, "git-crypt encrypted content"),
+            ParseErrorKind::DuplicateKey(ref key, _) => write!(f, "duplicate key `{}`", key),
+            ParseErrorKind::Interpolation => write!(f, "invalid interpolation"),
+            ParseErrorKind::LimitExceeded(limit) => write!(f, "{} limit exceeded", limit),
+        }?;
+        write!(f, " at byte {}", self.offset)?;
+        if let ParseErrorKind::DuplicateKey(_, first) = self.kind {
+            write!(f, ", first defined at byte {}", first)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The plain error code `ParseError` used to be, which was 0 for every
+/// failure, for callers not yet matching on `kind`.
+impl From<ParseError> for u32 {
+    fn from(_: ParseError) -> u32 {
+        0
+    }
+}
+
+impl PartialEq<u32> for ParseError {
+    fn eq(&self, code: &u32) -> bool {
+        *code == 0
+    }
+}
+
+/// Why parsing from a reader failed: the reader itself, or what it produced.
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Io(ref e) => write!(f, "read failed: {}", e),
+            ReadError::Parse(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            ReadError::Io(ref e) => Some(e),
+            ReadError::Parse(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> ReadError {
+        ReadError::Io(e)
+    }
+}
+
+impl From<ParseError> for ReadError {
+    fn from(e: ParseError) -> ReadError {
+        ReadError::Parse(e)
+    }
+}
+
+#[test]
+fn type_constraint_parse() {
+    let t = parse_type("list(object({ name = string, port = number }))").unwrap();
+    let mut attrs = BTreeMap::new();
+    attrs.insert("name".to_string(), Type::String);
+    attrs.insert("port".to_string(), Type::Number);
+    assert_eq!(Type::List(Box::new(Type::Object(attrs))), t);
+    assert_eq!(
+        "list(object({ name = string, port = number }))",
+        t.to_string()
+    );
+
+    assert_eq!(
+        Ok(Type::Tuple(vec![
+            Type::Bool,
+            Type::Map(Box::new(Type::Any))
+        ])),
+        parse_type("tuple([bool, map(any)])")
+    );
+    assert_eq!(Ok(Type::List(Box::new(Type::Any))), parse_type("\"list\""));
+    assert!(parse_type("list(strin)").is_err());
+    assert!(parse_type("object(string)").is_err());
+}
+
+#[test]
+fn type_convert() {
+    let t = parse_type("list(object({ name = string, port = number }))").unwrap();
+    let mut web = HashMap::new();
+    web.insert("name".to_string(), JsonValue::Str("web".to_string()));
+    web.insert("port".to_string(), JsonValue::Str("80".to_string()));
+    let mut db = HashMap::new();
+    db.insert("name".to_string(), JsonValue::Str("db".to_string()));
+    let value = JsonValue::Array(vec![JsonValue::Object(web), JsonValue::Object(db)]);
+
+    let err = t.convert(&value).unwrap_err();
+    assert_eq!("[1]: attribute \"port\" is required", err.to_string());
+
+    if let JsonValue::Array(ref items) = value {
+        let converted = t
+            .convert(&JsonValue::Array(vec![items[0].clone()]))
+            .unwrap();
+        if let JsonValue::Array(ref items) = converted {
+            if let Some(JsonValue::Object(dict)) = items.first() {
+                return assert_eq!(Some(&JsonValue::Num(80.)), dict.get("port"));
+            }
+        }
+    }
+    panic!("value did not convert");
+}
+
+#[test]
+fn type_convert_primitives() {
+    assert_eq!(
+        Ok(JsonValue::Str("1.5".to_string())),
+        Type::String.convert(&JsonValue::Num(1.5))
+    );
+    assert_eq!(
+        Ok(JsonValue::Boolean(true)),
+        Type::Bool.convert(&JsonValue::Str("true".to_string()))
+    );
+    assert_eq!(Ok(JsonValue::Null), Type::Number.convert(&JsonValue::Null));
+    assert!(Type::Number
+        .convert(&JsonValue::Str("x".to_string()))
+        .is_err());
+    assert_eq!(
+        Ok(JsonValue::Array(vec![JsonValue::Num(1.)])),
+        Type::Set(Box::new(Type::Number)).convert(&JsonValue::Array(vec![
+            JsonValue::Num(1.),
+            JsonValue::Str("1".to_string())
+        ]))
+    );
+}
+
+#[test]
+fn type_tf_variable_default() {
+    let source = include_str!("../tests/test-fixtures/decode_tf_variable.hcl");
+    let parsed = crate::hcl::parse_hcl(source).unwrap();
+    let default = |name: &str| -> JsonValue {
+        if let JsonValue::Object(ref top) = parsed {
+            if let Some(JsonValue::Array(blocks)) = top.get("variable") {
+                for block in blocks {
+                    if let JsonValue::Object(ref labels) = *block {
+                        if let Some(JsonValue::Array(bodies)) = labels.get(name) {
+                            if let Some(JsonValue::Object(body)) = bodies.first() {
+                                return body.get("default").unwrap().clone();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        panic!("variable {} not found", name);
+    };
+
+    let amis = default("amis");
+    let mut expected = HashMap::new();
+    expected.insert("east".to_string(), JsonValue::Str("foo".to_string()));
+    assert_eq!(
+        Ok(JsonValue::Object(expected)),
+        parse_type("map(string)").unwrap().convert(&amis)
+    );
+    assert!(parse_type("string").unwrap().convert(&amis).is_err());
+    assert!(parse_type("string")
+        .unwrap()
+        .convert(&default("foo"))
+        .is_ok());
+}
+
+#[test]
+fn type_unify() {
+    assert_eq!(
+        Some(Type::String),
+        Type::unify(&[Type::Number, Type::String, Type::Bool])
+    );
+    assert_eq!(
+        Some(Type::List(Box::new(Type::String))),
+        Type::unify(&[
+            Type::Tuple(vec![Type::String]),
+            Type::Tuple(vec![Type::Number, Type::String]),
+        ])
+    );
+    let obj = |t: Type| {
+        let mut attrs = BTreeMap::new();
+        attrs.insert("a".to_string(), t);
+        Type::Object(attrs)
+    };
+    assert_eq!(
+        Some(obj(Type::String)),
+        Type::unify(&[obj(Type::Number), obj(Type::String)])
+    );
+    assert_eq!(None, Type::unify(&[Type::Number, obj(Type::Number)]));
+}
+
+#[test]
+fn type_unknown_values() {
+    let unknown = Value::Unknown(Type::Number);
+    assert_eq!(
+        Ok(Value::Unknown(Type::String)),
+        unknown.convert(&Type::String)
+    );
+    assert!(unknown
+        .convert(&Type::List(Box::new(Type::String)))
+        .is_err());
+    assert_eq!(Type::Number, unknown.type_of());
+}
+
+#[test]
+fn merge_values() {
+    let parse = |s: &str| crate::hcl::parse_hcl(s).unwrap();
+    let base = parse("tags = [\"a\"]\nport = 80\ndb {\n  host = \"localhost\"\n  pool = 5\n}\n");
+    let env = parse("tags = [\"b\"]\nport = 8080\ndb {\n  host = \"db.internal\"\n}\n");
+
+    let mut replaced = base.clone();
+    replaced.merge(env.clone(), MergeStrategy::Replace);
+    assert_eq!(
+        parse("tags = [\"b\"]\nport = 8080\ndb {\n  host = \"db.internal\"\n}\n"),
+        replaced
+    );
+
+    let mut appended = base.clone();
+    appended.merge(env.clone(), MergeStrategy::Append);
+    assert_eq!(
+        parse(
+            "tags = [\"a\", \"b\"]\nport = 8080\n\
+             db {\n  host = \"localhost\"\n  pool = 5\n}\ndb {\n  host = \"db.internal\"\n}\n"
+        ),
+        appended
+    );
+
+    let mut blocks = base;
+    blocks.merge(env, MergeStrategy::Blocks);
+    assert_eq!(
+        parse("tags = [\"b\"]\nport = 8080\ndb {\n  host = \"db.internal\"\n  pool = 5\n}\n"),
+        blocks
+    );
+}
+
+#[test]
+fn merge_labeled_blocks() {
+    let parse = |s: &str| crate::hcl::parse_hcl(s).unwrap();
+    let mut base = parse(
+        "service \"foo\" {\n  port = 80\n  check \"http\" {\n  path = \"/\"\n  }\n}\n\
+         service \"bar\" {\n  port = 81\n}\n",
+    );
+    let env = parse(
+        "service \"foo\" {\n  replicas = 3\n  check \"http\" {\n  interval = 10\n  }\n}\n\
+         service \"baz\" {\n  port = 82\n}\n",
+    );
+    let schema = Schema::new().block("service", 1, Schema::new().block("check", 1, Schema::new()));
+    base.merge_with_schema(env, &schema);
+    assert_eq!(
+        parse(
+            "service \"foo\" {\n  port = 80\n  replicas = 3\n\
+             check \"http\" {\n  path = \"/\"\n  interval = 10\n  }\n}\n\
+             service \"bar\" {\n  port = 81\n}\n\
+             service \"baz\" {\n  port = 82\n}\n"
+        ),
+        base
+    );
+}
+
+#[test]
+fn merge_nested_blocks() {
+    let parse = |s: &str| crate::hcl::parse_hcl(s).unwrap();
+    let mut base = parse("db {\n  pool {\n    size = 1\n  }\n}\n");
+    base.merge(parse("db {\n  host = \"x\"\n}\n"), MergeStrategy::Blocks);
+    assert_eq!(
+        parse("db {\n  host = \"x\"\n  pool {\n    size = 1\n  }\n}\n"),
+        base
+    );
+
+    // labeled blocks written as json objects merge with hcl1 blocks
+    let mut base = parse("service \"foo\" {\n  port = 80\n}\n");
+    let env = crate::json::parse_json("{\"service\": {\"foo\": {\"port\": 81}}}");
+    base.merge_with_schema(
+        env.unwrap(),
+        &Schema::new().block("service", 1, Schema::new()),
+    );
+    assert_eq!(parse("service \"foo\" {\n  port = 81\n}\n"), base);
+}
+
+#[test]
+fn parse_error_code() {
+    let err = crate::hcl::parse_hcl("a = ").unwrap_err();
+    assert_eq!(0, u32::from(err.clone()));
+    assert!(err == 0);
+}
@@ -2,8 +2,13 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 
-use molysite::hcl::parse_hcl;
+use molysite::hcl::{parse_hcl, parse_hcl_bytes};
+use molysite::hcl2::parse_hcl2;
 use molysite::json::parse_json;
+use molysite::lexer::tokenize;
+use molysite::parser::{Parser, SAFE_MAX_DEPTH};
+use molysite::schema::Schema;
+use molysite::types::{Limit, ParseError, ParseErrorKind, Type};
 
 macro_rules! fixture_tests {
     ($($name:ident: $value:expr,)*) => {
@@ -22,7 +27,8 @@ fixture_tests! {
     test_fixture_basic: ("basic", true),
     test_fixture_basic_int_string: ("basic_int_string", true),
     test_fixture_basic_squish: ("basic_squish", true),
-    //test_fixture_block_assign: ("block_assign", false),
+    test_fixture_bom: ("bom", true),
+    test_fixture_block_assign: ("block_assign", false),
     test_fixture_decode_policy: ("decode_policy", true),
     test_fixture_decode_tf_variable: ("decode_tf_variable", true),
     test_fixture_empty: ("empty", true),
@@ -30,17 +36,18 @@ fixture_tests! {
     test_fixture_escape_backslash: ("escape_backslash", true),
     test_fixture_flat: ("flat", true),
     test_fixture_float: ("float", true),
-    //test_fixture_git_crypt: ("git_crypt", false),
+    test_fixture_git_crypt: ("git_crypt", false),
+    test_fixture_line_endings: ("line_endings", true),
     test_fixture_list_of_lists: ("list_of_lists", true),
     test_fixture_list_of_maps: ("list_of_maps", true),
     test_fixture_multiline: ("multiline", true),
-    //test_fixture_multiline_bad: ("multiline_bad", false),
+    test_fixture_multiline_bad: ("multiline_bad", false),
     test_fixture_multiline_indented: ("multiline_indented", true),
-    //test_fixture_multiline_literal: ("multiline_literal", false),
+    test_fixture_multiline_literal: ("multiline_literal", false),
     test_fixture_multiline_literal_with_hil: ("multiline_literal_with_hil", true),
     test_fixture_multiline_no_eof: ("multiline_no_eof", true),
     test_fixture_multiline_no_hanging_indent: ("multiline_no_hanging_indent", true),
-    //test_fixture_multiline_no_marker: ("multiline_no_marker", false),
+    test_fixture_multiline_no_marker: ("multiline_no_marker", false),
     test_fixture_nested_block_comment: ("nested_block_comment", true),
     //test_fixture_nested_provider_bad: ("nested_provider_bad", false),
     test_fixture_object_with_bool: ("object_with_bool", true),
@@ -53,10 +60,11 @@ fixture_tests! {
     test_fixture_structure_multi: ("structure_multi", true),
     test_fixture_terraform_heroku: ("terraform_heroku", true),
     test_fixture_tfvars: ("tfvars", true),
-    //test_fixture_unterminated_block_comment: ("unterminated_block_comment", false),
-    //test_fixture_unterminated_brace: ("unterminated_brace", false),
+    test_fixture_unterminated_block_comment: ("unterminated_block_comment", false),
+    test_fixture_unterminated_brace: ("unterminated_brace", false),
 }
 
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn test_fixture(case: &str, expect_pass: bool) {
     let mut hcl = String::new();
     let mut json = String::new();
@@ -77,6 +85,7 @@ fn test_fixture(case: &str, expect_pass: bool) {
     let parsed_hcl = parse_hcl(&hcl);
     if let Ok(parsed_hcl) = parsed_hcl {
         if expect_pass {
+            check_tokens(&hcl);
             let parsed_json = parse_json(&json).unwrap();
             assert_eq!(parsed_hcl, parsed_json);
         } else {
@@ -88,3 +97,152 @@ fn test_fixture(case: &str, expect_pass: bool) {
         }
     }
 }
+
+// tokens must cover the source in order, with only spaces and tabs between
+fn check_tokens(hcl: &str) {
+    let mut end = if hcl.starts_with('\u{feff}') { 3 } else { 0 };
+    for token in tokenize(hcl).unwrap() {
+        assert!(hcl[end..token.span.start]
+            .chars()
+            .all(|c| c == ' ' || c == '\t'));
+        assert_eq!(&hcl[token.span.start..token.span.end], token.text);
+        end = token.span.end;
+    }
+    assert!(hcl[end..].trim().is_empty());
+}
+
+fn read_fixture(case: &str) -> String {
+    let mut hcl = String::new();
+    let path = format!("tests/test-fixtures/{}.hcl", case);
+    let mut file = File::open(Path::new(&path)).unwrap();
+    file.read_to_string(&mut hcl).unwrap();
+    hcl
+}
+
+#[test]
+fn test_fixture_heredoc_errors() {
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::HeredocIntroducer, 6)),
+        parse_hcl(&read_fixture("multiline_bad"))
+    );
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::HeredocMarker, 8)),
+        parse_hcl(&read_fixture("multiline_no_marker"))
+    );
+}
+
+#[test]
+fn test_fixture_multiline_literal_allowed() {
+    let parsed = Parser::new()
+        .multiline_strings(true)
+        .parse(&read_fixture("multiline_literal"));
+    assert_eq!(
+        parse_json("{\"multiline_literal\": \"hello\\n  world\"}"),
+        parsed
+    );
+}
+
+#[test]
+fn test_fixture_nested_block_comment_comments() {
+    let hcl = read_fixture("nested_block_comment");
+    let (_, comments) = Parser::new().parse_with_comments(&hcl).unwrap();
+    // the comment is separated from `bar` by a blank line, and hides `foo`
+    assert!(comments.is_empty());
+
+    let hcl = hcl.replace("*/\n\n", "*/\n");
+    let (_, comments) = Parser::new().parse_with_comments(&hcl).unwrap();
+    assert_eq!(
+        vec!["foo = \"bar/*\"".to_string()],
+        comments.get(&["bar"]).unwrap().leading
+    );
+}
+
+#[test]
+fn test_fixture_decode_tf_variable_schema() {
+    let schema = Schema::new().block(
+        "variable",
+        1,
+        Schema::new()
+            .optional("default", Type::String)
+            .optional("description", Type::String),
+    );
+    let parsed = parse_hcl(&read_fixture("decode_tf_variable")).unwrap();
+    let violations = schema.validate(&parsed).unwrap_err();
+    let messages: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+    assert_eq!(
+        vec!["variable[\"amis\"].default: expected string, found object"],
+        messages
+    );
+}
+
+#[test]
+fn test_fixture_git_crypt_detected() {
+    let mut bytes = Vec::new();
+    let mut file = File::open("tests/test-fixtures/git_crypt.hcl").unwrap();
+    file.read_to_end(&mut bytes).unwrap();
+    assert_eq!(
+        Err(ParseError::new(ParseErrorKind::Encrypted, 0)),
+        parse_hcl_bytes(&bytes)
+    );
+}
+
+fn nest(open: &str, inner: &str, close: &str, depth: usize) -> String {
+    format!("{}{}{}", open.repeat(depth), inner, close.repeat(depth))
+}
+
+#[test]
+fn test_deep_nesting_rejected() {
+    let parser = Parser::new().max_depth(Some(SAFE_MAX_DEPTH));
+    let depth = 10_000;
+    let hostile = vec![
+        format!("a = {}", nest("[", "1", "]", depth)),
+        format!("a = {}", nest("{ b = ", "1", "}", depth)),
+        nest("a {\n", "b = 1\n", "}\n", depth),
+    ];
+    for config in &hostile {
+        match parser.parse(config) {
+            Err(ParseError {
+                kind: ParseErrorKind::LimitExceeded(Limit::Depth),
+                ..
+            }) => (),
+            other => panic!("expected a depth error, got {:?}", other),
+        }
+    }
+    let json = format!("{{\"a\": {}}}", nest("[", "1", "]", depth));
+    assert!(parser.parse_json(&json).is_err());
+    for (open, close) in &[("(", ")"), ("[", "]"), ("-", ""), ("{a = ", "}")] {
+        let config = format!("a = {}", nest(open, "1", close, depth));
+        assert!(parser.parse_hcl2(&config).is_err());
+    }
+    assert!(parser
+        .parse_hcl2(&nest("a {\n", "b = 1\n", "}\n", depth))
+        .is_err());
+
+    // a syntax error deep inside must not be retried at every level
+    let depth = SAFE_MAX_DEPTH - 1;
+    assert!(parser
+        .parse(&format!("a = {}", nest("{ b = ", "1 x", "}", depth)))
+        .is_err());
+
+    // the safe limit itself can be reached on a test thread
+    assert!(parser
+        .parse(&format!("a = {}", nest("[", "1", "]", depth)))
+        .is_ok());
+    assert!(parser
+        .parse_hcl2(&format!("a = {}", nest("[", "1", "]", depth)))
+        .is_ok());
+    assert!(parser
+        .parse_hcl2(&format!("a = {}", nest("(", "1", ")", depth)))
+        .is_ok());
+}
+
+#[test]
+fn test_deep_nesting_unlimited_by_default() {
+    let depth = 30;
+    let json = format!("{}1{}", "{\"a\": ".repeat(depth), "}".repeat(depth));
+    assert!(parse_json(&json).is_ok());
+    let hcl = format!("a = {}", nest("{ b = ", "1", "}", depth));
+    assert!(parse_hcl(&hcl).is_ok());
+    assert!(parse_hcl(&nest("a {\n", "b = 1\n", "}\n", depth)).is_ok());
+    assert!(parse_hcl2(&hcl).is_ok());
+}
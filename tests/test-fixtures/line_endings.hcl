@@ -0,0 +1,15 @@
+# windows checkout
+foo = "bar"
+// classic mac linecount = 2
+/* block
+ comment */
+script = <<EOF
+echo oneecho two
+EOF
+indented = <<-EOF
+    a
+    b  EOFservice "web" {
+  ports = [
+    80,    443,
+  ]
+}